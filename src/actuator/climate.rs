@@ -0,0 +1,124 @@
+use std::{cell::RefCell, rc::Rc, time::Instant};
+
+use esp_idf_sys::EspError;
+use log::{info, warn};
+use serde_json::Value;
+use termo_core::{
+    control::{
+        photoperiod::Photoperiod,
+        pid::PidGains,
+        thermostat::{ClimateState, Thermostat, ThermostatConfig, CHECK_INTERVAL},
+    },
+    sensor::Sensor,
+};
+
+use super::relay::Relay;
+use crate::{
+    trigger::timer::{get_timer, synced_now},
+    utils::nvs::{NvsStore, StorageError},
+};
+
+/// NVS key of the heater PID gains
+const GAINS_KEY: &str = "heater";
+
+/// Heater and exhaust fan driven by the thermostat
+pub struct Climate<'d> {
+    heater: Relay<'d>,
+    fan: Relay<'d>,
+    thermostat: Thermostat,
+    store: Option<NvsStore>,
+}
+
+impl<'d> Climate<'d> {
+    pub fn new(heater: Relay<'d>, fan: Relay<'d>, config: ThermostatConfig) -> Self {
+        Self {
+            heater,
+            fan,
+            thermostat: Thermostat::new(config),
+            store: None,
+        }
+    }
+
+    /// Keep the tuned PID gains in `store`, stored gains switch the heater to the PID mode
+    pub fn with_store(mut self, store: NvsStore) -> Self {
+        match store.load::<PidGains>(GAINS_KEY) {
+            Ok(Some(gains)) => {
+                info!("Heater PID gains restored: {gains:?}");
+                self.thermostat.set_gains(gains);
+            }
+            Ok(None) => {}
+            Err(err) => warn!("Could not load the heater PID gains: {:?}", err),
+        }
+        self.store = Some(store);
+        self
+    }
+
+    /// Run the heater on the PID loop with `gains`, they are persisted when there is a store
+    pub fn tune(&mut self, gains: PidGains) -> Result<(), StorageError> {
+        self.thermostat.set_gains(gains);
+        if let Some(store) = &mut self.store {
+            store.store(GAINS_KEY, &gains)?;
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> ClimateState {
+        self.thermostat.state()
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.thermostat.to_json()
+    }
+
+    fn update(
+        &mut self,
+        temperature: Option<f32>,
+        humidity: Option<f32>,
+        day: bool,
+    ) -> Result<ClimateState, EspError> {
+        let state = self
+            .thermostat
+            .update(temperature, humidity, day, Instant::now());
+        self.heater.set(state.heater)?;
+        self.fan.set(state.fan)?;
+        Ok(state)
+    }
+}
+
+/// Keep the temperature at the setpoint of the day or the night, day is when the photoperiod
+/// has the lights on. The night setpoint is held until the clock is synced.\
+/// `publish` gets the state whenever a relay switched.
+pub async fn climate_task<T: Sensor, H: Sensor>(
+    climate: Rc<RefCell<Climate<'_>>>,
+    temp_sensor: Rc<RefCell<T>>,
+    hum_sensor: Rc<RefCell<H>>,
+    period: Photoperiod,
+    mut publish: impl FnMut(ClimateState),
+) -> Result<(), EspError> {
+    let timer_service = get_timer()?;
+    let mut timer = timer_service.timer()?;
+    let mut last = None;
+    let mut day = false;
+
+    loop {
+        let temperature = temp_sensor.borrow_mut().get_measurment().ok();
+        let humidity = hum_sensor.borrow_mut().get_measurment().ok();
+        // An unsynced clock keeps the last known state
+        if let Some(now) = synced_now() {
+            day = period.is_light(now.time());
+        }
+
+        let mut climate = climate.borrow_mut();
+        let state = climate.update(temperature, humidity, day)?;
+        let switched = last.map_or(true, |(heater, fan)| {
+            (heater, fan) != (state.heater, state.fan)
+        });
+        if switched {
+            info!("Heater {}, fan {}", state.heater, state.fan);
+            publish(state);
+            last = Some((state.heater, state.fan));
+        }
+        drop(climate);
+        timer.after(CHECK_INTERVAL).await?;
+    }
+}
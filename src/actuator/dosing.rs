@@ -0,0 +1,78 @@
+use esp_idf_hal::gpio::AnyOutputPin;
+use esp_idf_sys::EspError;
+use log::{info, warn};
+use serde_json::Value;
+use termo_core::control::dosing::{DosingConfig, DosingScheduler, DosingState, DosingStep};
+
+use super::relay::Relay;
+use crate::{trigger::timer::synced_now, utils::nvs::NvsStore};
+
+/// NVS key of the dosing state
+const STATE_KEY: &str = "state";
+
+/// Peristaltic nutrient pump, it only doses into the running water of a watering
+pub struct Doser<'d> {
+    pump: Relay<'d>,
+    scheduler: DosingScheduler,
+    store: NvsStore,
+}
+
+impl<'d> Doser<'d> {
+    /// pin -> gpio switching the dosing pump
+    /// store -> nvs store the bottle level and the last dose are kept in
+    pub fn new(pin: AnyOutputPin, config: DosingConfig, store: NvsStore) -> Result<Self, EspError> {
+        let state: Option<DosingState> = store.load(STATE_KEY).unwrap_or_else(|err| {
+            warn!("Could not load the dosing state: {:?}", err);
+            None
+        });
+        Ok(Self {
+            pump: Relay::new(pin)?,
+            scheduler: DosingScheduler::new(config, state),
+            store,
+        })
+    }
+
+    /// Follow the water pump, `watering` is true while it runs.\
+    /// Nothing is dosed until the clock is synced, the calendar needs the date. The dose is saved
+    /// as started before the pump switches on, and as done once it is off.
+    pub fn update(&mut self, watering: bool) -> Result<Option<DosingStep>, EspError> {
+        let Some(now) = synced_now() else {
+            self.pump.set(false)?;
+            return Ok(None);
+        };
+        let was_on = self.pump.is_on();
+        let step = self.scheduler.update(watering, now);
+        if step.on && !was_on {
+            self.save();
+        }
+        self.pump.set(step.on)?;
+        if step.dosed {
+            info!("Nutrient dose given");
+        }
+        if was_on && !step.on {
+            self.save();
+        }
+        Ok(Some(step))
+    }
+
+    /// A full nutrient bottle was put in
+    pub fn refill(&mut self) {
+        self.scheduler.refill();
+        self.save();
+    }
+
+    /// Nutrient left in the bottle in ml
+    pub fn remaining(&self) -> f32 {
+        self.scheduler.state().remaining
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.scheduler.to_json()
+    }
+
+    fn save(&mut self) {
+        if let Err(err) = self.store.store(STATE_KEY, self.scheduler.state()) {
+            warn!("Could not store the dosing state: {:?}", err);
+        }
+    }
+}
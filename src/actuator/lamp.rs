@@ -0,0 +1,146 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use esp_idf_hal::{ledc::LedcDriver, task::asynch::Notification};
+use esp_idf_sys::EspError;
+use futures::{future::select, pin_mut};
+use log::info;
+use termo_core::control::{
+    lamp::{gamma_duty, LampConfig, LampController},
+    overrides::Override,
+};
+
+use crate::trigger::timer::get_timer;
+
+/// Dimmable grow lamp on a LEDC PWM channel.\
+/// The automatic control sets the level through [`Lamp::set`], [`Lamp::sunrise`] and
+/// [`Lamp::sunset`]. A manual override holds its level until it expires or is cancelled, then
+/// the lamp fades back to the last automatic level.
+pub struct Lamp<'d> {
+    driver: RefCell<LedcDriver<'d>>,
+    controller: RefCell<LampController>,
+    manual: RefCell<Override<u8>>,
+    scheduled: Cell<u8>,
+    wake: Notification,
+}
+
+impl<'d> Lamp<'d> {
+    /// The lamp starts dark
+    pub fn new(mut driver: LedcDriver<'d>, config: LampConfig) -> Result<Self, EspError> {
+        driver.set_duty(0)?;
+        Ok(Self {
+            driver: RefCell::new(driver),
+            controller: RefCell::new(LampController::new(config)),
+            manual: RefCell::new(Override::default()),
+            scheduled: Cell::new(0),
+            wake: Notification::new(),
+        })
+    }
+
+    /// Fade to `level` percent
+    pub fn set(&self, level: u8) {
+        self.automatic(level, |controller, now| controller.set(level, now));
+    }
+
+    /// Ramp up to the day `level` percent
+    pub fn sunrise(&self, level: u8) {
+        self.automatic(level, |controller, now| controller.sunrise(level, now));
+    }
+
+    /// Ramp down to dark
+    pub fn sunset(&self) {
+        self.automatic(0, |controller, now| controller.sunset(now));
+    }
+
+    /// Hold `level` percent for `duration` regardless of the automatic control
+    pub fn override_level(&self, level: u8, duration: Duration) {
+        let now = Instant::now();
+        self.manual.borrow_mut().set(level, duration, now);
+        self.controller.borrow_mut().set(level, now);
+        self.wake.notify_lsb();
+    }
+
+    /// End the override, the lamp fades back to the automatic level
+    pub fn cancel_override(&self) -> bool {
+        let cancelled = self.manual.borrow_mut().cancel();
+        if cancelled {
+            self.resume();
+        }
+        cancelled
+    }
+
+    /// Level and time left of the running override
+    pub fn active_override(&self) -> Option<(u8, Duration)> {
+        let now = Instant::now();
+        let manual = self.manual.borrow();
+        manual.get(now).zip(manual.remaining(now))
+    }
+
+    fn automatic(&self, level: u8, change: impl FnOnce(&mut LampController, Instant)) {
+        self.scheduled.set(level);
+        let now = Instant::now();
+        if self.manual.borrow().get(now).is_none() {
+            change(&mut self.controller.borrow_mut(), now);
+            self.wake.notify_lsb();
+        }
+    }
+
+    fn resume(&self) {
+        let level = self.scheduled.get();
+        info!("Lamp override ended, back to {level}%");
+        self.controller.borrow_mut().set(level, Instant::now());
+        self.wake.notify_lsb();
+    }
+
+    /// Level in percent the lamp is at or fading to
+    pub fn target(&self) -> u8 {
+        self.controller.borrow().target()
+    }
+}
+
+/// Drive the PWM duty along the fades and end expired overrides, `publish` gets the level once a
+/// fade is finished
+pub async fn lamp_task(lamp: Rc<Lamp<'_>>, mut publish: impl FnMut(u8)) -> Result<(), EspError> {
+    let timer_service = get_timer()?;
+    let mut timer = timer_service.timer()?;
+    let mut published = None;
+
+    loop {
+        let now = Instant::now();
+        if lamp.manual.borrow_mut().expire(now) {
+            lamp.resume();
+        }
+        let (level, next_step) = {
+            let mut controller = lamp.controller.borrow_mut();
+            (controller.update(now), controller.next_step(now))
+        };
+        {
+            let mut driver = lamp.driver.borrow_mut();
+            let duty = gamma_duty(level, driver.get_max_duty());
+            driver.set_duty(duty)?;
+        }
+
+        if next_step.is_none() {
+            let level = lamp.target();
+            if published != Some(level) {
+                info!("Lamp at {level}%");
+                publish(level);
+                published = Some(level);
+            }
+        }
+
+        let override_end = lamp.manual.borrow().remaining(now);
+        match next_step.into_iter().chain(override_end).min() {
+            Some(step) => {
+                let sleep = timer.after(step)?;
+                let wake = lamp.wake.wait();
+                pin_mut!(sleep, wake);
+                select(sleep, wake).await;
+            }
+            None => lamp.wake.wait().await,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::{cell::RefCell, rc::Rc};
+
+use termo_core::command::Amount;
+
+pub mod climate;
+pub mod dosing;
+pub mod lamp;
+pub mod pump;
+pub mod relay;
+pub mod valve;
+pub mod zone;
+
+/// Actuators shared by the control tasks and the command handler
+#[derive(Clone)]
+pub struct Actuators<'d> {
+    pub pump: Rc<RefCell<pump::Pump<'d>>>,
+    pub zones: Rc<zone::ZoneControl>,
+    pub lamp: Rc<lamp::Lamp<'d>>,
+    pub climate: Rc<RefCell<climate::Climate<'d>>>,
+    #[cfg(feature = "dosing")]
+    pub doser: Rc<RefCell<dosing::Doser<'d>>>,
+}
+
+impl Actuators<'_> {
+    /// Queue a manual watering, a volume needs the flow calibration of the zone
+    pub fn water(&self, zone: usize, amount: Option<Amount>) -> Result<(), String> {
+        let max_on_time = self.pump.borrow().max_on_time();
+        let duration = match amount {
+            // A volume is clamped to the longest pump run
+            Some(Amount::Volume(volume)) => self
+                .zones
+                .duration_for(zone, volume, max_on_time)
+                .map_err(|err| err.to_string())?,
+            Some(Amount::Time(duration)) => duration,
+            None => zone::MANUAL_WATERING,
+        };
+        if duration > max_on_time {
+            return Err(format!(
+                "Watering of {}s is longer than the pump may run at once ( {}s )",
+                duration.as_secs(),
+                max_on_time.as_secs()
+            ));
+        }
+        self.zones
+            .water(zone, duration)
+            .map_err(|err| err.to_string())
+    }
+}
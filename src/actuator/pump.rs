@@ -0,0 +1,193 @@
+use std::time::{Duration, Instant};
+
+use esp_idf_hal::{gpio::AnyOutputPin, ledc::LedcDriver};
+use esp_idf_sys::EspError;
+use log::{info, warn};
+use serde_json::Value;
+use termo_core::control::{
+    flow::FlowRate,
+    safety::{InterlockState, PumpInterlock, SafetyConfig, Trip},
+    soft_start::{SoftStart, SoftStartConfig},
+};
+
+use super::relay::Relay;
+use crate::utils::nvs::NvsStore;
+
+/// NVS key of the interlock state
+const INTERLOCK_KEY: &str = "interlock";
+
+/// Output stage of the pump
+enum Drive<'d> {
+    /// On/off relay, for AC pumps
+    Relay(Relay<'d>),
+    /// LEDC PWM into a MOSFET with soft start and stop ramps, for DC pumps
+    Pwm {
+        driver: LedcDriver<'d>,
+        ramp: SoftStart,
+    },
+}
+
+/// Water pump, every request passes through the safety interlocks
+pub struct Pump<'d> {
+    drive: Drive<'d>,
+    interlock: PumpInterlock,
+    store: NvsStore,
+    tank_level: Option<Box<dyn FnMut() -> bool + 'd>>,
+    flow_meter: Option<Box<dyn FnMut() -> Option<f32> + 'd>>,
+}
+
+impl<'d> Pump<'d> {
+    /// Pump switched by a relay on `pin`.\
+    /// The relay is switched off right away, so a reset never leaves the pump running.
+    /// The latched trip and the budget used are kept in `store`.
+    pub fn new(pin: AnyOutputPin, config: SafetyConfig, store: NvsStore) -> Result<Self, EspError> {
+        Ok(Self::with_drive(
+            Drive::Relay(Relay::new(pin)?),
+            config,
+            store,
+        ))
+    }
+
+    /// DC pump driven by PWM through a MOSFET, it starts still
+    pub fn new_pwm(
+        mut driver: LedcDriver<'d>,
+        soft_start: SoftStartConfig,
+        config: SafetyConfig,
+        store: NvsStore,
+    ) -> Result<Self, EspError> {
+        driver.set_duty(0)?;
+        let ramp = SoftStart::new(soft_start);
+        Ok(Self::with_drive(Drive::Pwm { driver, ramp }, config, store))
+    }
+
+    /// The interlocks carry on with the state of the last run, a trip stays latched
+    fn with_drive(drive: Drive<'d>, config: SafetyConfig, store: NvsStore) -> Self {
+        let state: Option<InterlockState> = store.load(INTERLOCK_KEY).unwrap_or_else(|err| {
+            warn!("Could not load the pump interlock state: {:?}", err);
+            None
+        });
+        let interlock = match state {
+            Some(state) => {
+                if let Some(trip) = state.trip {
+                    warn!("Pump interlock still tripped: {trip}");
+                }
+                PumpInterlock::restored(config, state, Instant::now())
+            }
+            None => PumpInterlock::new(config),
+        };
+        Self {
+            drive,
+            interlock,
+            store,
+            tank_level: None,
+            flow_meter: None,
+        }
+    }
+
+    /// Dry run protection, `tank_ok` returns false if the tank level is low or can't be read
+    pub fn with_tank_level(mut self, tank_ok: impl FnMut() -> bool + 'd) -> Self {
+        self.tank_level = Some(Box::new(tank_ok));
+        self
+    }
+
+    /// Flow meter on the outlet, `total` returns the volume in ml since boot, `None` on a failed read
+    pub fn with_flow_meter(mut self, total: impl FnMut() -> Option<f32> + 'd) -> Self {
+        self.flow_meter = Some(Box::new(total));
+        self
+    }
+
+    /// Flow rate of the zone about to be fed, for the volume budget
+    pub fn set_flow_rate(&mut self, flow: Option<FlowRate>) {
+        self.interlock
+            .set_flow_rate(flow.map(|FlowRate(rate)| rate));
+    }
+
+    /// Volume in ml counted by the flow meter since boot
+    pub fn metered(&mut self) -> Option<f32> {
+        self.flow_meter.as_mut().and_then(|total| total())
+    }
+
+    /// Longest single run the interlocks allow
+    pub fn max_on_time(&self) -> Duration {
+        self.interlock.config().max_on_time
+    }
+
+    /// Rest the interlocks need between two runs
+    pub fn min_off_time(&self) -> Duration {
+        self.interlock.config().min_off_time
+    }
+
+    /// Run time in the current budget window
+    pub fn run_time(&self) -> Duration {
+        self.interlock.run_time(Instant::now())
+    }
+
+    /// Switch the pump as far as the interlocks allow it, a new trip is returned
+    pub fn request(&mut self, on: bool) -> Result<Option<Trip>, EspError> {
+        let tank_ok = !on || self.tank_level.as_mut().map_or(true, |tank_ok| tank_ok());
+        let now = Instant::now();
+        let guarded = self.interlock.update(on, tank_ok, now);
+        match &mut self.drive {
+            Drive::Relay(relay) => relay.set(guarded.on)?,
+            Drive::Pwm { ramp, .. } => {
+                ramp.switch(guarded.on, now);
+                self.update()?;
+            }
+        }
+        self.save_state(now);
+        Ok(guarded.trip)
+    }
+
+    /// Move the PWM duty along a running ramp
+    pub fn update(&mut self) -> Result<(), EspError> {
+        if let Drive::Pwm { driver, ramp } = &mut self.drive {
+            let duty = ramp.update(Instant::now()) / 100.0 * driver.get_max_duty() as f32;
+            driver.set_duty(duty.round() as u32)?;
+        }
+        Ok(())
+    }
+
+    /// Time until the next step of a running soft start or stop
+    pub fn next_step(&self) -> Option<Duration> {
+        match &self.drive {
+            Drive::Relay(_) => None,
+            Drive::Pwm { ramp, .. } => ramp.next_step(Instant::now()),
+        }
+    }
+
+    /// Release a latched trip
+    pub fn acknowledge(&mut self) -> Option<Trip> {
+        let trip = self.interlock.acknowledge();
+        if let Some(trip) = trip {
+            info!("Pump interlock acknowledged: {trip}");
+        }
+        self.save_state(Instant::now());
+        trip
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.interlock.is_on()
+    }
+
+    pub fn trip(&self) -> Option<Trip> {
+        self.interlock.trip()
+    }
+
+    /// Time until the running pump is stopped by an interlock
+    pub fn time_to_cutoff(&self) -> Option<Duration> {
+        self.interlock.time_to_cutoff(Instant::now())
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.interlock.to_json(Instant::now())
+    }
+
+    /// Write the interlock state if it changed
+    fn save_state(&mut self, now: Instant) {
+        if let Some(state) = self.interlock.changed_state(now) {
+            if let Err(err) = self.store.store(INTERLOCK_KEY, &state) {
+                warn!("Could not write the pump interlock state: {:?}", err);
+            }
+        }
+    }
+}
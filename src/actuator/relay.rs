@@ -0,0 +1,28 @@
+use esp_idf_hal::gpio::{AnyOutputPin, Output, PinDriver};
+use esp_idf_sys::EspError;
+
+/// Plain on/off load switched by a relay, the output of the valves, fans, heater and pumps
+pub struct Relay<'d> {
+    pin: PinDriver<'d, AnyOutputPin, Output>,
+}
+
+impl<'d> Relay<'d> {
+    /// The relay is switched off right away
+    pub fn new(pin: AnyOutputPin) -> Result<Self, EspError> {
+        let mut pin = PinDriver::output(pin)?;
+        pin.set_low()?;
+        Ok(Self { pin })
+    }
+
+    pub fn set(&mut self, on: bool) -> Result<(), EspError> {
+        if on {
+            self.pin.set_high()
+        } else {
+            self.pin.set_low()
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.pin.is_set_high()
+    }
+}
@@ -0,0 +1,26 @@
+use esp_idf_hal::gpio::AnyOutputPin;
+use esp_idf_sys::EspError;
+
+use super::relay::Relay;
+
+/// Normally closed solenoid valve of a zone, switched by a relay
+pub struct Valve<'d> {
+    relay: Relay<'d>,
+}
+
+impl<'d> Valve<'d> {
+    /// The valve is closed right away
+    pub fn new(pin: AnyOutputPin) -> Result<Self, EspError> {
+        Ok(Self {
+            relay: Relay::new(pin)?,
+        })
+    }
+
+    pub fn set(&mut self, open: bool) -> Result<(), EspError> {
+        self.relay.set(open)
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.relay.is_on()
+    }
+}
@@ -0,0 +1,403 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use esp_idf_hal::task::asynch::Notification;
+use esp_idf_sys::EspError;
+use futures::{
+    future::{select, LocalBoxFuture},
+    pin_mut,
+};
+use log::{info, warn};
+use termo_core::{
+    control::{
+        flow::{FlowError, FlowRate, CALIBRATION_RUN, MIN_METERED_RUN},
+        safety::Trip,
+        watering::{CycleEnd, WateringController, IDLE_CHECK_INTERVAL},
+        zones::{ZoneError, ZoneOutputs, ZoneSequencer},
+    },
+    journal::WateringRecord,
+    profile::PlantProfile,
+};
+
+use super::{pump::Pump, valve::Valve};
+use crate::{
+    trigger::timer::{get_timer, synced_now},
+    utils::nvs::{NvsStore, StorageError},
+};
+
+/// Watering time of a manual request
+pub const MANUAL_WATERING: Duration = Duration::from_secs(10);
+
+/// Pot watered through its own valve from the shared pump
+pub struct Zone<'d> {
+    name: String,
+    valve: Valve<'d>,
+    moisture: Option<Box<dyn FnMut() -> Option<f32> + 'd>>,
+    sampler: Option<Box<dyn FnMut() -> LocalBoxFuture<'d, ()> + 'd>>,
+    controller: WateringController,
+    next_check: Instant,
+}
+
+impl<'d> Zone<'d> {
+    pub fn new(name: &str, valve: Valve<'d>, profile: PlantProfile) -> Self {
+        Self {
+            name: name.to_string(),
+            valve,
+            moisture: None,
+            sampler: None,
+            controller: WateringController::new(profile),
+            next_check: Instant::now(),
+        }
+    }
+
+    /// Soil probe of the zone, `None` from `moisture` is a probe fault.\
+    /// Zones without probe are only watered on request.
+    pub fn with_soil_probe(mut self, moisture: impl FnMut() -> Option<f32> + 'd) -> Self {
+        self.moisture = Some(Box::new(moisture));
+        self
+    }
+
+    /// Fresh sample of a switched soil probe, awaited before each decision of the closed loop so
+    /// it never runs on a sample taken before the water soaked in
+    pub fn with_soil_sampler(
+        mut self,
+        sample: impl FnMut() -> LocalBoxFuture<'d, ()> + 'd,
+    ) -> Self {
+        self.sampler = Some(Box::new(sample));
+        self
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CalibrationError {
+    #[error(transparent)]
+    Zone(#[from] ZoneError),
+    #[error("Zone {0} has no flow calibration")]
+    NotCalibrated(usize),
+    #[error("Measured volume is not valid")]
+    InvalidVolume,
+    #[error(transparent)]
+    Flow(#[from] FlowError),
+    #[error("Calibration not stored")]
+    Storage(#[from] StorageError),
+}
+
+/// NVS key of the flow rate of a zone
+fn flow_key(zone: usize) -> String {
+    format!("flow{zone}")
+}
+
+/// Watering requests and flow calibrations shared between the watering task and the command
+/// handler.\
+/// Manual waterings are overrides, the closed loop of the zone pauses until they are done.
+pub struct ZoneControl {
+    sequencer: RefCell<ZoneSequencer>,
+    manual: RefCell<Vec<bool>>,
+    flows: RefCell<Vec<Option<FlowRate>>>,
+    store: RefCell<Option<NvsStore>>,
+    wake: Notification,
+}
+
+impl ZoneControl {
+    pub fn new(zones: usize) -> Self {
+        Self {
+            sequencer: RefCell::new(ZoneSequencer::new(zones)),
+            manual: RefCell::new(vec![false; zones]),
+            flows: RefCell::new(vec![None; zones]),
+            store: RefCell::new(None),
+            wake: Notification::new(),
+        }
+    }
+
+    /// Rest the pump needs between two runs, the next zone waits for it before its run starts
+    pub fn with_pump_rest(self, rest: Duration) -> Self {
+        Self {
+            sequencer: RefCell::new(self.sequencer.into_inner().with_pump_rest(rest)),
+            ..self
+        }
+    }
+
+    /// Keep the flow calibrations in `store`, the stored ones are loaded right away
+    pub fn with_store(self, store: NvsStore) -> Self {
+        for (zone, flow) in self.flows.borrow_mut().iter_mut().enumerate() {
+            *flow = store.load(&flow_key(zone)).unwrap_or_else(|err| {
+                warn!("Could not load the flow of zone {zone}: {:?}", err);
+                None
+            });
+        }
+        *self.store.borrow_mut() = Some(store);
+        self
+    }
+
+    /// Calibrated flow rate of a zone
+    pub fn flow(&self, zone: usize) -> Option<FlowRate> {
+        self.flows.borrow().get(zone).copied().flatten()
+    }
+
+    /// Pump time of a zone for `volume` ml, at most `max`
+    pub fn duration_for(
+        &self,
+        zone: usize,
+        volume: f32,
+        max: Duration,
+    ) -> Result<Duration, CalibrationError> {
+        let flow = self
+            .flows
+            .borrow()
+            .get(zone)
+            .copied()
+            .ok_or(ZoneError::UnknownZone(zone))?
+            .ok_or(CalibrationError::NotCalibrated(zone))?;
+        Ok(flow.duration_for(volume, max)?)
+    }
+
+    /// Start the calibration run of a zone, the caught volume is then given to [`Self::calibrate`]
+    pub fn start_calibration(&self, zone: usize) -> Result<(), ZoneError> {
+        info!("Flow calibration of zone {zone}, catch the water");
+        self.water(zone, CALIBRATION_RUN)
+    }
+
+    /// Store the flow rate from the `volume` in ml caught during the calibration run
+    pub fn calibrate(&self, zone: usize, volume: f32) -> Result<FlowRate, CalibrationError> {
+        let flow =
+            FlowRate::from_run(volume, CALIBRATION_RUN).ok_or(CalibrationError::InvalidVolume)?;
+        self.set_flow(zone, flow)?;
+        Ok(flow)
+    }
+
+    /// Correct the flow rate of a zone with the flow meter reading of a run, an uncalibrated zone
+    /// takes the metered rate
+    fn correct_flow(
+        &self,
+        zone: usize,
+        metered: f32,
+        run: Duration,
+    ) -> Result<(), CalibrationError> {
+        let flow = match self.flow(zone) {
+            Some(mut flow) => flow.correct(metered, run).then_some(flow),
+            None if run >= MIN_METERED_RUN => FlowRate::from_run(metered, run),
+            None => None,
+        };
+        if let Some(flow) = flow {
+            info!("Zone {zone} flow corrected to {:.1} ml/s", flow.0);
+            self.set_flow(zone, flow)?;
+        }
+        Ok(())
+    }
+
+    fn set_flow(&self, zone: usize, flow: FlowRate) -> Result<(), CalibrationError> {
+        *self
+            .flows
+            .borrow_mut()
+            .get_mut(zone)
+            .ok_or(ZoneError::UnknownZone(zone))? = Some(flow);
+        if let Some(store) = self.store.borrow_mut().as_mut() {
+            store.store(&flow_key(zone), &flow)?;
+        }
+        Ok(())
+    }
+
+    /// Queue a manual watering of a zone, it ends by itself after `duration`.\
+    /// A zone already watering or waiting is rejected, stop it first.
+    pub fn water(&self, zone: usize, duration: Duration) -> Result<(), ZoneError> {
+        if !self.sequencer.borrow_mut().request(zone, duration)? {
+            return Err(ZoneError::Busy(zone));
+        }
+        self.manual.borrow_mut()[zone] = true;
+        self.wake.notify_lsb();
+        Ok(())
+    }
+
+    pub fn stop(&self, zone: usize) {
+        self.sequencer.borrow_mut().stop(zone, Instant::now());
+        self.end_manual(zone);
+        self.wake.notify_lsb();
+    }
+
+    /// Stop every manual watering, automatic ones go on
+    pub fn stop_manual(&self) {
+        let zones: Vec<usize> = self.manual_zones().collect();
+        for zone in zones {
+            self.stop(zone);
+        }
+    }
+
+    /// Zones with a manual watering waiting or running, with the time left of the running one
+    pub fn overrides(&self) -> Vec<(usize, Option<Duration>)> {
+        let sequencer = self.sequencer.borrow();
+        let now = Instant::now();
+        self.manual_zones()
+            .map(|zone| {
+                let remaining = sequencer
+                    .remaining(now)
+                    .filter(|_| sequencer.active_zone() == Some(zone));
+                (zone, remaining)
+            })
+            .collect()
+    }
+
+    fn is_manual(&self, zone: usize) -> bool {
+        self.manual.borrow().get(zone).copied().unwrap_or(false)
+    }
+
+    fn end_manual(&self, zone: usize) {
+        if let Some(manual) = self.manual.borrow_mut().get_mut(zone) {
+            *manual = false;
+        }
+    }
+
+    fn manual_zones(&self) -> impl Iterator<Item = usize> {
+        let manual = self.manual.borrow().clone();
+        manual
+            .into_iter()
+            .enumerate()
+            .filter_map(|(zone, manual)| manual.then_some(zone))
+    }
+}
+
+/// Keep the soil of every zone in the target band of its profile and run the watering requests.\
+/// The zones are watered one at a time, the pump only runs with the valve of the zone open.
+/// `alert` is called when a pump interlock trips, `journal` gets the watering in flight whenever
+/// a valve opens or closes. `dose` follows the state of the pump and returns the time until it
+/// wants to be called again.
+pub async fn watering_task(
+    pump: Rc<RefCell<Pump<'_>>>,
+    mut zones: Vec<Zone<'_>>,
+    control: Rc<ZoneControl>,
+    mut alert: impl FnMut(String),
+    mut journal: impl FnMut(Option<WateringRecord>),
+    mut dose: impl FnMut(bool) -> Option<Duration>,
+) -> Result<(), EspError> {
+    for zone in zones.iter() {
+        info!(
+            "Zone {}: {} profile, {}",
+            zone.name,
+            zone.controller.profile().name,
+            if zone.moisture.is_some() {
+                "closed loop"
+            } else {
+                "on request"
+            }
+        );
+    }
+    let timer_service = get_timer()?;
+    let mut timer = timer_service.timer()?;
+    let mut open_valve = None;
+    let mut metering = None;
+
+    loop {
+        pump.borrow_mut().update()?;
+        let now = Instant::now();
+        let tripped = pump.borrow().trip().is_some();
+        if tripped {
+            // Nothing to decide until the trip is acknowledged
+            control.sequencer.borrow_mut().stop_all(now);
+            control.manual.borrow_mut().fill(false);
+        }
+
+        for (index, zone) in zones.iter_mut().enumerate() {
+            let Some(moisture) = zone.moisture.as_mut() else {
+                continue;
+            };
+            zone.controller.set_flow(control.flow(index));
+            if tripped {
+                zone.controller.stop();
+                zone.next_check = now + IDLE_CHECK_INTERVAL;
+                continue;
+            }
+            // The closed loop resumes once the manual watering is done
+            if now < zone.next_check || control.is_manual(index) {
+                continue;
+            }
+            if let Some(sample) = zone.sampler.as_mut() {
+                sample().await;
+            }
+            let step = zone.controller.update(moisture(), now);
+            let mut sequencer = control.sequencer.borrow_mut();
+            if step.pump_on {
+                sequencer.request(index, zone.controller.pulse_time()).ok();
+            }
+            if step.cycle_end == Some(CycleEnd::ProbeFault) {
+                sequencer.stop(index, now);
+            }
+            zone.next_check = now + step.next_check;
+        }
+
+        let (outputs, next_step) = control.sequencer.borrow_mut().update(now);
+        let valve_moved = outputs.valve != open_valve;
+        if valve_moved {
+            // The sequencer stopped the pump already, the run of the last zone is over
+            if let Some(zone) = open_valve {
+                control.end_manual(zone);
+            }
+            let mut pump = pump.borrow_mut();
+            if let (Some(zone), Some((start_volume, start_run))) = (open_valve, metering.take()) {
+                if let Some(volume) = pump.metered() {
+                    let run = pump.run_time().saturating_sub(start_run);
+                    if let Err(err) = control.correct_flow(zone, volume - start_volume, run) {
+                        warn!("Flow of zone {zone} not corrected: {err}");
+                    }
+                }
+            }
+            pump.set_flow_rate(outputs.valve.and_then(|index| control.flow(index)));
+        }
+        let trip = drive(&mut pump.borrow_mut(), &mut zones, outputs)?;
+        let dose_step = dose(pump.borrow().is_on());
+        if valve_moved {
+            journal(outputs.valve.map(|index| WateringRecord {
+                zone: zones[index].name.clone(),
+                started: synced_now(),
+            }));
+            if outputs.valve.is_some() {
+                let mut pump = pump.borrow_mut();
+                metering = pump.metered().map(|volume| (volume, pump.run_time()));
+            }
+            open_valve = outputs.valve;
+        }
+        if let Some(trip) = trip {
+            alert(format!(
+                "Pump stopped :warning:\\n> {trip}, send the `ack` command to resume watering"
+            ));
+            // Close the valve right away
+            continue;
+        }
+
+        let wait = zones
+            .iter()
+            .enumerate()
+            .filter(|(index, zone)| zone.moisture.is_some() && !control.is_manual(*index))
+            .map(|(_, zone)| zone.next_check.saturating_duration_since(now))
+            .chain(next_step)
+            .chain(pump.borrow().time_to_cutoff())
+            .chain(pump.borrow().next_step())
+            .chain(dose_step)
+            .min()
+            .unwrap_or(IDLE_CHECK_INTERVAL);
+        let sleep = timer.after(wait)?;
+        let wake = control.wake.wait();
+        pin_mut!(sleep, wake);
+        select(sleep, wake).await;
+    }
+}
+
+/// Switch the pump and valves, the pump is stopped before and started after the valves moved
+fn drive(
+    pump: &mut Pump,
+    zones: &mut [Zone],
+    outputs: ZoneOutputs,
+) -> Result<Option<Trip>, EspError> {
+    debug_assert!(outputs.is_safe());
+    if !outputs.pump {
+        pump.request(false)?;
+    }
+    for (index, zone) in zones.iter_mut().enumerate() {
+        zone.valve.set(outputs.valve == Some(index))?;
+    }
+    if outputs.pump {
+        return pump.request(true);
+    }
+    Ok(None)
+}
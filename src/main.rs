@@ -1,26 +1,91 @@
 #![feature(never_type)]
 use async_lock::RwLock;
-use esp_idf_hal::{gpio::PinDriver, prelude::Peripherals, task::block_on};
-use futures::join;
+use esp_idf_hal::{
+    ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver, Resolution},
+    prelude::*,
+    task::block_on,
+};
+use futures::{join, FutureExt};
 use log::{error, info, warn};
-use std::{rc::Rc, result::Result::Ok, time::Duration};
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc, result::Result::Ok, time::Duration};
 
 use edge_executor::LocalExecutor;
 // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
 use esp_idf_sys as _;
 
+mod actuator;
 mod relay;
 mod sensor;
 mod trigger;
 mod utils;
 
+use actuator::{
+    climate::{climate_task, Climate},
+    lamp::{lamp_task, Lamp},
+    pump::Pump,
+    relay::Relay,
+    valve::Valve,
+    zone::{watering_task, Zone, ZoneControl},
+    Actuators,
+};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
 use relay::discord::discord_webhook;
 use sensor::{
+    adc::new_shared_adc,
     bme280::{get_bme280_sensors, new_bme280},
-    soil::SoilMoisture,
+    soil::{soil_task, SoilMoisture},
+    Sensor,
+};
+use termo_core::{
+    command::Command,
+    control::{
+        lamp::LampConfig,
+        overrides::LAMP_OVERRIDE,
+        photoperiod::Photoperiod,
+        safety::SafetyConfig,
+        thermostat::{ClimateState, ThermostatConfig},
+    },
+    journal::get_interrupted_message,
+    profile::PlantProfile,
+    report::{get_climate_message, get_message},
+    rules::{Action, Facts},
+    schedule::{CatchUp, Job, Scheduler},
+    sensor::health::{diagnostics_message, HealthConfig, Monitored},
+};
+use trigger::{
+    events::trigger_task,
+    photoperiod::photoperiod_task,
+    rules::rules_task,
+    timer::{scheduler_task, sntp_task, time_sync},
+};
+use utils::{
+    config::{apply_timezone, ConfigStore},
+    journal::{reset_reason, JournalStore},
+    nvs::NvsStore,
+    rules::RuleStore,
+    runs::RunStore,
+    wifi::WifiRelay,
 };
-use trigger::timer::shedule_event;
-use utils::{helper::discord::get_message, wifi::WifiRelay};
+
+/// Reads taken from every sensor by the boot time self test
+const SELF_TEST_SAMPLES: u32 = 5;
+/// Distance in cm from the ultrasonic sensor to the water surface of a nearly empty tank
+#[cfg(feature = "tank_level")]
+const TANK_EMPTY_DISTANCE: f32 = 25.0;
+/// Distance in cm from the ultrasonic sensor to the water surface of a full tank
+#[cfg(feature = "tank_level")]
+const TANK_FULL_DISTANCE: f32 = 5.0;
+/// Air temperature alarm in °C
+const HEAT_ALARM: f32 = 35.0;
+/// Air temperature change alarm in °C per minute, e.g. a stuck heater or an open door
+const TEMPERATURE_RATE_ALARM: f32 = 1.0;
+/// Soil moisture alarm in percent, well below the watering band
+const DRY_ALARM: f32 = 15.0;
+/// Hours after 8 AM the missed daily report is still sent
+const REPORT_CATCH_UP: u32 = 4;
+/// Daily light integral in mol/m² the lamp tops up on dull days
+#[cfg(feature = "light_sensor")]
+const DLI_TARGET: f32 = 17.0;
 
 fn main() -> anyhow::Result<()> {
     info!("program started :)");
@@ -28,9 +93,14 @@ fn main() -> anyhow::Result<()> {
     esp_idf_svc::log::EspLogger::initialize_default();
 
     let peripherals = Peripherals::take().unwrap();
+    let nvs = EspDefaultNvsPartition::take()?;
+    // Local time of the schedules, set before anything reads the clock
+    let config = ConfigStore::open(NvsStore::new(nvs.clone(), "config")?);
+    apply_timezone(&config.config().timezone);
+    let config = Rc::new(RefCell::new(config));
 
     // Setup wifi
-    let wifi = block_on(WifiRelay::new(peripherals.modem))?;
+    let wifi = block_on(WifiRelay::new(peripherals.modem, nvs.clone()))?;
     let wifi_handler = Rc::new(RwLock::new(wifi));
 
     // Setup sensors
@@ -39,60 +109,591 @@ fn main() -> anyhow::Result<()> {
         peripherals.pins.gpio22,
         peripherals.i2c0,
     );
-    let (mut temp_sensor, mut hum_sensor, mut _bar_sensor) = get_bme280_sensors(bme280_i2c);
-    let mut soil_sensor = SoilMoisture::new(peripherals.adc1, peripherals.pins.gpio36)?;
+    let (temp_sensor, hum_sensor, mut _bar_sensor) = get_bme280_sensors(bme280_i2c);
+    let adc1 = new_shared_adc(peripherals.adc1)?;
+    let soil_sensor = SoilMoisture::new_shared(adc1.clone(), peripherals.pins.gpio36)?;
+    // Probe supply switched by gpio25, so it is only powered during the reads
+    #[cfg(feature = "soil_power")]
+    let soil_sensor =
+        soil_sensor.with_power_pin(peripherals.pins.gpio25.into(), Duration::from_millis(200))?;
+
+    #[cfg(feature = "hydro")]
+    let (ph_probe, ec_probe) = {
+        use sensor::hydro::{EcProbe, PhProbe};
+
+        let ph_probe = PhProbe::new(
+            adc1.clone(),
+            peripherals.pins.gpio34,
+            NvsStore::new(nvs.clone(), "hydro")?,
+        )?;
+        // Air temperature is used until a dedicated water temperature probe is fitted
+        let mut water_temp = temp_sensor.clone();
+        let ec_probe = EcProbe::new(
+            adc1.clone(),
+            peripherals.pins.gpio35,
+            NvsStore::new(nvs.clone(), "hydro")?,
+        )?
+        .with_temperature_source(move || water_temp.get_measurment().ok());
+        // Shared by the daily report and the calibration commands
+        (
+            Rc::new(RefCell::new(ph_probe)),
+            Rc::new(RefCell::new(ec_probe)),
+        )
+    };
+
+    // Health monitoring, faulty sensors are reported and left out of the watering decisions
+    let soil_sensor = Monitored::new(soil_sensor, HealthConfig::soil());
+    let temp_sensor = Monitored::new(temp_sensor, HealthConfig::temperature());
+    let hum_sensor = Monitored::new(hum_sensor, HealthConfig::humidity());
+    // Shared by the control loops and the daily report
+    let soil_sensor = Rc::new(RefCell::new(soil_sensor));
+    let temp_sensor = Rc::new(RefCell::new(temp_sensor));
+    let hum_sensor = Rc::new(RefCell::new(hum_sensor));
+    // A switched probe has no reading before its first sample
+    #[cfg(feature = "soil_power")]
+    if let Err(err) = block_on(sensor::soil::sample_soil(&soil_sensor)) {
+        warn!("Soil sample failed: {err}");
+    }
+    let diagnostics = diagnostics_message(&[
+        soil_sensor.borrow_mut().self_test(SELF_TEST_SAMPLES),
+        temp_sensor.borrow_mut().self_test(SELF_TEST_SAMPLES),
+        hum_sensor.borrow_mut().self_test(SELF_TEST_SAMPLES),
+    ]);
+    info!("{diagnostics}");
 
     // Initialize the async executor
     let executor: LocalExecutor = Default::default();
 
-    let mut pump_relay = PinDriver::output(peripherals.pins.gpio13)?;
-    pump_relay.set_low().ok();
-    let pump = async {
-        let delay_service = trigger::timer::get_timer().unwrap();
-        let mut timer = delay_service.timer().unwrap();
-
-        loop {
-            pump_relay.set_high().ok();
-            timer.after(Duration::from_secs(1)).await.ok();
-            pump_relay.set_low().ok();
-            timer.after(Duration::from_secs(5)).await.ok();
-        }
+    // Pump on gpio13, a relay for AC pumps or a MOSFET driven by 20 kHz PWM for DC pumps
+    #[cfg(not(feature = "pump_pwm"))]
+    let pump = Pump::new(
+        peripherals.pins.gpio13.into(),
+        SafetyConfig::default(),
+        NvsStore::new(nvs.clone(), "pump")?,
+    )?;
+    #[cfg(feature = "pump_pwm")]
+    let pump = {
+        use termo_core::control::soft_start::SoftStartConfig;
+
+        let pump_timer = LedcTimerDriver::new(
+            peripherals.ledc.timer1,
+            &TimerConfig::new()
+                .frequency(20.kHz().into())
+                .resolution(Resolution::Bits10),
+        )?;
+        Pump::new_pwm(
+            LedcDriver::new(
+                peripherals.ledc.channel1,
+                pump_timer,
+                peripherals.pins.gpio13,
+            )?,
+            SoftStartConfig::default(),
+            SafetyConfig::default(),
+            NvsStore::new(nvs.clone(), "pump")?,
+        )?
     };
+    // Ultrasonic sensor above the water surface, a failed read counts as a low tank
+    #[cfg(feature = "tank_level")]
+    let (pump, ultrasonic) = {
+        use esp_idf_hal::gpio::{PinDriver, Pull};
+        use sensor::hc_sr04::{HcSr04, Unit};
 
-    // Send notification to discord at 8 AM
-    let discord_wifi_handler = wifi_handler.clone();
-    let discord_notification = shedule_event(|| {
-        let message = get_message(&mut soil_sensor, &mut hum_sensor, &mut temp_sensor);
+        let trig = PinDriver::output(peripherals.pins.gpio4)?;
+        let mut echo = PinDriver::input(peripherals.pins.gpio2)?;
+        echo.set_pull(Pull::Down)?;
+        let ultrasonic = HcSr04::new(trig, echo, None).map_err(|err| anyhow::anyhow!("{err:?}"))?;
+        let ultrasonic = Rc::new(RefCell::new(ultrasonic));
+        let tank_sensor = ultrasonic.clone();
+        let pump = pump.with_tank_level(move || {
+            matches!(
+                tank_sensor.borrow_mut().measure_distance(Unit::Centimeters),
+                Ok(Some(distance)) if distance < TANK_EMPTY_DISTANCE
+            )
+        });
+        (pump, ultrasonic)
+    };
+    // Hall flow meter on the pump outlet on gpio33, it corrects the flow calibration of the zones
+    #[cfg(feature = "flow_meter")]
+    let pump = {
+        use sensor::flow_meter::FlowMeter;
+
+        let mut meter = FlowMeter::new(peripherals.pcnt0, peripherals.pins.gpio33)?;
+        pump.with_flow_meter(move || meter.total_ml().ok())
+    };
+    let pump = Rc::new(RefCell::new(pump));
+
+    #[cfg(feature = "mqtt")]
+    let (mqtt_client, commands, updates) = {
+        let (sender, commands) = futures::channel::mpsc::unbounded();
+        let (update_sender, updates) = futures::channel::mpsc::unbounded();
+        let client = relay::mqtt::new_mqqt_client(
+            move |command| {
+                sender.unbounded_send(command).ok();
+            },
+            move |update| {
+                update_sender.unbounded_send(update).ok();
+            },
+        )?;
+        (Rc::new(RefCell::new(client)), commands, updates)
+    };
+
+    // Actuator journal of the last run, the lamp and the photoperiod are restored
+    let (journal, interrupted) = JournalStore::open(NvsStore::new(nvs.clone(), "journal")?);
+    let journal = Rc::new(RefCell::new(journal));
 
+    // Interlock trips are reported on discord and on MQTT
+    let alert_wifi_handler = wifi_handler.clone();
+    let alert = |message: String| {
+        #[cfg(feature = "mqtt")]
+        {
+            use relay::mqtt::SimpleMqttClient;
+            mqtt_client.borrow_mut().error_message(message.clone());
+        }
         executor
-            .spawn(async {
-                let wifi = discord_wifi_handler.read().await;
-                match wifi.get_inner().is_connected() {
-                    Ok(true) => {
-                        discord_webhook(message).await.ok();
-                    }
-                    Ok(false) => {
-                        drop(wifi);
-                        let mut wifi = discord_wifi_handler.write().await;
-                        wifi.reconnect().await.ok();
-                        trigger::timer::safe_sleep(Duration::from_secs(3)).await;
-                        discord_webhook(message).await.ok();
-                        wifi.disconnect().await.ok();
-                    }
-                    Err(_) => {
-                        error!("Wifi handler not awailable");
+            .spawn(send_to_discord(alert_wifi_handler.clone(), message))
+            .detach();
+    };
+    // Watering is never resumed after a reboot, the interrupted one is reported
+    if let Some(record) = interrupted {
+        warn!("Watering of {} interrupted by a reboot", record.zone);
+        alert(get_interrupted_message(&record, reset_reason()));
+    }
+
+    // Pots sharing the pump, each behind its own valve
+    let zone_soil = soil_sensor.clone();
+    let zone_sampler = soil_sensor.clone();
+    let zones = vec![
+        Zone::new(
+            "pot 1",
+            Valve::new(peripherals.pins.gpio26.into())?,
+            PlantProfile::default(),
+        )
+        .with_soil_probe(move || zone_soil.borrow_mut().get_measurment().ok())
+        .with_soil_sampler(move || {
+            let soil = zone_sampler.clone();
+            async move {
+                if !soil.borrow_mut().inner().is_switched() {
+                    return;
+                }
+                if let Err(err) = sensor::soil::sample_soil(&soil).await {
+                    warn!("Soil sample failed: {err}");
+                }
+            }
+            .boxed_local()
+        }),
+        Zone::new(
+            "pot 2",
+            Valve::new(peripherals.pins.gpio27.into())?,
+            PlantProfile::default(),
+        ),
+    ];
+    let zone_control = Rc::new(
+        ZoneControl::new(zones.len())
+            .with_pump_rest(pump.borrow().min_off_time())
+            .with_store(NvsStore::new(nvs.clone(), "flow")?),
+    );
+    // Peristaltic nutrient pump on gpio23, it doses into the running water on the calendar days
+    #[cfg(feature = "dosing")]
+    let doser = {
+        use actuator::dosing::Doser;
+        use termo_core::control::dosing::DosingConfig;
+
+        Rc::new(RefCell::new(Doser::new(
+            peripherals.pins.gpio23.into(),
+            DosingConfig::default(),
+            NvsStore::new(nvs.clone(), "dosing")?,
+        )?))
+    };
+    #[cfg(feature = "dosing")]
+    let dose = |watering| {
+        let mut doser = doser.borrow_mut();
+        match doser.update(watering) {
+            Ok(step) => {
+                let step = step?;
+                if step.low_stock {
+                    alert(format!(
+                        "Nutrient running low :warning:\\n> {:.0}ml left, refill the bottle and send the `refill` command",
+                        doser.remaining()
+                    ));
+                }
+                step.next_check
+            }
+            Err(err) => {
+                error!("Dosing pump error: {:?}", err);
+                None
+            }
+        }
+    };
+    #[cfg(not(feature = "dosing"))]
+    let dose = |_| None;
+    let watering = watering_task(
+        pump.clone(),
+        zones,
+        zone_control.clone(),
+        alert,
+        |record| {
+            journal
+                .borrow_mut()
+                .record(|journal| journal.set_watering(record))
+        },
+        dose,
+    );
+
+    // Grow lamp dimmed by PWM on gpio32
+    let lamp_timer = LedcTimerDriver::new(
+        peripherals.ledc.timer0,
+        &TimerConfig::new()
+            .frequency(1.kHz().into())
+            .resolution(Resolution::Bits13),
+    )?;
+    let lamp = Rc::new(Lamp::new(
+        LedcDriver::new(
+            peripherals.ledc.channel0,
+            lamp_timer,
+            peripherals.pins.gpio32,
+        )?,
+        LampConfig::default(),
+    )?);
+    lamp.set(journal.borrow().journal().lamp);
+    let publish_lamp = |level: u8| {
+        journal
+            .borrow_mut()
+            .record(|journal| journal.set_lamp(level));
+        #[cfg(feature = "mqtt")]
+        {
+            use relay::mqtt::SimpleMqttClient;
+            mqtt_client
+                .borrow_mut()
+                .state_message("status/lamp", level.to_string());
+        }
+    };
+    let lamp_control = lamp_task(lamp.clone(), publish_lamp);
+
+    // Ambient light on gpio39 for the daily light integral, dull days get more lamp time
+    #[cfg(feature = "light_sensor")]
+    let (period, light) = {
+        use sensor::light::AmbientLight;
+
+        let mut light_sensor = AmbientLight::new_shared(adc1.clone(), peripherals.pins.gpio39)?;
+        (
+            Photoperiod::vegetative().with_dli_target(DLI_TARGET),
+            move || light_sensor.get_measurment().ok(),
+        )
+    };
+    #[cfg(not(feature = "light_sensor"))]
+    let (period, light) = (Photoperiod::vegetative(), || None);
+    let light_day = journal.borrow().journal().light_day;
+    let photoperiod = photoperiod_task(lamp.clone(), period.clone(), light_day, light, |day| {
+        journal
+            .borrow_mut()
+            .record(|journal| journal.set_light_day(day))
+    });
+
+    // Heater relay on gpio18 and exhaust fan on gpio19, day setpoint while the lights are on
+    let climate = Rc::new(RefCell::new(
+        Climate::new(
+            Relay::new(peripherals.pins.gpio18.into())?,
+            Relay::new(peripherals.pins.gpio19.into())?,
+            ThermostatConfig::default(),
+        )
+        .with_store(NvsStore::new(nvs.clone(), "pid")?),
+    ));
+    // The heater and the fan start off, the thermostat decides on its first valid reading
+    #[allow(unused_variables)]
+    let publish_climate = |state: ClimateState| {
+        #[cfg(feature = "mqtt")]
+        {
+            use relay::mqtt::SimpleMqttClient;
+            mqtt_client
+                .borrow_mut()
+                .state_message("status/climate", state.to_json().to_string());
+        }
+    };
+    let thermostat = climate_task(
+        climate.clone(),
+        temp_sensor.clone(),
+        hum_sensor.clone(),
+        period,
+        publish_climate,
+    );
+
+    let actuators = Actuators {
+        pump,
+        zones: zone_control,
+        lamp,
+        climate: climate.clone(),
+        #[cfg(feature = "dosing")]
+        doser: doser.clone(),
+    };
+
+    // Event triggers, alarms go to discord and every event to MQTT
+    let triggers = {
+        use esp_idf_hal::gpio::{PinDriver, Pull};
+        use termo_core::trigger::{
+            Direction, Edge, EdgeTrigger, Event, EventBus, RateTrigger, Threshold,
+            ThresholdTrigger, Trigger,
+        };
+
+        let heat_sensor = temp_sensor.clone();
+        let rate_sensor = temp_sensor.clone();
+        let dry_sensor = soil_sensor.clone();
+        // Boot button of the dev board, it is pulled low while pressed
+        let mut button = PinDriver::input(peripherals.pins.gpio0)?;
+        button.set_pull(Pull::Up)?;
+        let triggers: Vec<Box<dyn Trigger>> = vec![
+            Box::new(ThresholdTrigger::new(
+                "temperature",
+                Threshold {
+                    direction: Direction::Above,
+                    limit: HEAT_ALARM,
+                    hysteresis: 2.0,
+                    dwell: Duration::from_secs(5 * 60),
+                },
+                move || heat_sensor.borrow_mut().get_measurment().ok(),
+            )),
+            Box::new(RateTrigger::new(
+                "temperature",
+                TEMPERATURE_RATE_ALARM,
+                Duration::from_secs(10 * 60),
+                move || rate_sensor.borrow_mut().get_measurment().ok(),
+            )),
+            Box::new(ThresholdTrigger::new(
+                "soil moisture",
+                Threshold {
+                    direction: Direction::Below,
+                    limit: DRY_ALARM,
+                    hysteresis: 3.0,
+                    dwell: Duration::from_secs(10 * 60),
+                },
+                move || dry_sensor.borrow_mut().get_measurment().ok(),
+            )),
+            Box::new(EdgeTrigger::new(
+                "button",
+                Edge::Falling,
+                Duration::from_millis(50),
+                move || Some(button.is_high()),
+            )),
+        ];
+
+        let mut bus = EventBus::default();
+        bus.subscribe(move |event| match event {
+            Event::Threshold {
+                source,
+                value,
+                limit,
+                active: true,
+            } => alert(format!(
+                "Alarm :warning:\n> {source} at {value:.1}, the limit is {limit:.1}"
+            )),
+            Event::Rate { source, rate } => alert(format!(
+                "Alarm :warning:\n> {source} changes {rate:+.1} per minute"
+            )),
+            _ => {}
+        });
+        // The button ends the manual overrides
+        let button_zones = actuators.zones.clone();
+        let button_lamp = actuators.lamp.clone();
+        bus.subscribe(move |event| {
+            if let Event::Edge { source, .. } = event {
+                if source == "button" {
+                    info!("Button pressed, manual overrides stopped");
+                    button_zones.stop_manual();
+                    button_lamp.cancel_override();
+                }
+            }
+        });
+        #[cfg(feature = "mqtt")]
+        bus.subscribe(|event| {
+            use relay::mqtt::SimpleMqttClient;
+            if let Ok(event) = serde_json::to_string(event) {
+                mqtt_client.borrow_mut().event_message(event);
+            }
+        });
+        trigger_task(triggers, bus)
+    };
+
+    // Automation rules from the NVS, they are replaced over MQTT
+    let rules = Rc::new(RefCell::new(RuleStore::open(NvsStore::new(
+        nvs.clone(),
+        "rules",
+    )?)));
+    let rule_facts = {
+        let soil_sensor = soil_sensor.clone();
+        let temp_sensor = temp_sensor.clone();
+        let hum_sensor = hum_sensor.clone();
+        let actuators = actuators.clone();
+        move |time| {
+            let mut readings = BTreeMap::new();
+            let sensors = [
+                ("soil", soil_sensor.borrow_mut().get_measurment().ok()),
+                (
+                    "temperature",
+                    temp_sensor.borrow_mut().get_measurment().ok(),
+                ),
+                ("humidity", hum_sensor.borrow_mut().get_measurment().ok()),
+                #[cfg(feature = "tank_level")]
+                (
+                    "tank",
+                    ultrasonic
+                        .borrow_mut()
+                        .measure_distance(sensor::hc_sr04::Unit::Centimeters)
+                        .ok()
+                        .flatten()
+                        .map(tank_percent),
+                ),
+            ];
+            for (name, reading) in sensors {
+                if let Some(reading) = reading {
+                    readings.insert(name.to_string(), reading);
+                }
+            }
+            let climate = actuators.climate.borrow().state();
+            let actuators = [
+                ("pump", actuators.pump.borrow().is_on()),
+                ("lamp", actuators.lamp.target() > 0),
+                ("heater", climate.heater),
+                ("fan", climate.fan),
+            ];
+            Facts {
+                time,
+                readings,
+                actuators: actuators
+                    .into_iter()
+                    .map(|(name, on)| (name.to_string(), on))
+                    .collect(),
+            }
+        }
+    };
+    let rule_actuators = actuators.clone();
+    let automation = rules_task(
+        rules.clone(),
+        rule_facts,
+        move |rule, action| match action {
+            Action::Notify(message) => alert(format!("Rule {rule}:\n> {message}")),
+            action => match action.command() {
+                Some(Command::Water { zone, amount, .. }) => {
+                    if let Err(err) = rule_actuators.water(zone.into(), amount) {
+                        alert(format!("Rule {rule} could not water zone {zone}: {err}"));
                     }
                 }
-            })
-            .detach();
+                Some(Command::Lamp { level, duration }) => rule_actuators
+                    .lamp
+                    .override_level(level, duration.unwrap_or(LAMP_OVERRIDE)),
+                command => warn!("Rule {rule} has no action for {command:?}"),
+            },
+        },
+    );
+
+    // Wall clock, the time based tasks wait for the first sync
+    let clock = sntp_task(config.borrow().config().ntp_servers.clone());
+
+    // Jobs run by the scheduler, they can be enabled and disabled over MQTT by their index
+    let mut scheduler = Scheduler::new(vec![
+        // Daily report on discord at 8 AM, sent late if the device was off at 8
+        Job::new("report", "0 8 *")?.with_catch_up(CatchUp::Within(REPORT_CATCH_UP)),
+    ]);
+    let (runs, last_runs) = RunStore::open(NvsStore::new(nvs.clone(), "schedule")?);
+    scheduler.restore_runs(last_runs);
+    {
+        let config = config.borrow();
+        let config = config.config();
+        scheduler.set_location(config.location, config.timezone.clone());
+    }
+    let scheduler = Rc::new(RefCell::new(scheduler));
+    let discord_wifi_handler = wifi_handler.clone();
+    let jobs = scheduler_task(scheduler.clone(), runs, |job| match job {
+        "report" => {
+            let mut message = get_message(
+                &mut *soil_sensor.borrow_mut(),
+                &mut *hum_sensor.borrow_mut(),
+                &mut *temp_sensor.borrow_mut(),
+            );
+            message.push_str(&get_climate_message(&climate.borrow().state()));
+            message.push_str(&time_sync().message(chrono::Utc::now().naive_utc()));
+            #[cfg(feature = "hydro")]
+            message.push_str(&termo_core::report::get_hydro_message(
+                &mut *ph_probe.borrow_mut(),
+                &mut *ec_probe.borrow_mut(),
+            ));
+
+            executor
+                .spawn(send_to_discord(discord_wifi_handler.clone(), message))
+                .detach();
+        }
+        job => warn!("Job {job} has nothing to run"),
     });
 
     // Start the executor with the tasks
     block_on(executor.run(async {
-        let _ = join!(executor.spawn(discord_notification), executor.spawn(pump));
+        executor
+            .spawn(send_to_discord(wifi_handler.clone(), diagnostics))
+            .detach();
+        #[cfg(feature = "mqtt")]
+        executor
+            .spawn(relay::command::handle_commands(
+                commands,
+                mqtt_client.clone(),
+                actuators.clone(),
+                scheduler.clone(),
+                #[cfg(feature = "hydro")]
+                sensor::hydro::HydroProbes {
+                    ph: ph_probe.clone(),
+                    ec: ec_probe.clone(),
+                },
+            ))
+            .detach();
+        #[cfg(feature = "mqtt")]
+        executor
+            .spawn(relay::command::handle_updates(
+                updates,
+                mqtt_client.clone(),
+                config.clone(),
+                rules.clone(),
+                scheduler.clone(),
+            ))
+            .detach();
+        let _ = join!(
+            executor.spawn(clock),
+            executor.spawn(jobs),
+            executor.spawn(watering),
+            executor.spawn(lamp_control),
+            executor.spawn(photoperiod),
+            executor.spawn(thermostat),
+            executor.spawn(triggers),
+            executor.spawn(automation),
+            executor.spawn(soil_task(soil_sensor.clone()))
+        );
     }));
 
     warn!("Tasks completed");
 
     Ok(())
 }
+
+/// Fill level of the tank in percent from the distance to the water surface
+#[cfg(feature = "tank_level")]
+fn tank_percent(distance: f32) -> f32 {
+    let level = (TANK_EMPTY_DISTANCE - distance) / (TANK_EMPTY_DISTANCE - TANK_FULL_DISTANCE);
+    (level * 100.0).clamp(0.0, 100.0)
+}
+
+/// Post the message to discord, the wifi is brought up for the time of the request if needed
+async fn send_to_discord(wifi_handler: Rc<RwLock<WifiRelay>>, message: String) {
+    let wifi = wifi_handler.read().await;
+    match wifi.get_inner().is_connected() {
+        Ok(true) => {
+            discord_webhook(message).await.ok();
+        }
+        Ok(false) => {
+            drop(wifi);
+            let mut wifi = wifi_handler.write().await;
+            wifi.reconnect().await.ok();
+            trigger::timer::safe_sleep(Duration::from_secs(3)).await;
+            discord_webhook(message).await.ok();
+            wifi.disconnect().await.ok();
+        }
+        Err(_) => {
+            error!("Wifi handler not awailable");
+        }
+    }
+}
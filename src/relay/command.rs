@@ -0,0 +1,212 @@
+//! Handling of the commands received over MQTT
+
+use std::{cell::RefCell, rc::Rc};
+
+use esp_idf_svc::mqtt::client::EspMqttClient;
+use futures::{channel::mpsc::UnboundedReceiver, StreamExt};
+use log::warn;
+use serde_json::{json, Value};
+#[cfg(feature = "hydro")]
+use termo_core::command::HydroProbe;
+use termo_core::{
+    command::{Command, CommandError, PidLoop},
+    control::{flow::CALIBRATION_RUN, overrides::LAMP_OVERRIDE},
+    rules::parse_rules,
+    schedule::Scheduler,
+};
+
+use super::mqtt::{SimplCommandError, SimpleMqttClient, Update};
+#[cfg(feature = "hydro")]
+use crate::sensor::hydro::HydroProbes;
+use crate::{
+    actuator::Actuators,
+    trigger::timer::time_sync,
+    utils::{config::ConfigStore, rules::RuleStore},
+};
+
+pub type CommandReceiver = UnboundedReceiver<Result<Command, CommandError>>;
+pub type UpdateReceiver = UnboundedReceiver<Update>;
+
+/// Execute the commands forwarded by the MQTT client, answers go to the message topics
+pub async fn handle_commands(
+    mut commands: CommandReceiver,
+    mqtt: Rc<RefCell<EspMqttClient<'_>>>,
+    actuators: Actuators<'_>,
+    scheduler: Rc<RefCell<Scheduler>>,
+    #[cfg(feature = "hydro")] probes: HydroProbes<'_>,
+) {
+    while let Some(command) = commands.next().await {
+        let mut mqtt = mqtt.borrow_mut();
+        match command {
+            Ok(Command::AckAlert) => match actuators.pump.borrow_mut().acknowledge() {
+                Some(trip) => mqtt.safe_message(format!("Pump alert acknowledged: {trip}")),
+                None => mqtt.safe_message("No pump alert to acknowledge".to_string()),
+            },
+            Ok(Command::Water {
+                zone,
+                on: true,
+                amount,
+            }) => {
+                if let Err(err) = actuators.water(zone.into(), amount) {
+                    mqtt.error_message(err);
+                }
+            }
+            Ok(Command::Water {
+                zone, on: false, ..
+            }) => actuators.zones.stop(zone.into()),
+            Ok(Command::CalibrateFlow(zone)) => {
+                match actuators.zones.start_calibration(zone.into()) {
+                    Ok(()) => mqtt.safe_message(format!(
+                        "Calibrating zone {zone}, catch the water of the next {}s and send its volume",
+                        CALIBRATION_RUN.as_secs()
+                    )),
+                    Err(err) => mqtt.error_message(err.to_string()),
+                }
+            }
+            Ok(Command::FlowMeasured { zone, volume }) => {
+                match actuators.zones.calibrate(zone.into(), volume) {
+                    Ok(flow) => mqtt.safe_message(format!("Zone {zone} flow: {:.1} ml/s", flow.0)),
+                    Err(err) => mqtt.error_message(err.to_string()),
+                }
+            }
+            Ok(Command::Lamp { level, duration }) => {
+                let duration = duration.unwrap_or(LAMP_OVERRIDE);
+                actuators.lamp.override_level(level, duration);
+                mqtt.safe_message(format!(
+                    "Lamp at {level}% for {} min, send `stop` to resume the schedule",
+                    duration.as_secs() / 60
+                ));
+            }
+            Ok(Command::Stop) => {
+                actuators.zones.stop_manual();
+                actuators.lamp.cancel_override();
+                mqtt.safe_message("Manual overrides stopped, back to automatic".to_string());
+            }
+            Ok(Command::TunePid {
+                target: PidLoop::Heater,
+                gains,
+            }) => match actuators.climate.borrow_mut().tune(gains) {
+                Ok(()) => mqtt.safe_message(format!("Heater PID gains set: {gains:?}")),
+                Err(err) => mqtt.error_message(format!("Heater PID gains not stored: {err}")),
+            },
+            Ok(Command::Job { job, enabled }) => {
+                match scheduler.borrow_mut().set_enabled(job.into(), enabled) {
+                    Ok(job) => mqtt.safe_message(format!(
+                        "Job {} ( {} ) {}",
+                        job.name,
+                        job.when,
+                        if job.enabled { "enabled" } else { "disabled" }
+                    )),
+                    Err(err) => mqtt.error_message(err.to_string()),
+                }
+            }
+            #[cfg(feature = "dosing")]
+            Ok(Command::Refill) => {
+                let mut doser = actuators.doser.borrow_mut();
+                doser.refill();
+                mqtt.safe_message(format!(
+                    "Nutrient bottle refilled, {:.0}ml",
+                    doser.remaining()
+                ));
+            }
+            #[cfg(feature = "hydro")]
+            Ok(Command::CalibratePh(buffer)) => match probes.ph.borrow_mut().calibrate(buffer) {
+                Ok(()) => mqtt.safe_message(format!("pH probe calibrated at {buffer:.2}")),
+                Err(err) => mqtt.error_message(format!("pH calibration failed: {err}")),
+            },
+            #[cfg(feature = "hydro")]
+            Ok(Command::CalibrateEc(buffer)) => match probes.ec.borrow_mut().calibrate(buffer) {
+                Ok(()) => mqtt.safe_message(format!("EC probe calibrated at {buffer:.3}mS/cm")),
+                Err(err) => mqtt.error_message(format!("EC calibration failed: {err}")),
+            },
+            #[cfg(feature = "hydro")]
+            Ok(Command::ClearCalibration(probe)) => {
+                let cleared = match probe {
+                    HydroProbe::Ph => probes.ph.borrow_mut().clear_calibration(),
+                    HydroProbe::Ec => probes.ec.borrow_mut().clear_calibration(),
+                };
+                match cleared {
+                    Ok(()) => mqtt.safe_message(format!("{probe:?} calibration cleared")),
+                    Err(err) => mqtt.error_message(format!("Calibration not cleared: {err}")),
+                }
+            }
+            Ok(Command::AllSemorData) => {
+                #[allow(unused_mut)]
+                let mut data = json!({
+                    "climate": actuators.climate.borrow().to_json(),
+                    "pump": actuators.pump.borrow().to_json(),
+                    "lamp": actuators.lamp.target(),
+                    "overrides": overrides(&actuators),
+                    "jobs": scheduler.borrow().jobs(),
+                    "time": time_sync(),
+                });
+                #[cfg(feature = "dosing")]
+                {
+                    data["dosing"] = actuators.doser.borrow().to_json();
+                }
+                mqtt.safe_message(data.to_string());
+            }
+            Ok(command) => warn!("Command not handled yet: {command:?}"),
+            Err(err) => mqtt.error_message(SimplCommandError::from(err).to_string()),
+        }
+    }
+}
+
+/// Apply and store the configuration and the rules updates, the stored values are the answer.\
+/// The timezone, the location and the rules take effect right away.
+pub async fn handle_updates(
+    mut updates: UpdateReceiver,
+    mqtt: Rc<RefCell<EspMqttClient<'_>>>,
+    config: Rc<RefCell<ConfigStore>>,
+    rules: Rc<RefCell<RuleStore>>,
+    scheduler: Rc<RefCell<Scheduler>>,
+) {
+    while let Some(update) = updates.next().await {
+        let mut mqtt = mqtt.borrow_mut();
+        match update {
+            Update::Config(update) => {
+                let mut config = config.borrow_mut();
+                match config.config().updated(&update) {
+                    Ok(updated) => match config.update(updated) {
+                        Ok(()) => {
+                            let config = config.config();
+                            scheduler
+                                .borrow_mut()
+                                .set_location(config.location, config.timezone.clone());
+                            mqtt.safe_message(json!({ "config": config }).to_string())
+                        }
+                        Err(err) => mqtt.error_message(format!("Configuration not stored: {err}")),
+                    },
+                    Err(err) => mqtt.error_message(err.to_string()),
+                }
+            }
+            Update::Rules(update) => {
+                let mut rules = rules.borrow_mut();
+                match parse_rules(&update) {
+                    Ok(parsed) => match rules.update(parsed) {
+                        Ok(()) => mqtt.safe_message(json!({ "rules": rules.rules() }).to_string()),
+                        Err(err) => mqtt.error_message(format!("Rules not stored: {err}")),
+                    },
+                    Err(err) => mqtt.error_message(err.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// Running manual overrides with their time left in seconds
+fn overrides(actuators: &Actuators) -> Value {
+    let lamp = actuators
+        .lamp
+        .active_override()
+        .map(|(level, remaining)| json!({ "level": level, "remaining": remaining.as_secs() }));
+    let zones: Vec<Value> = actuators
+        .zones
+        .overrides()
+        .into_iter()
+        .map(|(zone, remaining)| {
+            json!({ "zone": zone, "remaining": remaining.map(|remaining| remaining.as_secs()) })
+        })
+        .collect();
+    json!({ "lamp": lamp, "zones": zones })
+}
@@ -1,6 +1,8 @@
 use esp_idf_svc::eventloop::{Background, EspEventLoop, EspSubscription, User};
 use esp_idf_sys::EspError;
 
+#[cfg(feature = "mqtt")]
+pub mod command;
 pub mod discord;
 pub mod mqtt;
 pub mod ota;
@@ -6,10 +6,8 @@ use esp_idf_svc::eventloop::{
 use esp_idf_svc::tls::X509;
 use log::{error, info};
 use macro_lib::EspEvent;
-use serde_json::Value;
 
-use serde::{Deserialize, Serialize};
-use std::str::{from_utf8, FromStr};
+use std::str::from_utf8;
 use std::time::Duration;
 
 use dotenvy_macro::dotenv;
@@ -22,13 +20,27 @@ use esp_idf_svc::mqtt::client::{EspMqttClient, MqttClientConfiguration};
 // If using the `binstart` feature of `esp-idf-sys`, always keep this module imported
 use esp_idf_sys::{self as _, EspError};
 
+use termo_core::command::{Command, CommandError};
+
 const USERNAME: &str = dotenv!("USERNAME");
 const KEY: &str = dotenv!("KEY");
 const MQTT_SERVER: &str = dotenv!("MQTT_SERVER");
 const CERT: &[u8] = include_bytes!("../../certs/cert.pem");
+/// Device configuration updates, JSON objects of the changed fields
+pub const CONFIG_TOPIC: &str = "station/config";
+/// Automation rules, the JSON list of all the rules
+pub const RULES_TOPIC: &str = "station/rules";
+
+/// JSON documents received on the update topics
+#[derive(Debug)]
+pub enum Update {
+    Config(Vec<u8>),
+    Rules(Vec<u8>),
+}
 
 pub fn new_mqqt_client<'a>(
     process_message: impl Fn(Result<Command, CommandError>) + Send + 'static,
+    process_update: impl Fn(Update) + Send + 'static,
 ) -> Result<EspMqttClient<'a>, EspError> {
     let conf = MqttClientConfiguration {
         client_id: Some("esp32-sensore"),
@@ -50,6 +62,17 @@ pub fn new_mqqt_client<'a>(
         &conf,
         move |message_event| {
             match message_event {
+                Ok(Received(msg))
+                    if matches!(msg.topic().as_deref(), Some(CONFIG_TOPIC | RULES_TOPIC)) =>
+                {
+                    match msg.details() {
+                        Complete if msg.topic().as_deref() == Some(CONFIG_TOPIC) => {
+                            process_update(Update::Config(msg.data().to_vec()))
+                        }
+                        Complete => process_update(Update::Rules(msg.data().to_vec())),
+                        _ => info!("Received partial update: {:?}", message_event),
+                    }
+                }
                 Ok(Received(msg)) => {
                     let result: Result<(), CommandError> = match msg.details() {
                         Complete => from_utf8(msg.data())
@@ -80,6 +103,8 @@ pub fn new_mqqt_client<'a>(
     )?;
 
     client.subscribe("station/cmd", QoS::AtLeastOnce)?;
+    client.subscribe(CONFIG_TOPIC, QoS::AtLeastOnce)?;
+    client.subscribe(RULES_TOPIC, QoS::AtLeastOnce)?;
 
     info!("MQTT Listening for messages");
 
@@ -97,6 +122,10 @@ pub trait SimpleMqttClient {
     fn message(&mut self, msg: String) -> Result<(), EspError>;
     fn safe_message(&mut self, msg: String);
     fn error_message(&mut self, msg: String);
+    /// Retained state, new subscribers get the last value right away
+    fn state_message(&mut self, topic: &str, msg: String);
+    /// Events raised by the triggers
+    fn event_message(&mut self, msg: String);
 }
 
 impl SimpleMqttClient for EspMqttClient<'_> {
@@ -116,22 +145,35 @@ impl SimpleMqttClient for EspMqttClient<'_> {
                 error!("Error sending message: {:?}", err);
             });
     }
+    fn state_message(&mut self, topic: &str, msg: String) {
+        let _ = self
+            .publish(topic, QoS::AtLeastOnce, true, msg.as_bytes())
+            .map_err(|err| {
+                error!("Error sending state: {:?}", err);
+            });
+    }
+    fn event_message(&mut self, msg: String) {
+        let _ = self
+            .publish("status/event", QoS::AtLeastOnce, false, msg.as_bytes())
+            .map_err(|err| {
+                error!("Error sending event: {:?}", err);
+            });
+    }
 }
 
-#[derive(Debug, PartialEq, Clone, Copy, EspEvent)]
-pub enum Command {
-    Water(bool),
-    Lamp(u8),
-    ReadBarometer,
-    ReadSoilMoisture,
-    AllSemorData,
-}
-
+/// [`Command`] posted on the esp event loop
 #[derive(Debug, Clone, Copy, EspEvent)]
+pub struct CommandEvent(pub Command);
+
+#[derive(Debug, Clone, Copy, EspEvent, thiserror::Error)]
 pub enum SimplCommandError {
+    #[error("Unknown command")]
     WrongCommand,
+    #[error("Missing or wrong value")]
     InvalidValue,
+    #[error("Invalid Json")]
     JsonParseError,
+    #[error("Invalid encoding ( utf8 parsing failed )")]
     ParseError,
 }
 impl From<CommandError> for SimplCommandError {
@@ -144,53 +186,3 @@ impl From<CommandError> for SimplCommandError {
         }
     }
 }
-
-#[derive(Debug, thiserror::Error)]
-pub enum CommandError {
-    #[error("Command is not recognized")]
-    WrongCommand(CommandJson),
-    #[error("Command Value is invalid: {0}")]
-    InvalidValue(Value),
-    #[error("Command is not valid JSON")]
-    JsonParseError(serde_json::error::Category),
-    #[error("Message is not valid UTF8")]
-    ParseError(#[from] std::str::Utf8Error),
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct CommandJson {
-    name: String,
-    value: Option<serde_json::Value>,
-}
-
-//
-impl FromStr for Command {
-    fn from_str(input: &str) -> Result<Command, CommandError> {
-        let parsed_command = serde_json::from_str::<CommandJson>(input);
-        info!("Got command: {:?}", parsed_command);
-        match parsed_command {
-            Ok(command) => {
-                let error_cmd = command.clone();
-                match command.name.as_str() {
-                    "water" => {
-                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
-                        let value = value.as_bool().ok_or(CommandError::InvalidValue(value))?;
-                        Ok(Command::Water(value))
-                    }
-                    "lamp" => {
-                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
-                        let value = value.as_u64().ok_or(CommandError::InvalidValue(value))?;
-                        Ok(Command::Lamp(value as u8))
-                    }
-                    "read_barometer" => Ok(Command::ReadBarometer),
-                    "read_soil_moisture" => Ok(Command::ReadSoilMoisture),
-                    "all" => Ok(Command::AllSemorData),
-                    _ => Err(CommandError::WrongCommand(error_cmd)),
-                }
-            }
-            Err(err) => Err(CommandError::JsonParseError(err.classify())),
-        }
-    }
-
-    type Err = CommandError;
-}
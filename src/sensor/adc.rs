@@ -0,0 +1,18 @@
+use std::sync::{Arc, Mutex};
+
+use esp_idf_hal::{
+    adc::{config::Config, Adc, AdcDriver},
+    peripheral::Peripheral,
+};
+use esp_idf_sys::EspError;
+
+/// One ADC unit shared by every analog sensor wired to its channels
+pub type SharedAdc<'d, ADC> = Arc<Mutex<AdcDriver<'d, ADC>>>;
+
+/// Create a calibrated adc driver that can be handed to multiple analog sensors
+pub fn new_shared_adc<'d, ADC: Adc>(
+    adc: impl Peripheral<P = ADC> + 'd,
+) -> Result<SharedAdc<'d, ADC>, EspError> {
+    let driver = AdcDriver::new(adc, &Config::new().calibration(true))?;
+    Ok(Arc::new(Mutex::new(driver)))
+}
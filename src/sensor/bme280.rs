@@ -11,7 +11,8 @@ use esp_idf_hal::{
 };
 use esp_idf_sys::EspError;
 use log::{error, info};
-use parse_display::Display;
+
+use termo_core::sensor::status::{HumidityStatus, PressureStatus, TempStatus};
 
 use super::*;
 
@@ -91,6 +92,7 @@ pub fn get_bme280_sensors(
     }
 }
 
+#[derive(Clone)]
 pub struct Bme280TempSensor {
     bme280: Option<Arc<Mutex<Bme280<I2cDriver<'static>, Delay>>>>,
     unit: &'static str,
@@ -105,14 +107,6 @@ impl Default for Bme280TempSensor {
         }
     }
 }
-#[derive(Debug, Display)]
-pub enum TempStatus {
-    Freezing,
-    Cold,
-    Optimal,
-    Hot,
-}
-
 impl Sensor for Bme280TempSensor {
     type Error = Bme280Error;
     type Status = TempStatus;
@@ -129,12 +123,7 @@ impl Sensor for Bme280TempSensor {
 
     fn get_status(&mut self) -> Result<Self::Status, Self::Error> {
         let temp = self.get_measurment()?;
-        match temp {
-            t if t < 0.0 => Ok(TempStatus::Freezing),
-            t if t < 18.0 => Ok(TempStatus::Cold),
-            t if t < 25.0 => Ok(TempStatus::Optimal),
-            _ => Ok(TempStatus::Hot),
-        }
+        Ok(TempStatus::from_celsius(temp))
     }
 
     fn get_unit(&self) -> &str {
@@ -160,13 +149,6 @@ impl Default for Bme280HumiditySensor {
         }
     }
 }
-#[derive(Debug, Display)]
-pub enum HumidityStatus {
-    Dry,
-    Optimal,
-    Moist,
-    Wet,
-}
 impl Sensor for Bme280HumiditySensor {
     type Error = Bme280Error;
     type Status = HumidityStatus;
@@ -181,12 +163,7 @@ impl Sensor for Bme280HumiditySensor {
 
     fn get_status(&mut self) -> Result<Self::Status, Self::Error> {
         let humidity = self.get_measurment()?;
-        match humidity {
-            h if h < 30.0 => Ok(HumidityStatus::Dry),
-            h if h < 50.0 => Ok(HumidityStatus::Optimal),
-            h if h < 70.0 => Ok(HumidityStatus::Moist),
-            _ => Ok(HumidityStatus::Wet),
-        }
+        Ok(HumidityStatus::from_precentage(humidity))
     }
 
     fn get_unit(&self) -> &str {
@@ -212,12 +189,6 @@ impl Default for Bme280PressureSensor {
         }
     }
 }
-#[derive(Debug, Display)]
-pub enum PressureStatus {
-    Low,
-    Optimal,
-    High,
-}
 impl Sensor for Bme280PressureSensor {
     type Error = Bme280Error;
     type Status = PressureStatus;
@@ -234,11 +205,7 @@ impl Sensor for Bme280PressureSensor {
 
     fn get_status(&mut self) -> Result<Self::Status, Self::Error> {
         let pressure = self.get_measurment()?;
-        match pressure {
-            p if p < 1000.0 => Ok(PressureStatus::Low),
-            p if p < 1013.0 => Ok(PressureStatus::Optimal),
-            _ => Ok(PressureStatus::High),
-        }
+        Ok(PressureStatus::from_hpa(pressure))
     }
 
     fn get_unit(&self) -> &str {
@@ -0,0 +1,75 @@
+use esp_idf_hal::{
+    gpio::{AnyInputPin, InputPin},
+    pcnt::{
+        Pcnt, PcntChannel, PcntChannelConfig, PcntControlMode, PcntCountMode, PcntDriver, PinIndex,
+    },
+    peripheral::Peripheral,
+};
+use esp_idf_sys::EspError;
+
+/// YF-S201 hall flow sensor: 450 pulses per litre
+const PULSES_PER_ML: f32 = 0.45;
+/// Glitch filter of the pulse counter in APB clock cycles ( 80 MHz )
+const FILTER_CYCLES: u16 = 1000;
+
+/// Hall effect flow meter on the pump outlet, the pulses are counted by the PCNT peripheral
+pub struct FlowMeter<'d> {
+    counter: PcntDriver<'d>,
+    pulses: u64,
+    pulses_per_ml: f32,
+}
+
+impl<'d> FlowMeter<'d> {
+    /// pcnt -> pulse counter unit from the peripherals
+    /// pin -> gpio the pulse output of the meter is connected to
+    pub fn new<PCNT: Pcnt>(
+        pcnt: impl Peripheral<P = PCNT> + 'd,
+        pin: impl Peripheral<P = impl InputPin> + 'd,
+    ) -> Result<Self, EspError> {
+        let mut counter = PcntDriver::new(
+            pcnt,
+            Some(pin),
+            Option::<AnyInputPin>::None,
+            Option::<AnyInputPin>::None,
+            Option::<AnyInputPin>::None,
+        )?;
+        counter.channel_config(
+            PcntChannel::Channel0,
+            PinIndex::Pin0,
+            PinIndex::Pin1,
+            &PcntChannelConfig {
+                lctrl_mode: PcntControlMode::Keep,
+                hctrl_mode: PcntControlMode::Keep,
+                pos_mode: PcntCountMode::Increment,
+                neg_mode: PcntCountMode::Hold,
+                counter_h_lim: i16::MAX,
+                counter_l_lim: 0,
+            },
+        )?;
+        counter.set_filter_value(FILTER_CYCLES)?;
+        counter.filter_enable()?;
+        counter.counter_pause()?;
+        counter.counter_clear()?;
+        counter.counter_resume()?;
+        Ok(Self {
+            counter,
+            pulses: 0,
+            pulses_per_ml: PULSES_PER_ML,
+        })
+    }
+
+    /// Pulses per ml of a different meter
+    pub fn with_pulses_per_ml(mut self, pulses_per_ml: f32) -> Self {
+        self.pulses_per_ml = pulses_per_ml;
+        self
+    }
+
+    /// Volume in ml that went through the meter since boot.\
+    /// The hardware counter is only 16 bit, it is emptied into the total on every read.
+    pub fn total_ml(&mut self) -> Result<f32, EspError> {
+        let count = self.counter.get_counter_value()?;
+        self.counter.counter_clear()?;
+        self.pulses += count.max(0) as u64;
+        Ok(self.pulses as f32 / self.pulses_per_ml)
+    }
+}
@@ -0,0 +1,292 @@
+//! Analog **pH** and **EC** probes for the hydroponic tub.\
+//! Both probes sit on spare ADC1 channels and share the adc driver with the soil sensor.
+//! The buffer calibration of each probe is kept in the NVS so it survives reboots.
+
+use super::{adc::*, *};
+use crate::utils::nvs::{NvsStore, StorageError};
+use esp_idf_hal::{
+    adc::{attenuation, Adc, AdcChannelDriver},
+    gpio::ADCPin,
+    peripheral::Peripheral,
+};
+use esp_idf_sys::EspError;
+use log::{info, warn};
+use std::{cell::RefCell, rc::Rc};
+use termo_core::sensor::{
+    calibration::{temperature_factor, Calibration, EC_REFERENCE_TEMP},
+    status::{CropRange, EcStatus, PhStatus},
+};
+
+/// Number of adc reads averaged for one measurement
+const SAMPLES: u16 = 10;
+/// Below this voltage the pH amplifier board is considered unplugged
+const PH_MIN_MILLIVOLTS: u16 = 50;
+
+const PH_CALIBRATION_KEY: &str = "ph_cal";
+const EC_CALIBRATION_KEY: &str = "ec_cal";
+
+#[derive(Debug, thiserror::Error)]
+pub enum HydroError {
+    #[error("Sensor not connected")]
+    SensorNotConnected(),
+    #[error("Probe is not calibrated, at least 2 buffer points are needed")]
+    NotCalibrated(),
+    #[error("Calibration storage error")]
+    Storage(#[from] StorageError),
+    #[error("EspError internal error")]
+    EspError(#[from] EspError),
+}
+type HydroResult<T> = Result<T, HydroError>;
+
+/// Buffer calibration of a probe, run with the `calibrate_ph`, `calibrate_ec` and
+/// `clear_calibration` commands. The points are stored in the NVS right away.
+pub trait BufferCalibration {
+    /// Record the probe sitting in the buffer with the known `reference` value
+    fn calibrate(&mut self, reference: f32) -> HydroResult<()>;
+    fn clear_calibration(&mut self) -> HydroResult<()>;
+}
+
+/// The probes of the tub, shared by the daily report and the command handler
+#[derive(Clone)]
+pub struct HydroProbes<'d> {
+    pub ph: Rc<RefCell<dyn BufferCalibration + 'd>>,
+    pub ec: Rc<RefCell<dyn BufferCalibration + 'd>>,
+}
+
+/// Analog probe reading shared by the pH and EC sensors
+struct AnalogProbe<'d, T: ADCPin, ADC: Adc> {
+    adc_driver: SharedAdc<'d, ADC>,
+    adc_pin: AdcChannelDriver<'d, { attenuation::DB_11 }, T>,
+    calibration: Calibration,
+    store: NvsStore,
+    key: &'static str,
+}
+
+impl<'d, T: ADCPin, ADC: Adc> AnalogProbe<'d, T, ADC>
+where
+    T: ADCPin<Adc = ADC>,
+{
+    fn new(
+        adc: SharedAdc<'d, ADC>,
+        pin: impl Peripheral<P = T> + 'd,
+        store: NvsStore,
+        key: &'static str,
+    ) -> HydroResult<Self> {
+        let calibration: Option<Calibration> = store.load(key).unwrap_or_else(|err| {
+            warn!("Could not load {key} calibration: {:?}", err);
+            None
+        });
+        Ok(Self {
+            adc_driver: adc,
+            adc_pin: AdcChannelDriver::new(pin)?,
+            calibration: calibration.unwrap_or_default(),
+            store,
+            key,
+        })
+    }
+
+    /// Averaged probe voltage in mV
+    fn get_millivolts(&mut self) -> HydroResult<u16> {
+        let mut adc = self
+            .adc_driver
+            .lock()
+            .or(Err(HydroError::SensorNotConnected()))?;
+        let sum = (0..SAMPLES)
+            .map(|_| adc.read(&mut self.adc_pin).map(u32::from))
+            .sum::<Result<u32, EspError>>()?;
+        Ok((sum / SAMPLES as u32) as u16)
+    }
+
+    fn calibrated(&mut self, millivolts: u16) -> HydroResult<f32> {
+        self.calibration
+            .apply(millivolts as f32)
+            .ok_or(HydroError::NotCalibrated())
+    }
+
+    /// Record the current probe voltage for the buffer solution with the known `reference` value
+    fn calibrate(&mut self, reference: f32) -> HydroResult<()> {
+        let millivolts = self.get_millivolts()?;
+        self.calibration.add_point(millivolts as f32, reference);
+        self.store.store(self.key, &self.calibration)?;
+        info!("{} point {reference} stored at {millivolts}mV", self.key);
+        Ok(())
+    }
+
+    fn clear_calibration(&mut self) -> HydroResult<()> {
+        self.calibration = Calibration::default();
+        self.store.remove(self.key)?;
+        Ok(())
+    }
+}
+
+/// Analog pH probe, calibrate with pH 4.0, 7.0 and optionally 10.0 buffers
+pub struct PhProbe<'d, T: ADCPin, ADC: Adc> {
+    probe: AnalogProbe<'d, T, ADC>,
+    range: CropRange,
+}
+
+impl<'d, T: ADCPin, ADC: Adc> PhProbe<'d, T, ADC>
+where
+    T: ADCPin<Adc = ADC>,
+{
+    /// adc -> adc driver shared with the other analog sensors
+    /// pin -> gpio from peripherals pins that is connected
+    /// store -> nvs store the calibration is kept in
+    pub fn new(
+        adc: SharedAdc<'d, ADC>,
+        pin: impl Peripheral<P = T> + 'd,
+        store: NvsStore,
+    ) -> HydroResult<Self> {
+        Ok(Self {
+            probe: AnalogProbe::new(adc, pin, store, PH_CALIBRATION_KEY)?,
+            range: CropRange { min: 5.5, max: 6.5 },
+        })
+    }
+
+    /// Set the optimal pH range of the crop
+    pub fn with_range(mut self, range: CropRange) -> Self {
+        self.range = range;
+        self
+    }
+
+    pub fn get_ph(&mut self) -> HydroResult<f32> {
+        let millivolts = match self.probe.get_millivolts()? {
+            mv if mv < PH_MIN_MILLIVOLTS => Err(HydroError::SensorNotConnected()),
+            mv => Ok(mv),
+        }?;
+        self.probe.calibrated(millivolts)
+    }
+}
+
+impl<T: ADCPin, ADC: Adc> BufferCalibration for PhProbe<'_, T, ADC>
+where
+    T: ADCPin<Adc = ADC>,
+{
+    fn calibrate(&mut self, buffer_ph: f32) -> HydroResult<()> {
+        self.probe.calibrate(buffer_ph)
+    }
+
+    fn clear_calibration(&mut self) -> HydroResult<()> {
+        self.probe.clear_calibration()
+    }
+}
+
+impl<T: ADCPin, ADC: Adc> Sensor for PhProbe<'_, T, ADC>
+where
+    T: ADCPin<Adc = ADC>,
+{
+    type Error = HydroError;
+    type Status = PhStatus;
+
+    fn get_unit(&self) -> &str {
+        "pH"
+    }
+
+    fn get_name(&self) -> &str {
+        "pH"
+    }
+
+    fn get_measurment(&mut self) -> Result<f32, Self::Error> {
+        self.get_ph()
+    }
+
+    fn get_status(&mut self) -> Result<Self::Status, Self::Error> {
+        Ok(self.range.status(self.get_ph()?).into())
+    }
+}
+
+/// Analog EC probe, calibrate with 1.413 and 12.88 mS/cm buffers.\
+/// Readings are compensated to 25°C with the water temperature source.
+pub struct EcProbe<'d, T: ADCPin, ADC: Adc> {
+    probe: AnalogProbe<'d, T, ADC>,
+    range: CropRange,
+    water_temperature: Option<Box<dyn FnMut() -> Option<f32> + 'd>>,
+}
+
+impl<'d, T: ADCPin, ADC: Adc> EcProbe<'d, T, ADC>
+where
+    T: ADCPin<Adc = ADC>,
+{
+    /// adc -> adc driver shared with the other analog sensors
+    /// pin -> gpio from peripherals pins that is connected
+    /// store -> nvs store the calibration is kept in
+    pub fn new(
+        adc: SharedAdc<'d, ADC>,
+        pin: impl Peripheral<P = T> + 'd,
+        store: NvsStore,
+    ) -> HydroResult<Self> {
+        Ok(Self {
+            probe: AnalogProbe::new(adc, pin, store, EC_CALIBRATION_KEY)?,
+            range: CropRange { min: 1.2, max: 2.4 },
+            water_temperature: None,
+        })
+    }
+
+    /// Set the optimal EC range of the crop in mS/cm
+    pub fn with_range(mut self, range: CropRange) -> Self {
+        self.range = range;
+        self
+    }
+
+    /// Source of the water temperature in °C used for the compensation
+    pub fn with_temperature_source(mut self, source: impl FnMut() -> Option<f32> + 'd) -> Self {
+        self.water_temperature = Some(Box::new(source));
+        self
+    }
+
+    /// Conductivity in mS/cm compensated to 25°C
+    pub fn get_ec(&mut self) -> HydroResult<f32> {
+        let millivolts = self.probe.get_millivolts()?;
+        let raw_ec = self.probe.calibrated(millivolts)?.max(0.0);
+        Ok(raw_ec / temperature_factor(self.get_water_temperature()))
+    }
+
+    fn get_water_temperature(&mut self) -> f32 {
+        match self.water_temperature.as_mut().and_then(|source| source()) {
+            Some(temp) => temp,
+            None => {
+                warn!("No water temperature, EC is not compensated");
+                EC_REFERENCE_TEMP
+            }
+        }
+    }
+}
+
+impl<T: ADCPin, ADC: Adc> BufferCalibration for EcProbe<'_, T, ADC>
+where
+    T: ADCPin<Adc = ADC>,
+{
+    /// Calibrate with a buffer of known conductivity at 25°C
+    fn calibrate(&mut self, buffer_ms: f32) -> HydroResult<()> {
+        let temp = self.get_water_temperature();
+        self.probe.calibrate(buffer_ms * temperature_factor(temp))
+    }
+
+    fn clear_calibration(&mut self) -> HydroResult<()> {
+        self.probe.clear_calibration()
+    }
+}
+
+impl<T: ADCPin, ADC: Adc> Sensor for EcProbe<'_, T, ADC>
+where
+    T: ADCPin<Adc = ADC>,
+{
+    type Error = HydroError;
+    type Status = EcStatus;
+
+    fn get_unit(&self) -> &str {
+        "mS/cm"
+    }
+
+    fn get_name(&self) -> &str {
+        "EC"
+    }
+
+    fn get_measurment(&mut self) -> Result<f32, Self::Error> {
+        self.get_ec()
+    }
+
+    fn get_status(&mut self) -> Result<Self::Status, Self::Error> {
+        Ok(self.range.status(self.get_ec()?).into())
+    }
+}
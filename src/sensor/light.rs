@@ -0,0 +1,95 @@
+use super::{adc::*, *};
+use esp_idf_hal::{
+    adc::{attenuation, Adc, AdcChannelDriver},
+    gpio::ADCPin,
+    peripheral::Peripheral,
+    sys::adc_atten_t,
+};
+use esp_idf_sys::EspError;
+use termo_core::sensor::status::LightStatus;
+
+/// Number of adc reads averaged for one measurement
+const SAMPLES: u32 = 10;
+/// TEMT6000 breakout: 0.5 µA/lx over the 10 kΩ load resistor
+const LUX_PER_MILLIVOLT: f32 = 0.2;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LightError {
+    #[error("Sensor not connected")]
+    SensorNotConnected(),
+    #[error("EspError internal error")]
+    EspError(#[from] EspError),
+}
+type LightResult<T> = Result<T, LightError>;
+
+/// Analog ambient light sensor ( TEMT6000 phototransistor )
+pub struct AmbientLight<'d, T: ADCPin, ADC: Adc, const A: adc_atten_t = { attenuation::DB_11 }> {
+    adc_driver: SharedAdc<'d, ADC>,
+    adc_pin: AdcChannelDriver<'d, A, T>,
+    lux_per_millivolt: f32,
+}
+
+impl<'d, T: ADCPin, ADC: Adc> AmbientLight<'d, T, ADC>
+where
+    T: ADCPin<Adc = ADC>,
+{
+    /// adc -> adc driver shared with other analog sensors on the same unit
+    /// pin -> gpio from peripherals pins that is connected
+    pub fn new_shared(
+        adc: SharedAdc<'d, ADC>,
+        pin: impl Peripheral<P = T> + 'd,
+    ) -> LightResult<Self> {
+        Ok(AmbientLight {
+            adc_driver: adc,
+            adc_pin: AdcChannelDriver::new(pin)?,
+            lux_per_millivolt: LUX_PER_MILLIVOLT,
+        })
+    }
+
+    /// Sensors with an other load resistor have a different slope
+    pub fn with_lux_per_millivolt(mut self, lux_per_millivolt: f32) -> Self {
+        self.lux_per_millivolt = lux_per_millivolt;
+        self
+    }
+
+    /// Averaged read in mV
+    pub fn get_millivolts(&mut self) -> LightResult<f32> {
+        let mut adc = self
+            .adc_driver
+            .lock()
+            .or(Err(LightError::SensorNotConnected()))?;
+        let sum = (0..SAMPLES)
+            .map(|_| adc.read(&mut self.adc_pin).map(u32::from))
+            .sum::<Result<u32, EspError>>()?;
+        Ok(sum as f32 / SAMPLES as f32)
+    }
+
+    pub fn get_lux(&mut self) -> LightResult<f32> {
+        Ok(self.get_millivolts()? * self.lux_per_millivolt)
+    }
+}
+
+impl<T: ADCPin, ADC: Adc> Sensor for AmbientLight<'_, T, ADC>
+where
+    T: ADCPin<Adc = ADC>,
+{
+    type Error = LightError;
+
+    type Status = LightStatus;
+
+    fn get_unit(&self) -> &str {
+        "lx"
+    }
+
+    fn get_name(&self) -> &str {
+        "light"
+    }
+
+    fn get_measurment(&mut self) -> Result<f32, Self::Error> {
+        self.get_lux()
+    }
+
+    fn get_status(&mut self) -> Result<Self::Status, Self::Error> {
+        Ok(LightStatus::from_lux(self.get_lux()?))
+    }
+}
@@ -1,36 +1,25 @@
-use super::*;
+use super::{adc::*, *};
 use esp_idf_hal::{
-    adc::{attenuation, config::Config, Adc, AdcChannelDriver, AdcDriver},
-    gpio::ADCPin,
+    adc::{attenuation, Adc, AdcChannelDriver},
+    gpio::{ADCPin, AnyOutputPin, Output, PinDriver},
     peripheral::Peripheral,
     sys::adc_atten_t,
 };
 use esp_idf_sys::EspError;
-const MAX_DRY: u16 = 2800;
-const MAX_WET: u16 = 1300;
-
-const MOISTURE_RANGE: u16 = MAX_DRY - MAX_WET;
-const FULL_PRECENTAGE: f32 = 100.0;
-const NO_PRECENTAGE: f32 = 0.0;
-
-#[derive(Clone, PartialEq)]
-pub enum SoilStatus {
-    Dry,
-    Optimal,
-    Damp,
-    Wet,
-}
+use log::warn;
+use std::{cell::RefCell, rc::Rc, time::Duration};
+use termo_core::sensor::{
+    health::Monitored,
+    status::{moisture_precentage, SoilStatus},
+};
+
+use crate::trigger::timer::{get_timer, safe_sleep};
+
+/// Number of adc reads averaged for one measurement
+const SAMPLES: u16 = 10;
+/// How often a switched probe is powered up and sampled
+pub const SAMPLE_PERIOD: Duration = Duration::from_secs(60);
 
-impl std::fmt::Display for SoilStatus {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            SoilStatus::Dry => write!(f, "Dry🔥"),
-            SoilStatus::Optimal => write!(f, "Optimal 💚"),
-            SoilStatus::Damp => write!(f, "Damp ⚠️"),
-            SoilStatus::Wet => write!(f, "Wet 💦"),
-        }
-    }
-}
 #[derive(Debug, thiserror::Error)]
 pub enum MoistureError {
     #[error("Sensor not connected")]
@@ -41,8 +30,12 @@ pub enum MoistureError {
 type MoistureResult<T> = Result<T, MoistureError>;
 
 pub struct SoilMoisture<'d, T: ADCPin, ADC: Adc, const A: adc_atten_t = { attenuation::DB_11 }> {
-    adc_driver: AdcDriver<'d, ADC>,
+    adc_driver: SharedAdc<'d, ADC>,
     adc_pin: AdcChannelDriver<'d, A, T>,
+    power: Option<PinDriver<'d, AnyOutputPin, Output>>,
+    settle_time: Duration,
+    /// Last mean of a switched probe, it is only read by [`sample_soil`]
+    sample: Option<u16>,
 }
 
 impl<'d, T: ADCPin, ADC: Adc> SoilMoisture<'d, T, ADC>
@@ -55,54 +48,102 @@ where
         adc: impl Peripheral<P = ADC> + 'd,
         pin: impl Peripheral<P = T> + 'd,
     ) -> MoistureResult<Self> {
-        let adc = AdcDriver::new(adc, &Config::new().calibration(true))?;
+        Self::new_shared(new_shared_adc(adc)?, pin)
+    }
+
+    /// adc -> adc driver shared with other analog sensors on the same unit
+    /// pin -> gpio from peripherals pins that is connected
+    pub fn new_shared(
+        adc: SharedAdc<'d, ADC>,
+        pin: impl Peripheral<P = T> + 'd,
+    ) -> MoistureResult<Self> {
         let adc_pin: AdcChannelDriver<'_, { attenuation::DB_11 }, T> = AdcChannelDriver::new(pin)?;
         Ok(SoilMoisture {
             adc_driver: adc,
             adc_pin,
+            power: None,
+            settle_time: Duration::ZERO,
+            sample: None,
         })
     }
 
+    /// Switch the probe supply with a gpio, so it is only energized while it is read.\
+    /// Keeps resistive probes from corroding and saves power between samples.
+    ///
+    /// pin -> gpio driving the probe supply ( high = powered )
+    /// settle_time -> time the probe needs after power up before the reading is stable
+    pub fn with_power_pin(
+        mut self,
+        pin: AnyOutputPin,
+        settle_time: Duration,
+    ) -> MoistureResult<Self> {
+        let mut power = PinDriver::output(pin)?;
+        power.set_low()?;
+        self.power = Some(power);
+        self.settle_time = settle_time;
+        Ok(self)
+    }
+
     /// Get the raw read of the moisture result, analog read
-    pub fn get_raw_moisture(&mut self) -> MoistureResult<u16> {
-        Ok(self.adc_driver.read(&mut self.adc_pin)?)
+    fn get_raw_moisture(&mut self) -> MoistureResult<u16> {
+        Ok(self
+            .adc_driver
+            .lock()
+            .or(Err(MoistureError::SensorNotConnected()))?
+            .read(&mut self.adc_pin)?)
     }
 
-    /// Get precentage read of the moisture.
-    pub fn get_moisture_precentage(&mut self) -> MoistureResult<f32> {
-        let mean: u16 = (0..10)
+    /// The probe supply is switched, its reads go through [`sample_soil`]
+    pub fn is_switched(&self) -> bool {
+        self.power.is_some()
+    }
+
+    /// Power the probe, returns the time to wait before [`Self::sample`]
+    fn power_up(&mut self) -> MoistureResult<Duration> {
+        if let Some(power) = self.power.as_mut() {
+            power.set_high()?;
+        }
+        Ok(self.settle_time)
+    }
+
+    fn power_down(&mut self) -> MoistureResult<()> {
+        if let Some(power) = self.power.as_mut() {
+            power.set_low()?;
+        }
+        Ok(())
+    }
+
+    /// Averaged raw read of the powered probe, the supply is switched off afterwards even when
+    /// the read fails
+    fn sample(&mut self) -> MoistureResult<u16> {
+        let mean = (0..SAMPLES)
             .map(|_| self.get_raw_moisture())
-            .sum::<MoistureResult<u16>>()?
-            / 10;
-        let measurement = match mean {
-            msmnt if msmnt < 1000 => Err(MoistureError::SensorNotConnected()),
-            msmnt => Ok(msmnt),
-        }?;
-
-        if measurement > MAX_DRY {
-            return Ok(NO_PRECENTAGE);
-        } else if measurement < MAX_WET {
-            return Ok(FULL_PRECENTAGE);
+            .sum::<MoistureResult<u16>>()
+            .map(|sum| sum / SAMPLES);
+        self.power_down()?;
+        self.sample = mean.as_ref().ok().copied();
+        mean
+    }
+
+    /// Averaged raw read, a switched probe gives its last sample
+    fn get_mean_moisture(&mut self) -> MoistureResult<u16> {
+        if self.is_switched() {
+            self.sample.ok_or(MoistureError::SensorNotConnected())
+        } else {
+            self.sample()
         }
+    }
 
-        let value_diff = MAX_DRY - measurement;
-        Ok((value_diff as f32 / MOISTURE_RANGE as f32) * FULL_PRECENTAGE)
+    /// Get precentage read of the moisture.
+    pub fn get_moisture_precentage(&mut self) -> MoistureResult<f32> {
+        let mean = self.get_mean_moisture()?;
+        moisture_precentage(mean).ok_or(MoistureError::SensorNotConnected())
     }
 
-    /// Get the status of the soil
-    /// Dry -> 0-20%
-    /// Optimal -> 20-40%
-    /// Da -> 40-55%
-    /// Wet -> 55-100%
+    /// Get the status of the soil, see [`SoilStatus::from_precentage`]
     pub fn get_soil_status(&mut self) -> Option<SoilStatus> {
         let percentage = self.get_moisture_precentage().ok()?;
-
-        match percentage {
-            p if p < 20.0 => Some(SoilStatus::Dry),
-            p if p < 40.0 => Some(SoilStatus::Optimal),
-            p if p < 55.0 => Some(SoilStatus::Damp),
-            _ => Some(SoilStatus::Wet),
-        }
+        Some(SoilStatus::from_precentage(percentage))
     }
 }
 
@@ -131,3 +172,38 @@ where
             .ok_or(MoistureError::SensorNotConnected())
     }
 }
+
+/// Power the probe, wait for it to settle and take a sample, the probe is powered down on every
+/// path.\
+/// The sensor isn't borrowed during the wait, so the other tasks keep reading the last sample.
+pub async fn sample_soil<T: ADCPin<Adc = ADC>, ADC: Adc>(
+    soil: &RefCell<Monitored<SoilMoisture<'_, T, ADC>>>,
+) -> MoistureResult<u16> {
+    let powered = soil.borrow_mut().inner().power_up();
+    let settle_time = match powered {
+        Ok(settle_time) => settle_time,
+        Err(err) => {
+            soil.borrow_mut().inner().power_down().ok();
+            return Err(err);
+        }
+    };
+    safe_sleep(settle_time).await;
+    soil.borrow_mut().inner().sample()
+}
+
+/// Sample a switched probe every [`SAMPLE_PERIOD`], an always powered one is read directly
+pub async fn soil_task<T: ADCPin<Adc = ADC>, ADC: Adc>(
+    soil: Rc<RefCell<Monitored<SoilMoisture<'_, T, ADC>>>>,
+) -> MoistureResult<()> {
+    if !soil.borrow_mut().inner().is_switched() {
+        return Ok(());
+    }
+    let timer_service = get_timer()?;
+    let mut timer = timer_service.timer()?;
+    loop {
+        if let Err(err) = sample_soil(&soil).await {
+            warn!("Soil sample failed: {err}");
+        }
+        timer.after(SAMPLE_PERIOD).await?;
+    }
+}
@@ -0,0 +1,38 @@
+use std::time::Instant;
+
+use log::info;
+
+use super::{
+    timer::{get_timer, TimerError},
+    EventBus, Trigger,
+};
+
+/// Poll every trigger at its interval and publish the events it raises on the `bus`
+pub async fn trigger_task(
+    mut triggers: Vec<Box<dyn Trigger + '_>>,
+    mut bus: EventBus<'_>,
+) -> Result<(), TimerError> {
+    let timer_service = get_timer()?;
+    let mut timer = timer_service.timer()?;
+    let mut next_polls = vec![Instant::now(); triggers.len()];
+
+    loop {
+        let now = Instant::now();
+        for (trigger, next_poll) in triggers.iter_mut().zip(next_polls.iter_mut()) {
+            if now < *next_poll {
+                continue;
+            }
+            *next_poll = now + trigger.interval();
+            if let Some(event) = trigger.poll(now) {
+                info!("Event: {event:?}");
+                bus.publish(&event);
+            }
+        }
+        let Some(next_poll) = next_polls.iter().min() else {
+            return Ok(());
+        };
+        timer
+            .after(next_poll.saturating_duration_since(Instant::now()))
+            .await?;
+    }
+}
@@ -1,8 +1,6 @@
-pub mod timer;
+pub use termo_core::trigger::{EventBus, Trigger};
 
-trait Trigger {
-    fn handle_event(
-        &self,
-        // event_handler: impl Fn(Result<MqttCommand, CommandError>) + Send + 'static,
-    );
-}
+pub mod events;
+pub mod photoperiod;
+pub mod rules;
+pub mod timer;
@@ -0,0 +1,52 @@
+use std::rc::Rc;
+
+use chrono::Local;
+use log::info;
+use termo_core::control::photoperiod::{LightDay, Photoperiod, PhotoperiodController};
+
+use super::timer::{get_timer, wait_for_time_sync, TimerError};
+use crate::actuator::lamp::Lamp;
+
+/// Switch the lamp along the photoperiod with sunrise and sunset ramps.\
+/// After a reboot the lamp is restored to the state of the schedule right away, and the light day
+/// carries on from `saved`. `lux` reads the light sensor for the DLI target, `journal` gets the
+/// light day after every update.
+pub async fn photoperiod_task(
+    lamp: Rc<Lamp<'_>>,
+    period: Photoperiod,
+    saved: Option<LightDay>,
+    mut lux: impl FnMut() -> Option<f32>,
+    mut journal: impl FnMut(LightDay),
+) -> Result<(), TimerError> {
+    wait_for_time_sync().await;
+    let level = period.level;
+    let mut controller = PhotoperiodController::new(period, saved);
+    let timer_service = get_timer()?;
+    let mut timer = timer_service.timer()?;
+    let mut lamp_on = None;
+
+    loop {
+        let step = controller.update(Local::now().naive_local(), lux());
+        journal(controller.light_day());
+        match (lamp_on, step.on) {
+            (None, on) => {
+                info!(
+                    "Photoperiod restored, lamp {}",
+                    if on { "on" } else { "off" }
+                );
+                lamp.set(if on { level } else { 0 });
+            }
+            (Some(false), true) => lamp.sunrise(level),
+            (Some(true), false) => lamp.sunset(),
+            _ => {}
+        }
+        if step.extended {
+            info!(
+                "Dull day, lamp extended at {:.1} mol/m² DLI",
+                controller.dli()
+            );
+        }
+        lamp_on = Some(step.on);
+        timer.after(step.next_check).await?;
+    }
+}
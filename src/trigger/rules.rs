@@ -0,0 +1,38 @@
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use chrono::NaiveDateTime;
+use log::info;
+use termo_core::rules::{Action, Facts};
+
+use super::timer::{get_timer, synced_now, wait_for_time_sync, TimerError};
+use crate::utils::rules::RuleStore;
+
+/// Time between two evaluations of the rules
+const RULES_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Evaluate the `rules` every minute on the `facts` gathered at that time, `execute` gets the
+/// name of each rule that fires and its action.\
+/// The rules look at the time of day, so they wait for the clock to be synced.
+pub async fn rules_task(
+    rules: Rc<RefCell<RuleStore>>,
+    mut facts: impl FnMut(NaiveDateTime) -> Facts,
+    mut execute: impl FnMut(&str, Action),
+) -> Result<(), TimerError> {
+    wait_for_time_sync().await;
+    let timer_service = get_timer()?;
+    let mut timer = timer_service.timer()?;
+
+    loop {
+        if let Some(now) = synced_now() {
+            if !rules.borrow().rules().is_empty() {
+                let facts = facts(now);
+                let actions = rules.borrow_mut().evaluate(&facts);
+                for (rule, action) in actions {
+                    info!("Rule {rule}: {action:?}");
+                    execute(&rule, action);
+                }
+            }
+        }
+        timer.after(RULES_INTERVAL).await?;
+    }
+}
@@ -1,40 +1,85 @@
-use chrono::{Local, NaiveTime, Timelike};
+use chrono::{Local, NaiveDateTime, Timelike, Utc};
 use embedded_svc::utils::asyncify::timer::AsyncTimerService;
 use embedded_svc::utils::asyncify::Asyncify;
-use esp_idf_hal::task::asynch::Notification;
-use esp_idf_svc::sntp::{self};
+use esp_idf_svc::sntp::{EspSntp, SntpConf};
 use esp_idf_svc::timer::EspTimerService;
 use esp_idf_sys::EspError;
-use log::info;
-use std::time::Duration;
+use log::{info, warn};
+use std::{cell::RefCell, future::Future, pin::Pin, rc::Rc, sync::Mutex, time::Duration};
+use termo_core::{
+    clock::{Clock, Timer},
+    schedule::{run_jobs, Scheduler},
+    time_sync::TimeSync,
+};
+
+use crate::utils::runs::RunStore;
 
 #[derive(Debug, thiserror::Error)]
 pub enum TimerError {
-    #[error("chrono to std conversion error")]
-    ConversionError,
     #[error("EspError error")]
     TimerError(#[from] EspError),
 }
 
-pub async fn shedule_event(mut callback: impl FnMut()) -> Result<(), TimerError> {
-    update_current_time_async().await;
-    info!("SNTP updated");
-    let remaining = get_duration_until_next(8).ok_or(TimerError::ConversionError)?;
+/// Time the NTP servers have to answer before the other server list is tried
+const SNTP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Interval of the checks for a stalled resync
+const SNTP_RECHECK: Duration = Duration::from_secs(10 * 60);
+/// Age of the last sync the SNTP client is restarted at, lwIP resyncs every hour
+const MAX_SYNC_AGE: chrono::Duration = chrono::Duration::hours(3);
 
-    let timer_service = get_timer()?;
-    let mut timer = timer_service.timer()?;
-    callback();
+/// Sync state of the wall clock, updated by the SNTP callback
+static TIME_SYNC: Mutex<TimeSync> = Mutex::new(TimeSync::new());
 
+/// Run the jobs of the `scheduler`, `callback` gets the name of each job that is due.\
+/// The time to the next run is computed from the wall clock after every wake up, so the
+/// schedule doesn't drift. The run times are written to `runs` for the catch up after a restart.
+pub async fn scheduler_task(
+    scheduler: Rc<RefCell<Scheduler>>,
+    mut runs: RunStore,
+    mut callback: impl FnMut(&str),
+) -> Result<(), TimerError> {
+    wait_for_time_sync().await;
     showtime();
-    timer.after(remaining).await?;
-    callback();
 
-    let stream = timer.every(chrono::Duration::days(1).to_std().expect("Can't fail"))?;
+    run_jobs(&scheduler, &EspClock, &mut EspTimer::new()?, |due| {
+        runs.write(scheduler.borrow().last_runs());
+        for job in due {
+            info!("Running job {job}");
+            callback(job);
+        }
+    })
+    .await?;
+    Ok(())
+}
+
+/// Wall clock of the ESP in local time, set by SNTP
+pub struct EspClock;
 
-    loop {
-        info!("Waiting for timer");
-        stream.tick().await;
-        callback();
+impl Clock for EspClock {
+    fn now(&self) -> Option<NaiveDateTime> {
+        synced_now()
+    }
+}
+
+/// Timer on the ESP timer service, each wait takes a one shot timer of the service
+pub struct EspTimer {
+    service: AsyncTimerService<EspTimerService<esp_idf_svc::timer::Task>>,
+}
+
+impl EspTimer {
+    pub fn new() -> Result<Self, EspError> {
+        Ok(Self {
+            service: get_timer()?,
+        })
+    }
+}
+
+impl Timer for EspTimer {
+    type Error = EspError;
+    type Sleep<'a> = Pin<Box<dyn Future<Output = Result<(), EspError>> + 'a>>;
+
+    fn after(&mut self, duration: Duration) -> Self::Sleep<'_> {
+        Box::pin(async move { self.service.timer()?.after(duration).await })
     }
 }
 
@@ -46,13 +91,36 @@ pub fn get_timer() -> Result<AsyncTimerService<EspTimerService<esp_idf_svc::time
 }
 
 pub async fn safe_sleep(duration: Duration) {
-    if let Ok(ts) = get_timer() {
-        if let Ok(mut sleep) = ts.timer() {
-            sleep.after(duration).await.ok();
-        }
+    if let Ok(mut timer) = EspTimer::new() {
+        timer.after(duration).await.ok();
+    }
+}
+
+/// Sync state of the wall clock for the diagnostics
+pub fn time_sync() -> TimeSync {
+    TIME_SYNC
+        .lock()
+        .map(|sync| sync.clone())
+        .unwrap_or_default()
+}
+
+/// The wall clock was set by SNTP, it starts in 1970 after boot
+pub fn time_is_valid() -> bool {
+    time_sync().is_valid()
+}
+
+/// Wait until the wall clock is set by SNTP
+pub async fn wait_for_time_sync() {
+    while EspClock.now().is_none() {
+        safe_sleep(Duration::from_secs(1)).await;
     }
 }
 
+/// Wall clock time, `None` until the clock is synced
+pub fn synced_now() -> Option<NaiveDateTime> {
+    time_is_valid().then(|| Local::now().naive_local())
+}
+
 pub fn showtime() {
     let now = Local::now();
 
@@ -66,33 +134,49 @@ pub fn showtime() {
     );
 }
 
-pub fn get_duration_until_next(hour: u32) -> Option<Duration> {
-    use chrono::Duration;
-    let current_time = Local::now().time();
-    let target_time = NaiveTime::from_hms_opt(hour, 0, 0)?;
-
-    let elapsed = if current_time <= target_time {
-        // If current time is before 8 AM, calculate duration until 8 AM of the same day
-        target_time - current_time
-    } else {
-        // If current time is after 8 AM, calculate duration until 8 AM of the next day
-        Duration::days(1) - (current_time - target_time)
-    };
-    info!(
-        "Time until next 8 AM:{}:{}:{} ",
-        elapsed.num_hours(),
-        elapsed.num_minutes() % 60,
-        elapsed.num_seconds() % 60,
-    );
-    elapsed.to_std().ok()
-}
-
-async fn update_current_time_async() {
-    let notification = Notification::new();
+/// Keep the wall clock synced, the configured `servers` are asked first and the default pool
+/// when they don't answer in time.\
+/// The client stays alive so lwIP resyncs periodically, it is restarted once the resyncs stop.
+pub async fn sntp_task(servers: Vec<String>) -> Result<(), TimerError> {
+    let timer_service = get_timer()?;
+    let mut timer = timer_service.timer()?;
+    let mut fallback = servers.is_empty();
 
-    let _sntp = sntp::EspSntp::new_with_callback(&Default::default(), |_now| {
-        notification.notify_lsb();
-    });
+    loop {
+        let syncs = time_sync().syncs;
+        let _sntp = start_sntp(&servers, fallback)?;
+        timer.after(SNTP_TIMEOUT).await?;
+        if time_sync().syncs == syncs {
+            warn!(
+                "No answer from the {} NTP servers",
+                if fallback { "fallback" } else { "configured" }
+            );
+            fallback = servers.is_empty() || !fallback;
+            continue;
+        }
+        loop {
+            timer.after(SNTP_RECHECK).await?;
+            if time_sync().is_stale(Utc::now().naive_utc(), MAX_SYNC_AGE) {
+                warn!("The clock wasn't resynced for too long, restarting SNTP");
+                break;
+            }
+        }
+        fallback = servers.is_empty();
+    }
+}
 
-    notification.wait().await;
+fn start_sntp(servers: &[String], fallback: bool) -> Result<EspSntp, EspError> {
+    let mut conf = SntpConf::default();
+    // The slots past the configured servers keep the default pool
+    if !fallback {
+        for (slot, server) in conf.servers.iter_mut().zip(servers) {
+            *slot = server;
+        }
+    }
+    info!("SNTP servers: {:?}", conf.servers);
+    EspSntp::new_with_callback(&conf, move |_since_epoch| {
+        if let Ok(mut sync) = TIME_SYNC.lock() {
+            sync.synced(Utc::now().naive_utc(), fallback);
+        }
+    })
 }
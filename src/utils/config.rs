@@ -0,0 +1,46 @@
+use log::{info, warn};
+use termo_core::{config::DeviceConfig, timezone::TimeZone};
+
+use super::nvs::{NvsStore, StorageError};
+
+/// NVS key of the configuration
+const CONFIG_KEY: &str = "device";
+
+/// Device configuration kept in the NVS
+pub struct ConfigStore {
+    store: NvsStore,
+    config: DeviceConfig,
+}
+
+impl ConfigStore {
+    /// Load the stored configuration, the defaults if there is none
+    pub fn open(store: NvsStore) -> Self {
+        let config: Option<DeviceConfig> = store.load(CONFIG_KEY).unwrap_or_else(|err| {
+            warn!("Could not load the device configuration: {:?}", err);
+            None
+        });
+        Self {
+            store,
+            config: config.unwrap_or_default(),
+        }
+    }
+
+    pub fn config(&self) -> &DeviceConfig {
+        &self.config
+    }
+
+    /// Store the new configuration and apply the timezone
+    pub fn update(&mut self, config: DeviceConfig) -> Result<(), StorageError> {
+        self.store.store(CONFIG_KEY, &config)?;
+        apply_timezone(&config.timezone);
+        self.config = config;
+        Ok(())
+    }
+}
+
+/// Set the timezone of the C library, `chrono::Local` and the schedules follow it
+pub fn apply_timezone(timezone: &TimeZone) {
+    std::env::set_var("TZ", timezone.to_string());
+    unsafe { esp_idf_sys::tzset() };
+    info!("Timezone set to {timezone}");
+}
@@ -1,82 +1,42 @@
-pub mod discord {
-    use esp_idf_hal::{adc::Adc, gpio::ADCPin};
-
-    use crate::sensor::{
-        bme280::{Bme280HumiditySensor, Bme280TempSensor},
-        soil::SoilMoisture,
-        Sensor,
-    };
-
-    pub fn get_message<T: ADCPin, ADC: Adc>(
-        soil_sensor: &mut SoilMoisture<'_, T, ADC>,
-        hum_sensor: &mut Bme280HumiditySensor,
-        temp_sensor: &mut Bme280TempSensor,
-    ) -> String
-    where
-        T: ADCPin<Adc = ADC>,
-    {
-        fn printer<S: Sensor>(s: &mut S) -> String {
-            match s.get_measurment() {
-                Ok(value) => format!("{:.1}", value),
-                Err(_) => "Sensor not connected".to_string(),
-            }
-        }
-        let status = match soil_sensor.get_status() {
-            Ok(status) => status.to_string(),
-            Err(_) => "Sensor not connected".to_string(),
-        };
-        let soil = printer(soil_sensor);
-        let hum = printer(hum_sensor);
-        let temp = printer(temp_sensor);
-
-        format!(
-            r#"
-                        Good morning! :sun_with_face:
-                        Here is the daily report:
-                        > Soil moisture: {soil}{}
-                        > Soil moisture status: **{status}**
-                        > Temperature: **{temp}{}**
-                        > Humidity: **{hum}{}**
-                        "#,
-            soil_sensor.get_unit(),
-            temp_sensor.get_unit(),
-            hum_sensor.get_unit()
-        )
-        .replace('\n', r"\n")
-        .replace("  ", "")
-    }
-}
-
 pub mod mqtt {
     use esp_idf_svc::eventloop::EspBackgroundEventLoop;
     use log::{error, info};
 
-    use crate::relay::mqtt::{new_mqqt_client, Command, SimplCommandError, SimpleMqttClient};
+    use termo_core::command::Command;
+
+    use crate::relay::mqtt::{new_mqqt_client, CommandEvent, SimplCommandError, SimpleMqttClient};
 
     fn setup_mqtt() -> Result<(), anyhow::Error> {
         todo!();
         let mut event_loop = EspBackgroundEventLoop::new(&Default::default())?;
         let cmd_loop = event_loop.clone();
-        let mqqt_service = new_mqqt_client(move |msg| {
-            let _ = match msg {
-                Ok(cmd) => cmd_loop.post(&cmd, None),
-                Err(err) => cmd_loop.post::<SimplCommandError>(&err.into(), None),
-            }
-            .map_err(|err| {
-                error!("Error posting: {:?}", err);
-                err
-            });
-        })?;
+        let mqqt_service = new_mqqt_client(
+            move |msg| {
+                let _ = match msg {
+                    Ok(cmd) => cmd_loop.post(&CommandEvent(cmd), None),
+                    Err(err) => cmd_loop.post::<SimplCommandError>(&err.into(), None),
+                }
+                .map_err(|err| {
+                    error!("Error posting: {:?}", err);
+                    err
+                });
+            },
+            |_update| info!("Configuration and rules updates are not handled by the event loop"),
+        )?;
         use std::sync::{Arc, Mutex};
         let mqtt_client = Arc::new(Mutex::new(mqqt_service));
         let mqtt_err = mqtt_client.clone();
         info!("Ready to broadcast ...");
         info!("Setup background event loop");
-        let _subscription = event_loop.subscribe(move |message: &Command| {
+        let _subscription = event_loop.subscribe(move |CommandEvent(message): &CommandEvent| {
             info!("Got message from the event loop: {:?}", message);
             match message {
-                Command::Water(on_off) => info!("Turn on water: {on_off}"),
-                Command::Lamp(percent) => info!("Set lamp dim to: {percent}"),
+                Command::Water { zone, on, .. } => info!("Turn on water in zone {zone}: {on}"),
+                Command::CalibrateFlow(zone) => info!("Calibrate flow of zone {zone}"),
+                Command::FlowMeasured { zone, volume } => {
+                    info!("Zone {zone} calibration volume: {volume}ml")
+                }
+                Command::Lamp { level, .. } => info!("Set lamp dim to: {level}"),
                 Command::ReadSoilMoisture => {
                     if let Ok(mut mqtt) = mqtt_client.lock() {
                         match Some("soil".to_string()) {
@@ -106,6 +66,11 @@ pub mod mqtt {
                 Command::AllSemorData => {
                     todo!("implement all sensor data")
                 }
+                Command::AckAlert => info!("Acknowledge alert"),
+                Command::Refill => info!("Nutrient bottle refilled"),
+                Command::Stop => info!("Stop manual overrides"),
+                Command::TunePid { target, gains } => info!("Tune {target:?} PID: {gains:?}"),
+                Command::Job { job, enabled } => info!("Job {job} enabled: {enabled}"),
             }
         });
         let _error_sub = event_loop.subscribe(move |err: &SimplCommandError| {
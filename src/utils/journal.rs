@@ -0,0 +1,67 @@
+use log::warn;
+use termo_core::journal::{Journal, WateringRecord};
+
+use super::nvs::NvsStore;
+
+/// NVS key of the journal
+const JOURNAL_KEY: &str = "actuators";
+
+/// Actuator journal kept in the NVS, every change is written right away
+pub struct JournalStore {
+    store: NvsStore,
+    journal: Journal,
+}
+
+impl JournalStore {
+    /// Load the journal of the last run, the watering it interrupted is taken out and returned
+    pub fn open(store: NvsStore) -> (Self, Option<WateringRecord>) {
+        let journal: Option<Journal> = store.load(JOURNAL_KEY).unwrap_or_else(|err| {
+            warn!("Could not load the actuator journal: {:?}", err);
+            None
+        });
+        let mut journal = Self {
+            store,
+            journal: journal.unwrap_or_default(),
+        };
+        let interrupted = journal.journal.take_interrupted();
+        if interrupted.is_some() {
+            journal.write();
+        }
+        (journal, interrupted)
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    /// Apply `change` to the journal, it is written if `change` returns true
+    pub fn record(&mut self, change: impl FnOnce(&mut Journal) -> bool) {
+        if change(&mut self.journal) {
+            self.write();
+        }
+    }
+
+    fn write(&mut self) {
+        if let Err(err) = self.store.store(JOURNAL_KEY, &self.journal) {
+            warn!("Could not write the actuator journal: {:?}", err);
+        }
+    }
+}
+
+/// Reason of the last reset for the reports
+pub fn reset_reason() -> &'static str {
+    use esp_idf_sys::*;
+
+    #[allow(non_upper_case_globals)]
+    match unsafe { esp_reset_reason() } {
+        esp_reset_reason_t_ESP_RST_POWERON => "power on",
+        esp_reset_reason_t_ESP_RST_SW => "restart",
+        esp_reset_reason_t_ESP_RST_PANIC => "crash",
+        esp_reset_reason_t_ESP_RST_INT_WDT
+        | esp_reset_reason_t_ESP_RST_TASK_WDT
+        | esp_reset_reason_t_ESP_RST_WDT => "watchdog",
+        esp_reset_reason_t_ESP_RST_BROWNOUT => "brownout",
+        esp_reset_reason_t_ESP_RST_DEEPSLEEP => "deep sleep wake up",
+        _ => "unknown reset",
+    }
+}
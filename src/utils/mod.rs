@@ -1,3 +1,8 @@
+pub mod config;
 pub mod helper;
+pub mod journal;
+pub mod nvs;
 pub mod power;
+pub mod rules;
+pub mod runs;
 pub mod wifi;
@@ -0,0 +1,59 @@
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_sys::EspError;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Largest blob that is read back from the NVS, bigger entries are truncated by the driver
+const MAX_BLOB_SIZE: usize = 4000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("EspError internal error")]
+    EspError(#[from] EspError),
+    #[error("Stored value is not valid JSON")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Value of {0} bytes is too large to be read back")]
+    TooLarge(usize),
+}
+type StorageResult<T> = Result<T, StorageError>;
+
+/// Key value store on top of an NVS namespace.\
+/// Values are kept as JSON blobs so any serde type can be persisted.
+///
+/// **Note**: NVS keys are limited to 15 characters.
+pub struct NvsStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl NvsStore {
+    /// partition -> the default nvs partition, it can be cloned for every store
+    /// namespace -> the nvs namespace the keys of this store live in
+    pub fn new(partition: EspDefaultNvsPartition, namespace: &str) -> StorageResult<Self> {
+        let nvs = EspNvs::new(partition, namespace, true)?;
+        Ok(Self { nvs })
+    }
+
+    /// Load and deserialize the value stored under `key`, `None` if the key is missing
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> StorageResult<Option<T>> {
+        let mut buf = vec![0_u8; MAX_BLOB_SIZE];
+        match self.nvs.get_raw(key, &mut buf)? {
+            Some(data) => Ok(Some(serde_json::from_slice(data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Serialize and store the value under `key`, it has to fit in [`MAX_BLOB_SIZE`]
+    pub fn store<T: Serialize>(&mut self, key: &str, value: &T) -> StorageResult<()> {
+        let data = serde_json::to_vec(value)?;
+        if data.len() > MAX_BLOB_SIZE {
+            return Err(StorageError::TooLarge(data.len()));
+        }
+        self.nvs.set_raw(key, &data)?;
+        Ok(())
+    }
+
+    /// Remove the value stored under `key`
+    pub fn remove(&mut self, key: &str) -> StorageResult<()> {
+        self.nvs.remove(key)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+use log::warn;
+use termo_core::rules::{Action, Facts, Rule, RuleEngine, Runs};
+
+use super::nvs::{NvsStore, StorageError};
+
+/// NVS key of the rules
+const RULES_KEY: &str = "rules";
+/// NVS key of the runs of the rules
+const RUNS_KEY: &str = "runs";
+
+/// Automation rules kept in the NVS, with their runs for the daily limits and the cooldowns
+pub struct RuleStore {
+    store: NvsStore,
+    engine: RuleEngine,
+}
+
+impl RuleStore {
+    /// Load the stored rules and their runs, no rules if there are no valid ones
+    pub fn open(store: NvsStore) -> Self {
+        let rules: Option<Vec<Rule>> = store.load(RULES_KEY).unwrap_or_else(|err| {
+            warn!("Could not load the rules: {:?}", err);
+            None
+        });
+        let runs: Option<BTreeMap<String, Runs>> = store.load(RUNS_KEY).unwrap_or_else(|err| {
+            warn!("Could not load the runs of the rules: {:?}", err);
+            None
+        });
+        Self {
+            store,
+            engine: RuleEngine::new(rules.unwrap_or_default(), runs.unwrap_or_default()),
+        }
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        self.engine.rules()
+    }
+
+    /// Store the new rules and replace the running ones
+    pub fn update(&mut self, rules: Vec<Rule>) -> Result<(), StorageError> {
+        self.store.store(RULES_KEY, &rules)?;
+        self.engine.set_rules(rules);
+        Ok(())
+    }
+
+    /// Actions of the rules that fire on the `facts`, the runs are written when a rule fired
+    pub fn evaluate(&mut self, facts: &Facts) -> Vec<(String, Action)> {
+        let actions = self.engine.evaluate(facts);
+        if !actions.is_empty() {
+            if let Err(err) = self.store.store(RUNS_KEY, self.engine.runs()) {
+                warn!("Could not store the runs of the rules: {:?}", err);
+            }
+        }
+        actions
+    }
+}
@@ -0,0 +1,32 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDateTime;
+use log::warn;
+
+use super::nvs::NvsStore;
+
+/// NVS key of the last runs
+const RUNS_KEY: &str = "last_runs";
+
+/// Last run of each scheduled job kept in the NVS, so missed runs are caught up after a restart
+pub struct RunStore {
+    store: NvsStore,
+}
+
+impl RunStore {
+    /// Load the last runs of the jobs, none if there are no valid ones
+    pub fn open(store: NvsStore) -> (Self, BTreeMap<String, NaiveDateTime>) {
+        let runs: Option<BTreeMap<String, NaiveDateTime>> =
+            store.load(RUNS_KEY).unwrap_or_else(|err| {
+                warn!("Could not load the last runs of the jobs: {:?}", err);
+                None
+            });
+        (Self { store }, runs.unwrap_or_default())
+    }
+
+    pub fn write(&mut self, runs: &BTreeMap<String, NaiveDateTime>) {
+        if let Err(err) = self.store.store(RUNS_KEY, runs) {
+            warn!("Could not write the last runs of the jobs: {:?}", err);
+        }
+    }
+}
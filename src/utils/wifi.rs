@@ -19,9 +19,9 @@ const PASSWORD: &str = dotenv!("PASSWORD");
 
 async fn connect(
     modem: esp_idf_hal::modem::Modem,
+    nvs: EspDefaultNvsPartition,
 ) -> Result<AsyncWifi<EspWifi<'static>>, EspError> {
     let sys_loop = EspSystemEventLoop::take()?;
-    let nvs = EspDefaultNvsPartition::take()?;
 
     let esp_wifi = EspWifi::new(modem, sys_loop.clone(), Some(nvs))?;
     let timer_service = EspTaskTimerService::new()?;
@@ -63,8 +63,11 @@ pub struct WifiRelay {
 }
 
 impl WifiRelay {
-    pub async fn new(modem: esp_idf_hal::modem::Modem) -> Result<Self, EspError> {
-        let wifi = connect(modem).await?;
+    pub async fn new(
+        modem: esp_idf_hal::modem::Modem,
+        nvs: EspDefaultNvsPartition,
+    ) -> Result<Self, EspError> {
+        let wifi = connect(modem, nvs).await?;
         let (tx, rx) = async_watch::channel(false);
         Ok(Self { wifi, tx, rx })
     }
@@ -0,0 +1,161 @@
+//! Time source and timers of the scheduling tasks.\
+//! The firmware implements them on the ESP wall clock and timer service. [`VirtualClock`] runs
+//! on simulated time, its timers move the time forward instead of waiting, so the tasks go
+//! through days of schedules in a host test.
+
+use std::{
+    cell::Cell,
+    future::{ready, Future, Ready},
+    pin::pin,
+    rc::Rc,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
+};
+
+use chrono::NaiveDateTime;
+
+pub trait Clock {
+    /// Local wall clock time, `None` until the clock is set
+    fn now(&self) -> Option<NaiveDateTime>;
+}
+
+pub trait Timer {
+    type Error;
+    type Sleep<'a>: Future<Output = Result<(), Self::Error>>
+    where
+        Self: 'a;
+
+    /// Resolves after `duration`
+    fn after(&mut self, duration: Duration) -> Self::Sleep<'_>;
+}
+
+/// The simulation went past its end time
+#[derive(Debug, thiserror::Error, PartialEq)]
+#[error("The simulation ended at {0}")]
+pub struct SimulationEnd(pub NaiveDateTime);
+
+/// Simulated wall clock, the clones share the time
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    now: Rc<Cell<Option<NaiveDateTime>>>,
+}
+
+impl VirtualClock {
+    pub fn new(start: NaiveDateTime) -> Self {
+        Self {
+            now: Rc::new(Cell::new(Some(start))),
+        }
+    }
+
+    /// Clock that isn't set yet, like the ESP one before the first SNTP sync
+    pub fn unset() -> Self {
+        Self {
+            now: Rc::new(Cell::new(None)),
+        }
+    }
+
+    pub fn set(&self, time: NaiveDateTime) {
+        self.now.set(Some(time));
+    }
+
+    /// Move the time forward, an unset clock stays unset
+    pub fn advance(&self, duration: Duration) {
+        if let (Some(now), Ok(duration)) = (self.now.get(), chrono::Duration::from_std(duration)) {
+            self.now.set(Some(now + duration));
+        }
+    }
+
+    /// Timer of this clock that fails once the time would go past `end`
+    pub fn timer_until(&self, end: NaiveDateTime) -> VirtualTimer {
+        VirtualTimer {
+            clock: self.clone(),
+            end,
+            waited: Duration::ZERO,
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Option<NaiveDateTime> {
+        self.now.get()
+    }
+}
+
+/// Timer on a [`VirtualClock`], the waits return right away
+#[derive(Debug)]
+pub struct VirtualTimer {
+    clock: VirtualClock,
+    end: NaiveDateTime,
+    /// Total of the waits, the time of an unset clock doesn't move
+    waited: Duration,
+}
+
+impl VirtualTimer {
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+}
+
+impl Timer for VirtualTimer {
+    type Error = SimulationEnd;
+    type Sleep<'a> = Ready<Result<(), SimulationEnd>>;
+
+    fn after(&mut self, duration: Duration) -> Self::Sleep<'_> {
+        self.waited += duration;
+        self.clock.advance(duration);
+        let ended = self.clock.now().map_or(false, |now| now > self.end);
+        ready(if ended {
+            Err(SimulationEnd(self.end))
+        } else {
+            Ok(())
+        })
+    }
+}
+
+struct NoWake;
+
+impl Wake for NoWake {
+    fn wake(self: Arc<Self>) {}
+}
+
+/// Run a future that only waits on virtual timers, it completes on the first poll
+pub fn run_virtual<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(NoWake));
+    match pin!(future).poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("The future waits on something else than a virtual timer"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2023, 10, 2)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn virtual_time() {
+        let clock = VirtualClock::new(at(8, 0));
+        let mut timer = clock.timer_until(at(9, 0));
+        let result = run_virtual(async {
+            timer.after(Duration::from_secs(30 * 60)).await?;
+            assert_eq!(clock.now(), Some(at(8, 30)));
+            timer.after(Duration::from_secs(30 * 60)).await?;
+            timer.after(Duration::from_secs(1)).await
+        });
+        assert_eq!(result, Err(SimulationEnd(at(9, 0))));
+        assert_eq!(timer.waited(), Duration::from_secs(3601));
+
+        let unset = VirtualClock::unset();
+        unset.advance(Duration::from_secs(60));
+        assert_eq!(unset.now(), None);
+        unset.set(at(8, 0));
+        assert_eq!(unset.now(), Some(at(8, 0)));
+    }
+}
@@ -0,0 +1,448 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{str::FromStr, time::Duration};
+
+use crate::control::pid::PidGains;
+
+/// Commands received over MQTT on the `station/cmd` topic
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Command {
+    /// Manual watering of a zone, a plain bool value addresses the first zone.\
+    /// Without an amount the zone is watered for a default time.
+    Water {
+        zone: u8,
+        on: bool,
+        amount: Option<Amount>,
+    },
+    /// Run the pump of a zone for the calibration time
+    CalibrateFlow(u8),
+    /// Volume in ml caught during the calibration run of a zone
+    FlowMeasured {
+        zone: u8,
+        volume: f32,
+    },
+    /// Lamp level override in percent, for a default time without a duration
+    Lamp {
+        level: u8,
+        duration: Option<Duration>,
+    },
+    ReadBarometer,
+    ReadSoilMoisture,
+    AllSemorData,
+    /// Acknowledge a latched pump interlock trip
+    AckAlert,
+    /// A full nutrient bottle was put in
+    Refill,
+    /// End every manual override, the automatic control takes over
+    Stop,
+    /// New gains of a PID loop
+    TunePid {
+        target: PidLoop,
+        gains: PidGains,
+    },
+    /// Enable or disable a scheduled job by its index
+    Job {
+        job: u8,
+        enabled: bool,
+    },
+    /// The pH probe sits in a buffer of this pH
+    #[cfg(feature = "hydro")]
+    CalibratePh(f32),
+    /// The EC probe sits in a buffer of this conductivity in mS/cm at 25°C
+    #[cfg(feature = "hydro")]
+    CalibrateEc(f32),
+    /// Drop the buffer points of a probe
+    #[cfg(feature = "hydro")]
+    ClearCalibration(HydroProbe),
+}
+
+/// Water given by a manual watering
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Amount {
+    /// Pump time
+    Time(Duration),
+    /// Volume in ml, the zone has to be calibrated
+    Volume(f32),
+}
+
+/// Control loops with tunable PID gains
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PidLoop {
+    Heater,
+}
+
+/// Probes of the hydroponic tub
+#[cfg(feature = "hydro")]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum HydroProbe {
+    Ph,
+    Ec,
+}
+
+#[derive(Deserialize)]
+struct TuneJson {
+    #[serde(rename = "loop")]
+    target: PidLoop,
+    #[serde(flatten)]
+    gains: PidGains,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("Command is not recognized")]
+    WrongCommand(CommandJson),
+    #[error("Command Value is invalid: {0}")]
+    InvalidValue(Value),
+    #[error("Command is not valid JSON")]
+    JsonParseError(serde_json::error::Category),
+    #[error("Message is not valid UTF8")]
+    ParseError(#[from] std::str::Utf8Error),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommandJson {
+    name: String,
+    value: Option<serde_json::Value>,
+}
+
+//
+impl FromStr for Command {
+    fn from_str(input: &str) -> Result<Command, CommandError> {
+        let parsed_command = serde_json::from_str::<CommandJson>(input);
+        info!("Got command: {:?}", parsed_command);
+        match parsed_command {
+            Ok(command) => {
+                let error_cmd = command.clone();
+                match command.name.as_str() {
+                    "water" => {
+                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
+                        parse_water(&value).ok_or(CommandError::InvalidValue(value))
+                    }
+                    "calibrate_flow" => {
+                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
+                        let zone = value
+                            .as_u64()
+                            .and_then(|zone| u8::try_from(zone).ok())
+                            .ok_or(CommandError::InvalidValue(value))?;
+                        Ok(Command::CalibrateFlow(zone))
+                    }
+                    "flow" => {
+                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
+                        let zone = value
+                            .get("zone")
+                            .and_then(Value::as_u64)
+                            .and_then(|zone| u8::try_from(zone).ok());
+                        let volume = value.get("ml").and_then(positive);
+                        match (zone, volume) {
+                            (Some(zone), Some(volume)) => {
+                                Ok(Command::FlowMeasured { zone, volume })
+                            }
+                            _ => Err(CommandError::InvalidValue(value)),
+                        }
+                    }
+                    "lamp" => {
+                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
+                        parse_lamp(&value).ok_or(CommandError::InvalidValue(value))
+                    }
+                    "read_barometer" => Ok(Command::ReadBarometer),
+                    "read_soil_moisture" => Ok(Command::ReadSoilMoisture),
+                    "all" => Ok(Command::AllSemorData),
+                    "ack" => Ok(Command::AckAlert),
+                    "refill" => Ok(Command::Refill),
+                    "stop" => Ok(Command::Stop),
+                    "pid" => {
+                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
+                        let TuneJson { target, gains } =
+                            serde_json::from_value::<TuneJson>(value.clone())
+                                .map_err(|_| CommandError::InvalidValue(value.clone()))?;
+                        let valid = [gains.kp, gains.ki, gains.kd]
+                            .iter()
+                            .all(|gain| gain.is_finite() && *gain >= 0.0);
+                        if !valid {
+                            return Err(CommandError::InvalidValue(value));
+                        }
+                        Ok(Command::TunePid { target, gains })
+                    }
+                    "job" => {
+                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
+                        let job = value
+                            .get("job")
+                            .and_then(Value::as_u64)
+                            .and_then(|job| u8::try_from(job).ok());
+                        let enabled = value.get("enabled").and_then(Value::as_bool);
+                        match (job, enabled) {
+                            (Some(job), Some(enabled)) => Ok(Command::Job { job, enabled }),
+                            _ => Err(CommandError::InvalidValue(value)),
+                        }
+                    }
+                    #[cfg(feature = "hydro")]
+                    "calibrate_ph" => {
+                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
+                        let buffer = positive(&value)
+                            .filter(|ph| *ph <= 14.0)
+                            .ok_or(CommandError::InvalidValue(value))?;
+                        Ok(Command::CalibratePh(buffer))
+                    }
+                    #[cfg(feature = "hydro")]
+                    "calibrate_ec" => {
+                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
+                        let buffer = positive(&value).ok_or(CommandError::InvalidValue(value))?;
+                        Ok(Command::CalibrateEc(buffer))
+                    }
+                    #[cfg(feature = "hydro")]
+                    "clear_calibration" => {
+                        let value = command.value.ok_or(CommandError::WrongCommand(error_cmd))?;
+                        let probe = serde_json::from_value::<HydroProbe>(value.clone())
+                            .map_err(|_| CommandError::InvalidValue(value))?;
+                        Ok(Command::ClearCalibration(probe))
+                    }
+                    _ => Err(CommandError::WrongCommand(error_cmd)),
+                }
+            }
+            Err(err) => Err(CommandError::JsonParseError(err.classify())),
+        }
+    }
+
+    type Err = CommandError;
+}
+
+/// `true`/`false` for the first zone or `{"zone":1,"on":true}` with either `"ml"` or `"seconds"`
+fn parse_water(value: &Value) -> Option<Command> {
+    if let Value::Bool(on) = value {
+        return Some(Command::Water {
+            zone: 0,
+            on: *on,
+            amount: None,
+        });
+    }
+    let zone = u8::try_from(value.get("zone")?.as_u64()?).ok()?;
+    let on = value.get("on")?.as_bool()?;
+    let amount = match (value.get("ml"), value.get("seconds")) {
+        (None, None) => None,
+        (Some(ml), None) => Some(Amount::Volume(positive(ml)?)),
+        (None, Some(seconds)) => Some(Amount::Time(
+            Duration::try_from_secs_f32(positive(seconds)?).ok()?,
+        )),
+        (Some(_), Some(_)) => return None,
+    };
+    Some(Command::Water { zone, on, amount })
+}
+
+/// Level in percent or `{"level":80,"minutes":120}`
+fn parse_lamp(value: &Value) -> Option<Command> {
+    let (level, minutes) = match value {
+        Value::Object(_) => (value.get("level")?, value.get("minutes")),
+        level => (level, None),
+    };
+    let level = u8::try_from(level.as_u64()?)
+        .ok()
+        .filter(|level| *level <= 100)?;
+    let duration = match minutes {
+        Some(minutes) => Some(Duration::try_from_secs_f32(positive(minutes)? * 60.0).ok()?),
+        None => None,
+    };
+    Some(Command::Lamp { level, duration })
+}
+
+/// Volume or rate value, it has to be a positive number
+fn positive(value: &Value) -> Option<f32> {
+    value
+        .as_f64()
+        .map(|value| value as f32)
+        .filter(|value| value.is_finite() && *value > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            r#"{"name":"water","value":true}"#.parse::<Command>().unwrap(),
+            Command::Water {
+                zone: 0,
+                on: true,
+                amount: None
+            }
+        );
+        assert_eq!(
+            r#"{"name":"water","value":{"zone":2,"on":false}}"#.parse::<Command>().unwrap(),
+            Command::Water {
+                zone: 2,
+                on: false,
+                amount: None
+            }
+        );
+        assert_eq!(
+            r#"{"name":"water","value":{"zone":1,"on":true,"ml":250}}"#
+                .parse::<Command>()
+                .unwrap(),
+            Command::Water {
+                zone: 1,
+                on: true,
+                amount: Some(Amount::Volume(250.0))
+            }
+        );
+        assert_eq!(
+            r#"{"name":"water","value":{"zone":1,"on":true,"seconds":20}}"#
+                .parse::<Command>()
+                .unwrap(),
+            Command::Water {
+                zone: 1,
+                on: true,
+                amount: Some(Amount::Time(Duration::from_secs(20)))
+            }
+        );
+        assert_eq!(
+            r#"{"name":"calibrate_flow","value":1}"#.parse::<Command>().unwrap(),
+            Command::CalibrateFlow(1)
+        );
+        assert_eq!(
+            r#"{"name":"flow","value":{"zone":1,"ml":310.5}}"#.parse::<Command>().unwrap(),
+            Command::FlowMeasured {
+                zone: 1,
+                volume: 310.5
+            }
+        );
+        assert_eq!(
+            r#"{"name":"lamp","value":80}"#.parse::<Command>().unwrap(),
+            Command::Lamp {
+                level: 80,
+                duration: None
+            }
+        );
+        assert_eq!(
+            r#"{"name":"lamp","value":{"level":80,"minutes":120}}"#
+                .parse::<Command>()
+                .unwrap(),
+            Command::Lamp {
+                level: 80,
+                duration: Some(Duration::from_secs(2 * 3600))
+            }
+        );
+        assert_eq!(
+            r#"{"name":"stop"}"#.parse::<Command>().unwrap(),
+            Command::Stop
+        );
+        assert_eq!(
+            r#"{"name":"all"}"#.parse::<Command>().unwrap(),
+            Command::AllSemorData
+        );
+        assert_eq!(
+            r#"{"name":"ack"}"#.parse::<Command>().unwrap(),
+            Command::AckAlert
+        );
+        assert_eq!(
+            r#"{"name":"refill"}"#.parse::<Command>().unwrap(),
+            Command::Refill
+        );
+        assert_eq!(
+            r#"{"name":"pid","value":{"loop":"heater","kp":0.5,"ki":0.001,"kd":2}}"#
+                .parse::<Command>()
+                .unwrap(),
+            Command::TunePid {
+                target: PidLoop::Heater,
+                gains: PidGains {
+                    kp: 0.5,
+                    ki: 0.001,
+                    kd: 2.0
+                }
+            }
+        );
+        assert_eq!(
+            r#"{"name":"job","value":{"job":0,"enabled":false}}"#
+                .parse::<Command>()
+                .unwrap(),
+            Command::Job {
+                job: 0,
+                enabled: false
+            }
+        );
+    }
+
+    #[cfg(feature = "hydro")]
+    #[test]
+    fn parses_hydro_commands() {
+        assert_eq!(
+            r#"{"name":"calibrate_ph","value":7.0}"#.parse::<Command>().unwrap(),
+            Command::CalibratePh(7.0)
+        );
+        assert_eq!(
+            r#"{"name":"calibrate_ec","value":1.413}"#.parse::<Command>().unwrap(),
+            Command::CalibrateEc(1.413)
+        );
+        assert_eq!(
+            r#"{"name":"clear_calibration","value":"ec"}"#.parse::<Command>().unwrap(),
+            Command::ClearCalibration(HydroProbe::Ec)
+        );
+        assert!(matches!(
+            r#"{"name":"calibrate_ph","value":15}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"calibrate_ec","value":-1}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"clear_calibration","value":"soil"}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(matches!(
+            r#"{"name":"water","value":"yes"}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"water","value":{"zone":300,"on":true}}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"water","value":{"zone":1,"on":true,"ml":-10}}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"water","value":{"zone":1,"on":true,"ml":50,"seconds":5}}"#
+                .parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"lamp","value":{"level":120,"minutes":5}}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"flow","value":{"zone":1,"ml":0}}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"lamp"}"#.parse::<Command>(),
+            Err(CommandError::WrongCommand(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"pid","value":{"loop":"fan","kp":1,"ki":0,"kd":0}}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"pid","value":{"loop":"heater","kp":-1,"ki":0,"kd":0}}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"job","value":{"job":0}}"#.parse::<Command>(),
+            Err(CommandError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            r#"{"name":"dance"}"#.parse::<Command>(),
+            Err(CommandError::WrongCommand(_))
+        ));
+        assert!(matches!(
+            "water on".parse::<Command>(),
+            Err(CommandError::JsonParseError(_))
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+//! Device configuration, kept in the NVS and updated over MQTT on the `station/config` topic.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{sun::Location, time_sync::DEFAULT_NTP_SERVERS, timezone::TimeZone};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Configuration is not a JSON object")]
+    NotAnObject,
+    #[error("Invalid configuration: {0}")]
+    Invalid(#[from] serde_json::Error),
+    #[error("Location is out of range")]
+    InvalidLocation,
+    #[error("NTP server names can't be empty")]
+    InvalidNtpServer,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct DeviceConfig {
+    /// POSIX TZ string of the local time the schedules run on
+    pub timezone: TimeZone,
+    /// Position of the station for the schedules relative to the sun
+    pub location: Option<Location>,
+    /// NTP servers asked first, the default pool is the fallback
+    pub ntp_servers: Vec<String>,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            timezone: TimeZone::default(),
+            location: None,
+            ntp_servers: DEFAULT_NTP_SERVERS.map(String::from).to_vec(),
+        }
+    }
+}
+
+impl DeviceConfig {
+    /// Configuration with the fields of the `update` object replaced, the rest is kept
+    pub fn updated(&self, update: &[u8]) -> Result<Self, ConfigError> {
+        let Value::Object(update) = serde_json::from_slice(update)? else {
+            return Err(ConfigError::NotAnObject);
+        };
+        let mut config = serde_json::to_value(self)?;
+        if let Value::Object(fields) = &mut config {
+            fields.extend(update);
+        }
+        let config: Self = serde_json::from_value(config)?;
+        let valid_location = config.location.map_or(true, |location| {
+            location.latitude.abs() <= 90.0 && location.longitude.abs() <= 180.0
+        });
+        if !valid_location {
+            return Err(ConfigError::InvalidLocation);
+        }
+        if config
+            .ntp_servers
+            .iter()
+            .any(|server| server.trim().is_empty())
+        {
+            return Err(ConfigError::InvalidNtpServer);
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_fields() {
+        let config = DeviceConfig::default()
+            .updated(br#"{"timezone":"CET-1CEST,M3.5.0,M10.5.0/3"}"#)
+            .unwrap();
+        assert_eq!(config.timezone.to_string(), "CET-1CEST,M3.5.0,M10.5.0/3");
+        assert_eq!(config.updated(b"{}").unwrap(), config);
+        let config = config
+            .updated(br#"{"location":{"latitude":47.5,"longitude":19.04}}"#)
+            .unwrap();
+        assert_eq!(config.location.unwrap().latitude, 47.5);
+        assert_eq!(config.timezone.to_string(), "CET-1CEST,M3.5.0,M10.5.0/3");
+
+        assert!(matches!(
+            config.updated(br#"{"timezone":"Europe/Berlin"}"#),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            config.updated(br#"{"location":{"latitude":147.5,"longitude":19.04}}"#),
+            Err(ConfigError::InvalidLocation)
+        ));
+        let config = config
+            .updated(br#"{"ntp_servers":["time.cloudflare.com","time.google.com"]}"#)
+            .unwrap();
+        assert_eq!(config.ntp_servers.len(), 2);
+        assert_eq!(
+            DeviceConfig::default().ntp_servers,
+            vec!["pool.ntp.org".to_string()]
+        );
+        assert!(matches!(
+            config.updated(br#"{"ntp_servers":[""]}"#),
+            Err(ConfigError::InvalidNtpServer)
+        ));
+        assert!(matches!(
+            config.updated(b"[]"),
+            Err(ConfigError::NotAnObject)
+        ));
+    }
+}
@@ -0,0 +1,298 @@
+//! Nutrient dosing with a peristaltic pump.\
+//! Doses follow a weekly calendar and are only given into running water, never into dry soil.
+//! The dose of a day is owed from its first watering on, if a watering ends before the dose is
+//! done the rest goes into the next watering of the same day. Doses not given by midnight are
+//! dropped. The volume left in the nutrient bottle is tracked for the low stock notification.
+//! A dose is saved as started before the pump switches on, one cut by a reboot counts as given.
+
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Weekday};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DosingConfig {
+    /// Volume of one dose in ml
+    pub dose: f32,
+    /// Flow of the dosing pump in ml/s
+    pub flow_rate: f32,
+    /// Days of the week a dose is given
+    pub days: Vec<Weekday>,
+    /// Volume of a full nutrient bottle in ml
+    pub bottle: f32,
+    /// Low stock notification below this many ml
+    pub low_stock: f32,
+}
+
+impl Default for DosingConfig {
+    fn default() -> Self {
+        Self {
+            dose: 5.0,
+            flow_rate: 1.0,
+            days: vec![Weekday::Mon, Weekday::Thu],
+            bottle: 500.0,
+            low_stock: 50.0,
+        }
+    }
+}
+
+/// State kept over reboots, so a dose is never given twice a day
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DosingState {
+    /// Nutrient left in the bottle in ml
+    pub remaining: f32,
+    /// Day of the last finished dose
+    pub last_dose: Option<NaiveDate>,
+    /// Dose the pump is running for, `None` once it is done
+    #[serde(default)]
+    pub running: Option<RunningDose>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct RunningDose {
+    pub started: NaiveDateTime,
+    /// Volume the run was planned for in ml
+    pub planned: f32,
+}
+
+/// Output of one scheduler step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DosingStep {
+    /// Desired state of the dosing pump
+    pub on: bool,
+    /// Time until the running dose is done
+    pub next_check: Option<Duration>,
+    /// The bottle just went below the low stock level
+    pub low_stock: bool,
+    /// A dose was finished in this step
+    pub dosed: bool,
+}
+
+pub struct DosingScheduler {
+    config: DosingConfig,
+    state: DosingState,
+    day: Option<NaiveDate>,
+    /// Volume still owed today in ml
+    pending: f32,
+    /// Time of the last update while the pump was running
+    running_since: Option<NaiveDateTime>,
+}
+
+impl DosingScheduler {
+    /// `state` restored from before a reboot, a full bottle if there is none.\
+    /// A dose cut by the reboot is taken as given in full, so it is never repeated.
+    pub fn new(config: DosingConfig, state: Option<DosingState>) -> Self {
+        let mut state = state.unwrap_or(DosingState {
+            remaining: config.bottle,
+            last_dose: None,
+            running: None,
+        });
+        if let Some(dose) = state.running.take() {
+            warn!(
+                "Dose of {:.1}ml started at {} was cut by a reboot, counted as given",
+                dose.planned, dose.started
+            );
+            state.remaining = (state.remaining - dose.planned).max(0.0);
+            state.last_dose = Some(dose.started.date());
+        }
+        Self {
+            config,
+            state,
+            day: None,
+            pending: 0.0,
+            running_since: None,
+        }
+    }
+
+    pub fn config(&self) -> &DosingConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> &DosingState {
+        &self.state
+    }
+
+    /// A full bottle was put in
+    pub fn refill(&mut self) {
+        self.state.remaining = self.config.bottle;
+    }
+
+    /// Advance to the wall clock `now`, `watering` is true while the water pump runs
+    pub fn update(&mut self, watering: bool, now: NaiveDateTime) -> DosingStep {
+        let today = now.date();
+        if self.day != Some(today) {
+            self.day = Some(today);
+            if self.pending > 0.0 {
+                info!("Dose of {:.1}ml missed, no watering", self.pending);
+            }
+            let due =
+                self.config.days.contains(&today.weekday()) && self.state.last_dose != Some(today);
+            self.pending = if due { self.config.dose } else { 0.0 };
+            self.running_since = None;
+        }
+
+        let was_on = self.running_since.is_some();
+        let mut low_stock = false;
+        let mut dosed = false;
+        if let Some(since) = self.running_since.take() {
+            let elapsed = (now - since).to_std().unwrap_or_default();
+            let volume = (self.config.flow_rate * elapsed.as_secs_f32())
+                .min(self.pending)
+                .min(self.state.remaining);
+            let was_low = self.state.remaining < self.config.low_stock;
+            self.pending -= volume;
+            self.state.remaining -= volume;
+            low_stock = !was_low && self.state.remaining < self.config.low_stock;
+            if self.pending <= f32::EPSILON {
+                self.pending = 0.0;
+                self.state.last_dose = Some(today);
+                dosed = true;
+            }
+        }
+
+        let on = watering && self.pending > 0.0 && self.state.remaining > 0.0;
+        if on {
+            self.running_since = Some(now);
+        }
+        if on && !was_on {
+            self.state.running = Some(RunningDose {
+                started: now,
+                planned: self.pending.min(self.state.remaining),
+            });
+        } else if !on {
+            self.state.running = None;
+        }
+        let next_check = on.then(|| {
+            Duration::from_secs_f32(self.pending.min(self.state.remaining) / self.config.flow_rate)
+        });
+        DosingStep {
+            on,
+            next_check,
+            low_stock,
+            dosed,
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "remaining": self.state.remaining,
+            "last_dose": self.state.last_dose,
+            "pending": self.pending,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2023-06-05 is a monday
+    fn monday(hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 6, 5)
+            .unwrap()
+            .and_hms_opt(hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn doses_only_into_running_water() {
+        let mut dosing = DosingScheduler::new(DosingConfig::default(), None);
+        assert!(!dosing.update(false, monday(8, 0, 0)).on);
+
+        let step = dosing.update(true, monday(9, 0, 0));
+        assert!(step.on);
+        assert_eq!(step.next_check, Some(Duration::from_secs(5)));
+
+        // The watering ends after 3s, the rest waits for the next watering
+        let step = dosing.update(false, monday(9, 0, 3));
+        assert!(!step.on && !step.dosed);
+        let step = dosing.update(true, monday(12, 0, 0));
+        assert_eq!(step.next_check, Some(Duration::from_secs(2)));
+        assert!(dosing.update(true, monday(12, 0, 2)).dosed);
+        assert_eq!(dosing.state().remaining, 495.0);
+
+        // Once a day
+        assert!(!dosing.update(true, monday(18, 0, 0)).on);
+    }
+
+    #[test]
+    fn follows_the_calendar() {
+        let mut dosing = DosingScheduler::new(DosingConfig::default(), None);
+        let tuesday = monday(9, 0, 0) + chrono::Duration::days(1);
+        assert!(!dosing.update(true, tuesday).on);
+    }
+
+    #[test]
+    fn no_second_dose_after_reboot() {
+        let mut dosing = DosingScheduler::new(DosingConfig::default(), None);
+        dosing.update(true, monday(9, 0, 0));
+        dosing.update(true, monday(9, 0, 5));
+        let state = dosing.state().clone();
+
+        let mut rebooted = DosingScheduler::new(DosingConfig::default(), Some(state));
+        assert!(!rebooted.update(true, monday(10, 0, 0)).on);
+    }
+
+    #[test]
+    fn dose_cut_by_reboot_is_not_repeated() {
+        let mut dosing = DosingScheduler::new(DosingConfig::default(), None);
+        dosing.update(true, monday(9, 0, 0));
+        let running = dosing.state().running.unwrap();
+        assert_eq!(running.started, monday(9, 0, 0));
+        assert_eq!(running.planned, 5.0);
+        // Saved right after the pump was switched on
+        let json = serde_json::to_string(dosing.state()).unwrap();
+
+        let mut rebooted = DosingScheduler::new(
+            DosingConfig::default(),
+            Some(serde_json::from_str(&json).unwrap()),
+        );
+        assert_eq!(rebooted.state().running, None);
+        assert_eq!(rebooted.state().remaining, 495.0);
+        assert!(!rebooted.update(true, monday(9, 5, 0)).on);
+    }
+
+    #[test]
+    fn finished_dose_is_marked_done() {
+        let mut dosing = DosingScheduler::new(DosingConfig::default(), None);
+        dosing.update(true, monday(9, 0, 0));
+        assert!(dosing.state().running.is_some());
+        assert!(dosing.update(true, monday(9, 0, 5)).dosed);
+        assert_eq!(dosing.state().running, None);
+        assert_eq!(dosing.state().last_dose, Some(monday(0, 0, 0).date()));
+    }
+
+    #[test]
+    fn low_stock_notification() {
+        let state = DosingState {
+            remaining: 52.0,
+            last_dose: None,
+            running: None,
+        };
+        let mut dosing = DosingScheduler::new(DosingConfig::default(), Some(state));
+        dosing.update(true, monday(9, 0, 0));
+        let step = dosing.update(true, monday(9, 0, 5));
+        assert!(step.low_stock);
+        assert_eq!(dosing.state().remaining, 47.0);
+
+        // Put in a new bottle
+        dosing.refill();
+        assert_eq!(dosing.state().remaining, 500.0);
+    }
+
+    #[test]
+    fn empty_bottle_stops_dosing() {
+        let state = DosingState {
+            remaining: 2.0,
+            last_dose: None,
+            running: None,
+        };
+        let mut dosing = DosingScheduler::new(DosingConfig::default(), Some(state));
+        dosing.update(true, monday(9, 0, 0));
+        let step = dosing.update(true, monday(9, 0, 5));
+        assert!(!step.on);
+        assert_eq!(dosing.state().remaining, 0.0);
+    }
+}
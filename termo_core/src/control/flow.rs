@@ -0,0 +1,126 @@
+//! Flow calibration of the pump, so watering can be given in millilitres.\
+//! Every zone has its own flow rate, the hoses and drippers behind the valves differ. The rate is
+//! measured once by a timed calibration run and then corrected by the flow meter if there is one.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Pump time of a calibration run
+pub const CALIBRATION_RUN: Duration = Duration::from_secs(30);
+/// Weight of a flow meter reading in the corrected flow rate
+pub const FLOW_METER_WEIGHT: f32 = 0.2;
+/// Runs shorter than this are too noisy to correct the flow rate
+pub const MIN_METERED_RUN: Duration = Duration::from_secs(2);
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum FlowError {
+    #[error("Volume of {0} ml is not valid")]
+    InvalidVolume(f32),
+    #[error("Flow rate of {0} ml/s is not valid, calibrate the zone again")]
+    InvalidFlow(f32),
+}
+
+/// Flow rate of a zone in ml/s
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FlowRate(pub f32);
+
+impl FlowRate {
+    /// Flow rate from the `volume` in ml caught during a pump run of `run`
+    pub fn from_run(volume: f32, run: Duration) -> Option<Self> {
+        let rate = volume / run.as_secs_f32();
+        (rate.is_finite() && rate > 0.0).then_some(Self(rate))
+    }
+
+    /// Pump time needed for `volume` ml, at most `max`
+    pub fn duration_for(&self, volume: f32, max: Duration) -> Result<Duration, FlowError> {
+        if !(volume.is_finite() && volume > 0.0) {
+            return Err(FlowError::InvalidVolume(volume));
+        }
+        if !(self.0.is_finite() && self.0 > 0.0) {
+            return Err(FlowError::InvalidFlow(self.0));
+        }
+        let duration = Duration::try_from_secs_f32(volume / self.0).unwrap_or(max);
+        Ok(duration.min(max))
+    }
+
+    /// Volume in ml pumped in `run`
+    pub fn volume(&self, run: Duration) -> f32 {
+        self.0 * run.as_secs_f32()
+    }
+
+    /// Move the rate towards the `metered` ml of a pump run of `run`.\
+    /// Short runs and readings off by more than a factor of two are ignored, a stuck meter or a
+    /// dry tank must not ruin the calibration.
+    pub fn correct(&mut self, metered: f32, run: Duration) -> bool {
+        if run < MIN_METERED_RUN {
+            return false;
+        }
+        let Some(Self(measured)) = Self::from_run(metered, run) else {
+            return false;
+        };
+        if !(self.0 / 2.0..=self.0 * 2.0).contains(&measured) {
+            return false;
+        }
+        self.0 += (measured - self.0) * FLOW_METER_WEIGHT;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_run() {
+        let flow = FlowRate::from_run(300.0, CALIBRATION_RUN).unwrap();
+        assert_eq!(flow, FlowRate(10.0));
+        assert_eq!(
+            flow.duration_for(250.0, Duration::from_secs(60)),
+            Ok(Duration::from_secs(25))
+        );
+        assert_eq!(flow.volume(Duration::from_secs(3)), 30.0);
+        assert_eq!(FlowRate::from_run(0.0, CALIBRATION_RUN), None);
+        assert_eq!(FlowRate::from_run(-5.0, CALIBRATION_RUN), None);
+    }
+
+    #[test]
+    fn invalid_volumes_and_flows() {
+        let max = Duration::from_secs(60);
+        let flow = FlowRate(10.0);
+        // Clamped to the longest pump run
+        assert_eq!(flow.duration_for(1e38, max), Ok(max));
+        assert_eq!(flow.duration_for(1000.0, max), Ok(max));
+        for volume in [0.0, -5.0, f32::INFINITY, f32::NAN] {
+            assert!(matches!(
+                flow.duration_for(volume, max),
+                Err(FlowError::InvalidVolume(_))
+            ));
+        }
+        for rate in [0.0, -1.0, f32::NAN] {
+            assert!(matches!(
+                FlowRate(rate).duration_for(100.0, max),
+                Err(FlowError::InvalidFlow(_))
+            ));
+        }
+        // A tiny flow gives a duration too long for `Duration`
+        assert_eq!(FlowRate(1e-38).duration_for(1e38, max), Ok(max));
+    }
+
+    #[test]
+    fn flow_meter_correction() {
+        let mut flow = FlowRate(10.0);
+        let run = Duration::from_secs(10);
+        // The pump delivers 12 ml/s, the rate converges to it
+        for _ in 0..30 {
+            assert!(flow.correct(120.0, run));
+        }
+        assert!((flow.0 - 12.0).abs() < 0.01);
+
+        // Implausible readings are ignored
+        assert!(!flow.correct(0.0, run));
+        assert!(!flow.correct(500.0, run));
+        assert!(!flow.correct(12.0, Duration::from_secs(1)));
+        assert!((flow.0 - 12.0).abs() < 0.01);
+    }
+}
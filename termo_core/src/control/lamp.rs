@@ -0,0 +1,163 @@
+//! Brightness of the dimmable grow lamp.\
+//! Levels are in percent of the perceived brightness, they are gamma corrected to the PWM duty.
+//! Every change fades, sunrise and sunset are the same fades stretched to minutes.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::ramp::Ramp;
+
+/// Gamma of the perceived brightness of the LEDs
+pub const GAMMA: f32 = 2.2;
+
+/// PWM duty of a brightness level in percent
+pub fn gamma_duty(level: f32, max_duty: u32) -> u32 {
+    let level = level.clamp(0.0, 100.0) / 100.0;
+    (level.powf(GAMMA) * max_duty as f32).round() as u32
+}
+
+/// Fade lengths of the lamp
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LampConfig {
+    /// Fade of a plain level change
+    pub fade: Duration,
+    /// Ramp from dark to the day level
+    pub sunrise: Duration,
+    /// Ramp from the day level to dark
+    pub sunset: Duration,
+}
+
+impl Default for LampConfig {
+    fn default() -> Self {
+        Self {
+            fade: Duration::from_secs(1),
+            sunrise: Duration::from_secs(30 * 60),
+            sunset: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+pub struct LampController {
+    config: LampConfig,
+    level: f32,
+    fade: Option<Ramp>,
+}
+
+impl LampController {
+    pub fn new(config: LampConfig) -> Self {
+        Self {
+            config,
+            level: 0.0,
+            fade: None,
+        }
+    }
+
+    pub fn config(&self) -> &LampConfig {
+        &self.config
+    }
+
+    /// Fade to `level` percent with the normal fade length
+    pub fn set(&mut self, level: u8, now: Instant) {
+        self.fade_to(level, self.config.fade, now);
+    }
+
+    /// Ramp up to the day `level`
+    pub fn sunrise(&mut self, level: u8, now: Instant) {
+        self.fade_to(level, self.config.sunrise, now);
+    }
+
+    /// Ramp down to dark
+    pub fn sunset(&mut self, now: Instant) {
+        self.fade_to(0, self.config.sunset, now);
+    }
+
+    /// Fade from the current level to `level` in `duration`
+    pub fn fade_to(&mut self, level: u8, duration: Duration, now: Instant) {
+        let from = self.update(now);
+        self.fade = Some(Ramp {
+            from,
+            to: f32::from(level.min(100)),
+            start: now,
+            duration,
+        });
+    }
+
+    /// Level at `now`, a finished fade is dropped
+    pub fn update(&mut self, now: Instant) -> f32 {
+        if let Some(fade) = self.fade {
+            self.level = fade.level_at(now);
+            if fade.is_done(now) {
+                self.fade = None;
+            }
+        }
+        self.level
+    }
+
+    /// Level the lamp is heading to
+    pub fn target(&self) -> u8 {
+        self.fade.map_or(self.level, |fade| fade.to).round() as u8
+    }
+
+    pub fn is_fading(&self) -> bool {
+        self.fade.is_some()
+    }
+
+    /// Time until the next step of a running fade, about a quarter percent per step
+    pub fn next_step(&self, now: Instant) -> Option<Duration> {
+        let fade = self.fade?;
+        let steps = (fade.to - fade.from).abs() * 4.0;
+        Some(fade.next_step(steps, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::ramp::MIN_RAMP_STEP;
+
+    #[test]
+    fn gamma_correction() {
+        assert_eq!(gamma_duty(0.0, 8191), 0);
+        assert_eq!(gamma_duty(100.0, 8191), 8191);
+        // Half perceived brightness is far below half duty
+        assert_eq!(gamma_duty(50.0, 8191), 1783);
+        assert_eq!(gamma_duty(150.0, 8191), 8191);
+    }
+
+    #[test]
+    fn fades_linearly() {
+        let mut lamp = LampController::new(LampConfig::default());
+        let start = Instant::now();
+        lamp.set(80, start);
+        assert_eq!(lamp.target(), 80);
+        assert_eq!(lamp.update(start + Duration::from_millis(500)), 40.0);
+        assert_eq!(lamp.update(start + Duration::from_secs(2)), 80.0);
+        assert!(!lamp.is_fading());
+        assert_eq!(lamp.next_step(start), None);
+    }
+
+    #[test]
+    fn fade_starts_from_current_level() {
+        let mut lamp = LampController::new(LampConfig::default());
+        let start = Instant::now();
+        lamp.sunrise(100, start);
+        let half = start + Duration::from_secs(15 * 60);
+        assert_eq!(lamp.update(half), 50.0);
+
+        lamp.sunset(half);
+        assert_eq!(lamp.update(half + Duration::from_secs(15 * 60)), 25.0);
+        assert_eq!(lamp.target(), 0);
+    }
+
+    #[test]
+    fn fade_steps() {
+        let mut lamp = LampController::new(LampConfig::default());
+        let start = Instant::now();
+        lamp.sunrise(100, start);
+        // 400 steps over 30 minutes
+        assert_eq!(lamp.next_step(start), Some(Duration::from_millis(4500)));
+        lamp.set(50, start);
+        assert_eq!(lamp.next_step(start), Some(MIN_RAMP_STEP));
+    }
+}
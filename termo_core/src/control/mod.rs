@@ -0,0 +1,14 @@
+//! Control loops of the actuators, they decide and the firmware drives the hardware.
+
+pub mod dosing;
+pub mod flow;
+pub mod lamp;
+pub mod overrides;
+pub mod photoperiod;
+pub mod pid;
+pub mod ramp;
+pub mod safety;
+pub mod soft_start;
+pub mod thermostat;
+pub mod watering;
+pub mod zones;
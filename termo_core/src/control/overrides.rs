@@ -0,0 +1,97 @@
+//! Manual overrides of the automatic control.\
+//! An override always runs for a limited time, a lost stop message can't leave an output on.
+//! Once it expires or is stopped the automatic control takes over again.
+
+use std::time::{Duration, Instant};
+
+/// Length of a lamp override if the command has none
+pub const LAMP_OVERRIDE: Duration = Duration::from_secs(60 * 60);
+/// Longest override accepted
+pub const MAX_OVERRIDE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Override<T> {
+    active: Option<(T, Instant)>,
+}
+
+impl<T> Default for Override<T> {
+    fn default() -> Self {
+        Self { active: None }
+    }
+}
+
+impl<T: Copy> Override<T> {
+    /// Hold `value` for `duration`, capped to [`MAX_OVERRIDE`]
+    pub fn set(&mut self, value: T, duration: Duration, now: Instant) {
+        self.active = Some((value, now + duration.min(MAX_OVERRIDE)));
+    }
+
+    /// Value of the running override
+    pub fn get(&self, now: Instant) -> Option<T> {
+        self.active
+            .filter(|(_, until)| now < *until)
+            .map(|(value, _)| value)
+    }
+
+    /// Time left of the running override
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        self.active
+            .map(|(_, until)| until.saturating_duration_since(now))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Stop the override, true if one was running
+    pub fn cancel(&mut self) -> bool {
+        self.active.take().is_some()
+    }
+
+    /// Drop an expired override, true once when it expired so the automatic control can resume
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let expired = self.active.map_or(false, |(_, until)| now >= until);
+        if expired {
+            self.active = None;
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expires() {
+        let mut lamp = Override::default();
+        let start = Instant::now();
+        lamp.set(80_u8, Duration::from_secs(60), start);
+        assert_eq!(lamp.get(start), Some(80));
+        assert_eq!(
+            lamp.remaining(start + Duration::from_secs(20)),
+            Some(Duration::from_secs(40))
+        );
+
+        let end = start + Duration::from_secs(60);
+        assert_eq!(lamp.get(end), None);
+        assert!(lamp.expire(end));
+        assert!(!lamp.expire(end));
+    }
+
+    #[test]
+    fn stop_preempts() {
+        let mut lamp = Override::default();
+        let start = Instant::now();
+        lamp.set(80_u8, Duration::from_secs(60), start);
+        assert!(lamp.cancel());
+        assert_eq!(lamp.get(start), None);
+        assert!(!lamp.cancel());
+        assert!(!lamp.expire(start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn capped_duration() {
+        let mut lamp = Override::default();
+        let start = Instant::now();
+        lamp.set(80_u8, Duration::from_secs(7 * 24 * 3600), start);
+        assert_eq!(lamp.remaining(start), Some(MAX_OVERRIDE));
+    }
+}
@@ -0,0 +1,366 @@
+//! Photoperiod of the grow lamp.\
+//! The lamp is on in the light windows of the day. With a daily light integral ( DLI ) target the
+//! light of the day is summed up from the light sensor, and the lamp is kept on after the last
+//! window of a dull day until the target is reached. The light day starts with the window after
+//! the longest dark period, not at midnight, so a window ending at midnight can be extended.
+//! The light summed up so far is kept in a [`LightDay`], a reboot during the day carries on with it.
+
+use std::time::Duration;
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::schedule::duration_until;
+
+/// Photosynthetic photon flux in µmol/m²/s of one lux of sunlight
+pub const LUX_TO_PPFD: f32 = 0.0185;
+/// Light sensor sampling interval for the DLI
+pub const DLI_SAMPLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Time of the day the lamp is on, it may span midnight
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LightWindow {
+    pub on: NaiveTime,
+    pub off: NaiveTime,
+}
+
+impl LightWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.on <= self.off {
+            self.on <= time && time < self.off
+        } else {
+            time >= self.on || time < self.off
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Photoperiod {
+    pub windows: Vec<LightWindow>,
+    /// Lamp level in percent during the day
+    pub level: u8,
+    /// Daily light integral target in mol/m²/day
+    pub dli_target: Option<f32>,
+    /// Longest extension of the lamp time on a dull day
+    pub max_extension: Duration,
+}
+
+impl Photoperiod {
+    /// 18 hours light, 6 hours dark for the growth phase
+    pub fn vegetative() -> Self {
+        Self::single_window(6, 0)
+    }
+
+    /// 12 hours light, 12 hours dark to trigger blooming
+    pub fn flowering() -> Self {
+        Self::single_window(6, 18)
+    }
+
+    fn single_window(on: u32, off: u32) -> Self {
+        Self {
+            windows: vec![LightWindow {
+                on: NaiveTime::from_hms_opt(on, 0, 0).expect("Valid hour"),
+                off: NaiveTime::from_hms_opt(off, 0, 0).expect("Valid hour"),
+            }],
+            level: 100,
+            dli_target: None,
+            max_extension: Duration::from_secs(3 * 3600),
+        }
+    }
+
+    pub fn with_dli_target(mut self, dli: f32) -> Self {
+        self.dli_target = Some(dli);
+        self
+    }
+
+    /// Scheduled state of the lamp at `time`
+    pub fn is_light(&self, time: NaiveTime) -> bool {
+        self.windows.iter().any(|window| window.contains(time))
+    }
+
+    /// Start of the light day the DLI is summed up over, the window after the longest dark period
+    pub fn day_start(&self) -> NaiveTime {
+        self.windows
+            .iter()
+            .max_by_key(|window| {
+                self.windows
+                    .iter()
+                    .map(|other| duration_until(other.off, window.on))
+                    .min()
+            })
+            .map_or(NaiveTime::MIN, |window| window.on)
+    }
+
+    /// Time until the next window starts or ends
+    pub fn next_change(&self, time: NaiveTime) -> Duration {
+        self.windows
+            .iter()
+            .flat_map(|window| [window.on, window.off])
+            .map(|change| duration_until(time, change))
+            .filter(|until| !until.is_zero())
+            .min()
+            .unwrap_or(Duration::from_secs(24 * 3600))
+    }
+}
+
+/// Lamp state decided by the [`PhotoperiodController`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightStep {
+    pub on: bool,
+    /// The lamp is kept on past the window for the DLI target
+    pub extended: bool,
+    /// Time until the controller wants to be updated again
+    pub next_check: Duration,
+}
+
+/// Progress of the light day, journaled so a reboot doesn't start the DLI over
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct LightDay {
+    pub day: Option<NaiveDate>,
+    /// Light summed up in mol/m²
+    pub dli: f32,
+    /// Lamp time added past the last window
+    pub extension: Duration,
+    /// The lamp was on, an extension carries on after the reboot
+    pub on: bool,
+}
+
+pub struct PhotoperiodController {
+    period: Photoperiod,
+    day: LightDay,
+    last: Option<NaiveDateTime>,
+}
+
+impl PhotoperiodController {
+    /// `saved` is the light day journaled before a reboot, it is dropped once its day is over
+    pub fn new(period: Photoperiod, saved: Option<LightDay>) -> Self {
+        Self {
+            period,
+            day: saved.unwrap_or_default(),
+            last: None,
+        }
+    }
+
+    pub fn period(&self) -> &Photoperiod {
+        &self.period
+    }
+
+    pub fn set_period(&mut self, period: Photoperiod) {
+        self.period = period;
+    }
+
+    /// Light summed up in the current light day in mol/m²
+    pub fn dli(&self) -> f32 {
+        self.day.dli
+    }
+
+    /// State to journal after an update
+    pub fn light_day(&self) -> LightDay {
+        self.day
+    }
+
+    /// Advance to the wall clock `now`, `lux` is the reading of the light sensor if there is one.\
+    /// The first update right after boot restores the state of the schedule.
+    pub fn update(&mut self, now: NaiveDateTime, lux: Option<f32>) -> LightStep {
+        let day_start = i64::from(self.period.day_start().num_seconds_from_midnight());
+        let day = (now - chrono::Duration::seconds(day_start)).date();
+        if self.day.day != Some(day) {
+            self.day = LightDay {
+                day: Some(day),
+                on: self.day.on,
+                ..Default::default()
+            };
+        }
+        let elapsed = self
+            .last
+            .and_then(|last| (now - last).to_std().ok())
+            .unwrap_or_default();
+        self.last = Some(now);
+        if let Some(lux) = lux {
+            self.day.dli += lux * LUX_TO_PPFD * elapsed.as_secs_f32() / 1_000_000.0;
+        }
+
+        let scheduled = self.period.is_light(now.time());
+        // Only a lamp left on by the last window is extended, never one that already went dark
+        let dull = !scheduled
+            && self.day.on
+            && lux.is_some()
+            && self
+                .period
+                .dli_target
+                .map_or(false, |target| self.day.dli < target);
+        if dull {
+            self.day.extension += elapsed;
+        }
+        let extended = dull && self.day.extension < self.period.max_extension;
+        self.day.on = scheduled || extended;
+
+        let next_change = self.period.next_change(now.time());
+        let next_check = match self.period.dli_target {
+            Some(_) => next_change.min(DLI_SAMPLE_INTERVAL),
+            None => next_change,
+        };
+        LightStep {
+            on: self.day.on,
+            extended,
+            next_check,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 6, 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn presets() {
+        let veg = Photoperiod::vegetative();
+        assert!(!veg.is_light(at(5, 59).time()));
+        assert!(veg.is_light(at(6, 0).time()));
+        assert!(veg.is_light(at(23, 59).time()));
+        assert!(!veg.is_light(at(0, 0).time()));
+
+        let flower = Photoperiod::flowering();
+        assert!(flower.is_light(at(17, 59).time()));
+        assert!(!flower.is_light(at(18, 0).time()));
+    }
+
+    #[test]
+    fn window_over_midnight() {
+        let window = LightWindow {
+            on: at(20, 0).time(),
+            off: at(2, 0).time(),
+        };
+        assert!(window.contains(at(23, 0).time()));
+        assert!(window.contains(at(1, 0).time()));
+        assert!(!window.contains(at(12, 0).time()));
+    }
+
+    #[test]
+    fn next_change() {
+        let flower = Photoperiod::flowering();
+        assert_eq!(
+            flower.next_change(at(6, 0).time()),
+            Duration::from_secs(12 * 3600)
+        );
+        assert_eq!(
+            flower.next_change(at(20, 0).time()),
+            Duration::from_secs(10 * 3600)
+        );
+    }
+
+    #[test]
+    fn restores_state_after_reboot() {
+        let mut controller = PhotoperiodController::new(Photoperiod::flowering(), None);
+        assert!(controller.update(at(12, 0), None).on);
+
+        let mut controller = PhotoperiodController::new(Photoperiod::flowering(), None);
+        assert!(!controller.update(at(22, 0), None).on);
+    }
+
+    #[test]
+    fn dull_day_is_extended() {
+        let period = Photoperiod::flowering().with_dli_target(10.0);
+        let mut controller = PhotoperiodController::new(period, None);
+        let mut now = at(6, 0);
+        // 5000 lux for 12 hours is only ~4 mol/m²
+        while now < at(18, 0) {
+            assert!(controller.update(now, Some(5000.0)).on);
+            now += chrono::Duration::minutes(5);
+        }
+        let step = controller.update(now, Some(5000.0));
+        assert!(step.on && step.extended);
+
+        // Extension stops at the maximum
+        let step = controller.update(at(21, 1), Some(5000.0));
+        assert!(!step.on);
+        assert!(!controller.update(at(21, 5), Some(5000.0)).on);
+    }
+
+    #[test]
+    fn window_ending_at_midnight() {
+        let period = Photoperiod::vegetative().with_dli_target(30.0);
+        assert_eq!(period.day_start(), at(6, 0).time());
+        let next_day = |hour, minute| at(hour, minute) + chrono::Duration::days(1);
+
+        // A bright day is not extended past midnight
+        let mut controller = PhotoperiodController::new(period.clone(), None);
+        let mut now = at(6, 0);
+        while now < next_day(0, 0) {
+            assert!(controller.update(now, Some(50_000.0)).on);
+            now += chrono::Duration::minutes(5);
+        }
+        assert!(controller.dli() > 30.0);
+        let step = controller.update(now, Some(0.0));
+        assert!(!step.on && !step.extended);
+
+        // A dull one is, up to the maximum
+        let mut controller = PhotoperiodController::new(period, None);
+        let mut now = at(6, 0);
+        while now < next_day(0, 0) {
+            controller.update(now, Some(5000.0));
+            now += chrono::Duration::minutes(5);
+        }
+        assert!(controller.update(now, Some(0.0)).extended);
+        assert!(controller.update(next_day(2, 50), Some(0.0)).on);
+        assert!(!controller.update(next_day(3, 5), Some(0.0)).on);
+        // The next light day starts over
+        assert!(controller.update(next_day(6, 0), Some(0.0)).on);
+        assert_eq!(controller.dli(), 0.0);
+    }
+
+    #[test]
+    fn reboot_during_a_tracked_day() {
+        let period = Photoperiod::flowering().with_dli_target(10.0);
+        let mut controller = PhotoperiodController::new(period.clone(), None);
+        let mut now = at(6, 0);
+        while now < at(12, 0) {
+            controller.update(now, Some(5000.0));
+            now += chrono::Duration::minutes(5);
+        }
+        let dli = controller.dli();
+        assert!(dli > 0.0);
+        let json = serde_json::to_string(&controller.light_day()).unwrap();
+
+        // The light of the morning still counts, the downtime adds nothing
+        let saved = serde_json::from_str(&json).unwrap();
+        let mut controller = PhotoperiodController::new(period.clone(), Some(saved));
+        assert!(controller.update(at(12, 30), Some(5000.0)).on);
+        assert_eq!(controller.dli(), dli);
+        let mut now = at(12, 35);
+        while now <= at(18, 0) {
+            controller.update(now, Some(5000.0));
+            now += chrono::Duration::minutes(5);
+        }
+        assert!(controller.light_day().on);
+        let extending = controller.light_day();
+
+        // A reboot during the extension carries on with it, the time off is not counted
+        let mut controller = PhotoperiodController::new(period.clone(), Some(extending));
+        assert!(controller.update(at(20, 0), Some(5000.0)).extended);
+        assert!(controller.update(at(22, 50), Some(5000.0)).extended);
+        assert!(!controller.update(at(23, 0), Some(5000.0)).on);
+
+        // A light day saved the day before is dropped
+        let mut controller = PhotoperiodController::new(period, Some(extending));
+        controller.update(at(6, 0) + chrono::Duration::days(1), Some(5000.0));
+        assert_eq!(controller.dli(), 0.0);
+    }
+
+    #[test]
+    fn bright_day_is_not_extended() {
+        let period = Photoperiod::flowering().with_dli_target(10.0);
+        let mut controller = PhotoperiodController::new(period, None);
+        controller.update(at(6, 0), Some(50_000.0));
+        controller.update(at(18, 0), Some(50_000.0));
+        assert!(controller.dli() > 10.0);
+        assert!(!controller.update(at(18, 0), Some(50_000.0)).on);
+    }
+}
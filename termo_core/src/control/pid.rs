@@ -0,0 +1,210 @@
+//! PID controller shared by the control loops.\
+//! The integral term is kept scaled by its gain, so gain changes don't bump the output. The
+//! derivative acts on the measurement, setpoint steps don't kick the output. Every term is scaled
+//! by the real time between the samples, irregular sampling doesn't change the tuning.
+//! Anti-windup: the integral is clamped to the output range and frozen while the output is
+//! saturated in the direction of the error.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Gains of a PID loop, the integral and derivative are per second
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pid {
+    gains: PidGains,
+    min: f32,
+    max: f32,
+    integral: f32,
+    last_measurement: Option<f32>,
+}
+
+impl Pid {
+    /// Controller with the output clamped to `min..=max`
+    pub fn new(gains: PidGains, min: f32, max: f32) -> Self {
+        Self {
+            gains,
+            min,
+            max,
+            integral: 0.0,
+            last_measurement: None,
+        }
+    }
+
+    pub fn gains(&self) -> PidGains {
+        self.gains
+    }
+
+    /// Change the gains without a bump in the output
+    pub fn set_gains(&mut self, gains: PidGains) {
+        self.gains = gains;
+    }
+
+    /// Forget the history, e.g. after a sensor fault
+    pub fn reset(&mut self) {
+        self.integral = 0.0;
+        self.last_measurement = None;
+    }
+
+    /// Output for the `measurement` taken `dt` after the previous one
+    pub fn update(&mut self, setpoint: f32, measurement: f32, dt: Duration) -> f32 {
+        let PidGains { kp, ki, kd } = self.gains;
+        let dt = dt.as_secs_f32();
+        let error = setpoint - measurement;
+
+        let derivative = match self.last_measurement {
+            Some(last) if dt > 0.0 => (measurement - last) / dt,
+            _ => 0.0,
+        };
+        self.last_measurement = Some(measurement);
+
+        let proportional = kp * error;
+        let integral = (self.integral + ki * error * dt).clamp(self.min, self.max);
+        let output = proportional + integral - kd * derivative;
+
+        // Don't integrate further into a saturated output
+        let winding_up = (output > self.max && error > 0.0) || (output < self.min && error < 0.0);
+        if !winding_up {
+            self.integral = integral;
+        }
+        (proportional + self.integral - kd * derivative).clamp(self.min, self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// First order thermal plant: heats with the output and loses heat to the ambient
+    struct Plant {
+        temperature: f32,
+        ambient: f32,
+        /// °C/s at full output
+        heating: f32,
+        /// Time constant of the heat loss in s
+        tau: f32,
+    }
+
+    impl Plant {
+        fn new() -> Self {
+            Self {
+                temperature: 15.0,
+                ambient: 15.0,
+                heating: 0.05,
+                tau: 600.0,
+            }
+        }
+
+        fn step(&mut self, output: f32, dt: f32) {
+            let loss = (self.temperature - self.ambient) / self.tau;
+            self.temperature += (self.heating * output - loss) * dt;
+        }
+    }
+
+    fn gains() -> PidGains {
+        PidGains {
+            kp: 0.5,
+            ki: 0.002,
+            kd: 5.0,
+        }
+    }
+
+    /// Run the closed loop, the temperature after every sample is returned
+    fn simulate(pid: &mut Pid, plant: &mut Plant, setpoint: f32, dt: f32, secs: f32) -> Vec<f32> {
+        let steps = (secs / dt) as usize;
+        (0..steps)
+            .map(|_| {
+                let output = pid.update(setpoint, plant.temperature, Duration::from_secs_f32(dt));
+                assert!((0.0..=1.0).contains(&output));
+                plant.step(output, dt);
+                plant.temperature
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reaches_the_setpoint() {
+        let mut pid = Pid::new(gains(), 0.0, 1.0);
+        let mut plant = Plant::new();
+        let trace = simulate(&mut pid, &mut plant, 22.0, 1.0, 4.0 * 3600.0);
+        let overshoot = trace.iter().cloned().fold(f32::MIN, f32::max) - 22.0;
+        assert!(overshoot < 0.5, "overshoot {overshoot}");
+        assert!((plant.temperature - 22.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn no_windup_while_saturated() {
+        let mut pid = Pid::new(gains(), 0.0, 1.0);
+        let mut plant = Plant::new();
+        // The heater can't reach the setpoint, the output is saturated for hours
+        plant.heating = 0.0;
+        simulate(&mut pid, &mut plant, 22.0, 1.0, 3.0 * 3600.0);
+        assert!(pid.integral <= 1.0);
+
+        // Once the heater works again the loop recovers without a large overshoot
+        plant.heating = 0.05;
+        let trace = simulate(&mut pid, &mut plant, 22.0, 1.0, 4.0 * 3600.0);
+        let overshoot = trace.iter().cloned().fold(f32::MIN, f32::max) - 22.0;
+        assert!(overshoot < 1.0, "overshoot {overshoot}");
+    }
+
+    #[test]
+    fn no_derivative_kick_on_setpoint_step() {
+        let mut pid = Pid::new(
+            PidGains {
+                kp: 0.1,
+                ki: 0.0,
+                kd: 100.0,
+            },
+            -10.0,
+            10.0,
+        );
+        let dt = Duration::from_secs(1);
+        pid.update(20.0, 20.0, dt);
+        assert_eq!(pid.update(21.0, 20.0, dt), 0.1);
+    }
+
+    #[test]
+    fn sample_time_independent() {
+        let mut fast = Plant::new();
+        let mut slow = Plant::new();
+        simulate(
+            &mut Pid::new(gains(), 0.0, 1.0),
+            &mut fast,
+            22.0,
+            0.5,
+            3600.0,
+        );
+        simulate(
+            &mut Pid::new(gains(), 0.0, 1.0),
+            &mut slow,
+            22.0,
+            5.0,
+            3600.0,
+        );
+        assert!((fast.temperature - slow.temperature).abs() < 0.2);
+    }
+
+    #[test]
+    fn bumpless_gain_change() {
+        let mut pid = Pid::new(gains(), 0.0, 1.0);
+        let dt = Duration::from_secs(1);
+        for _ in 0..100 {
+            pid.update(22.0, 21.5, dt);
+        }
+        let before = pid.update(22.0, 21.5, dt);
+        pid.set_gains(PidGains {
+            ki: 0.01,
+            ..gains()
+        });
+        let after = pid.update(22.0, 21.5, dt);
+        assert!((after - before).abs() < 0.01);
+    }
+}
@@ -0,0 +1,41 @@
+//! Linear ramps of PWM outputs, shared by the lamp fades and the pump soft start.
+
+use std::time::{Duration, Instant};
+
+/// Shortest time between two steps of a ramp
+pub const MIN_RAMP_STEP: Duration = Duration::from_millis(20);
+
+/// Linear ramp of a level from `from` to `to`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ramp {
+    pub from: f32,
+    pub to: f32,
+    pub start: Instant,
+    pub duration: Duration,
+}
+
+impl Ramp {
+    pub fn level_at(&self, now: Instant) -> f32 {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            return self.to;
+        }
+        let progress = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        self.from + (self.to - self.from) * progress
+    }
+
+    pub fn is_done(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.start) >= self.duration
+    }
+
+    /// Time until the next step, the ramp is cut in `steps` equal steps
+    pub fn next_step(&self, steps: f32, now: Instant) -> Duration {
+        let remaining = self
+            .duration
+            .saturating_sub(now.saturating_duration_since(self.start));
+        self.duration
+            .div_f32(steps.max(1.0))
+            .max(MIN_RAMP_STEP)
+            .min(remaining)
+    }
+}
@@ -0,0 +1,443 @@
+//! Safety interlocks of the pump output.\
+//! Every pump request passes through here, independent of the task asking for it. A trip
+//! switches the pump off and latches until it is acknowledged. The latched trip and the budget
+//! used are kept in an [`InterlockState`], so a reboot neither clears nor refills them.
+
+use std::time::{Duration, Instant};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// Length of the water budget window
+pub const BUDGET_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Limits of the pump
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SafetyConfig {
+    /// Longest single activation
+    pub max_on_time: Duration,
+    /// Rest of the pump between two activations
+    pub min_off_time: Duration,
+    /// Run time allowed in a day
+    pub daily_run_time: Duration,
+    /// Volume allowed in a day in ml, only checked if the flow rate is known
+    pub daily_volume: Option<f32>,
+    /// Flow rate of the pump in ml/s, set per zone from its calibration
+    pub flow_rate: Option<f32>,
+}
+
+impl Default for SafetyConfig {
+    fn default() -> Self {
+        Self {
+            max_on_time: Duration::from_secs(60),
+            min_off_time: Duration::from_secs(10),
+            daily_run_time: Duration::from_secs(10 * 60),
+            daily_volume: None,
+            flow_rate: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Trip {
+    /// A single activation ran longer than allowed
+    MaxOnTime,
+    /// Daily run time or volume used up
+    DailyBudget,
+    /// Tank level low, the pump would run dry
+    DryRun,
+}
+
+impl std::fmt::Display for Trip {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Trip::MaxOnTime => write!(f, "maximum on-time exceeded"),
+            Trip::DailyBudget => write!(f, "daily water budget used up"),
+            Trip::DryRun => write!(f, "tank level low"),
+        }
+    }
+}
+
+/// Outcome of a guarded pump request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Guarded {
+    /// State the pump has to be driven to
+    pub on: bool,
+    /// Set if an interlock tripped on this request
+    pub trip: Option<Trip>,
+}
+
+/// Part of the interlocks that has to survive a reboot
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct InterlockState {
+    pub trip: Option<Trip>,
+    /// Run time used in the budget window
+    pub run_time: Duration,
+    /// Volume used in the budget window in ml
+    pub volume: f32,
+    /// Time left of the budget window, the time spent powered off is not counted
+    pub window_left: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct PumpInterlock {
+    config: SafetyConfig,
+    on_since: Option<Instant>,
+    off_since: Option<Instant>,
+    budget_start: Option<Instant>,
+    run_today: Duration,
+    volume_today: f32,
+    trip: Option<Trip>,
+    /// The state changed since it was last taken with [`PumpInterlock::changed_state`]
+    changed: bool,
+}
+
+impl PumpInterlock {
+    pub fn new(config: SafetyConfig) -> Self {
+        Self {
+            config,
+            on_since: None,
+            off_since: None,
+            budget_start: None,
+            run_today: Duration::ZERO,
+            volume_today: 0.0,
+            trip: None,
+            changed: false,
+        }
+    }
+
+    /// Interlocks carrying on with the state saved before a reboot, the pump starts off
+    pub fn restored(config: SafetyConfig, state: InterlockState, now: Instant) -> Self {
+        let window_used = BUDGET_WINDOW.saturating_sub(state.window_left);
+        Self {
+            budget_start: now.checked_sub(window_used),
+            run_today: state.run_time,
+            volume_today: state.volume,
+            trip: state.trip,
+            ..Self::new(config)
+        }
+    }
+
+    /// State to save, `None` if it did not change since the last call
+    pub fn changed_state(&mut self, now: Instant) -> Option<InterlockState> {
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+        let window_left = self.budget_start.map_or(Duration::ZERO, |start| {
+            BUDGET_WINDOW.saturating_sub(now.duration_since(start))
+        });
+        Some(InterlockState {
+            trip: self.trip,
+            run_time: self.run_today,
+            volume: self.volume_today,
+            window_left,
+        })
+    }
+
+    pub fn config(&self) -> &SafetyConfig {
+        &self.config
+    }
+
+    /// Flow rate of the zone the pump feeds next, only change it while the pump is off
+    pub fn set_flow_rate(&mut self, flow_rate: Option<f32>) {
+        self.config.flow_rate = flow_rate;
+    }
+
+    /// Check a pump request.\
+    /// `tank_ok` is false when the tank level is low or unknown.
+    pub fn update(&mut self, want_on: bool, tank_ok: bool, now: Instant) -> Guarded {
+        self.roll_budget(now);
+        if self.trip.is_some() {
+            self.switch(false, now);
+            return Guarded {
+                on: false,
+                trip: None,
+            };
+        }
+
+        let trip = if !want_on {
+            None
+        } else if !tank_ok {
+            Some(Trip::DryRun)
+        } else if self.on_time(now) >= self.config.max_on_time {
+            Some(Trip::MaxOnTime)
+        } else if self.budget_used(now) {
+            Some(Trip::DailyBudget)
+        } else {
+            None
+        };
+        if let Some(trip) = trip {
+            warn!("Pump interlock tripped: {trip}");
+            self.trip = Some(trip);
+            self.changed = true;
+        }
+
+        let resting = self.on_since.is_none()
+            && self.off_since.map_or(false, |off| {
+                now.duration_since(off) < self.config.min_off_time
+            });
+        let on = want_on && trip.is_none() && !resting;
+        self.switch(on, now);
+        Guarded { on, trip }
+    }
+
+    /// Time until the running pump has to be stopped by an interlock
+    pub fn time_to_cutoff(&self, now: Instant) -> Option<Duration> {
+        self.on_since?;
+        let on_left = self.config.max_on_time.saturating_sub(self.on_time(now));
+        let budget_left = self
+            .config
+            .daily_run_time
+            .saturating_sub(self.run_time(now));
+        Some(on_left.min(budget_left))
+    }
+
+    /// Clear a latched trip, the tripped interlock is returned
+    pub fn acknowledge(&mut self) -> Option<Trip> {
+        let trip = self.trip.take();
+        self.changed |= trip.is_some();
+        trip
+    }
+
+    pub fn trip(&self) -> Option<Trip> {
+        self.trip
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on_since.is_some()
+    }
+
+    /// Run time in the current budget window
+    pub fn run_time(&self, now: Instant) -> Duration {
+        self.run_today + self.on_time(now)
+    }
+
+    /// Pumped volume in the current budget window in ml, if the flow rate is known.\
+    /// Runs at an unknown flow rate are not counted.
+    pub fn volume(&self, now: Instant) -> Option<f32> {
+        self.config
+            .flow_rate
+            .map(|flow| self.volume_today + flow * self.on_time(now).as_secs_f32())
+    }
+
+    pub fn to_json(&self, now: Instant) -> Value {
+        json!({
+            "on": self.is_on(),
+            "tripped": self.trip.map(|trip| trip.to_string()),
+            "run_time": self.run_time(now).as_secs(),
+            "volume": self.volume(now),
+        })
+    }
+
+    fn on_time(&self, now: Instant) -> Duration {
+        self.on_since
+            .map_or(Duration::ZERO, |since| now.duration_since(since))
+    }
+
+    fn budget_used(&self, now: Instant) -> bool {
+        let volume_used = self
+            .config
+            .daily_volume
+            .zip(self.volume(now))
+            .map_or(false, |(limit, volume)| volume >= limit);
+        volume_used || self.run_time(now) >= self.config.daily_run_time
+    }
+
+    fn roll_budget(&mut self, now: Instant) {
+        match self.budget_start {
+            Some(start) if now.duration_since(start) < BUDGET_WINDOW => {}
+            _ => {
+                self.budget_start = Some(now);
+                self.run_today = Duration::ZERO;
+                self.volume_today = 0.0;
+                self.changed = true;
+            }
+        }
+    }
+
+    fn switch(&mut self, on: bool, now: Instant) {
+        match (on, self.on_since) {
+            (true, None) => self.on_since = Some(now),
+            (false, Some(since)) => {
+                let run = now.duration_since(since);
+                self.run_today += run;
+                if let Some(flow) = self.config.flow_rate {
+                    self.volume_today += flow * run.as_secs_f32();
+                }
+                self.on_since = None;
+                self.off_since = Some(now);
+                self.changed = true;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn max_on_time_trips_and_latches() {
+        let mut interlock = PumpInterlock::new(SafetyConfig::default());
+        let start = Instant::now();
+        assert!(interlock.update(true, true, start).on);
+        assert_eq!(interlock.time_to_cutoff(start), Some(secs(60)));
+
+        let tripped = interlock.update(true, true, start + secs(60));
+        assert_eq!(
+            tripped,
+            Guarded {
+                on: false,
+                trip: Some(Trip::MaxOnTime)
+            }
+        );
+        // Latched until acknowledged
+        assert!(!interlock.update(true, true, start + secs(600)).on);
+        assert_eq!(interlock.acknowledge(), Some(Trip::MaxOnTime));
+        assert!(interlock.update(true, true, start + secs(601)).on);
+    }
+
+    #[test]
+    fn min_off_time_holds_without_trip() {
+        let mut interlock = PumpInterlock::new(SafetyConfig::default());
+        let start = Instant::now();
+        interlock.update(true, true, start);
+        interlock.update(false, true, start + secs(5));
+
+        let early = interlock.update(true, true, start + secs(10));
+        assert_eq!(
+            early,
+            Guarded {
+                on: false,
+                trip: None
+            }
+        );
+        assert!(interlock.update(true, true, start + secs(15)).on);
+    }
+
+    #[test]
+    fn daily_budget() {
+        let config = SafetyConfig {
+            daily_run_time: secs(30),
+            min_off_time: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut interlock = PumpInterlock::new(config);
+        let mut now = Instant::now();
+        for _ in 0..3 {
+            assert!(interlock.update(true, true, now).on);
+            now += secs(10);
+            interlock.update(false, true, now);
+        }
+        assert_eq!(interlock.run_time(now), secs(30));
+        assert_eq!(
+            interlock.update(true, true, now).trip,
+            Some(Trip::DailyBudget)
+        );
+
+        // A new window starts the next day
+        interlock.acknowledge();
+        assert!(interlock.update(true, true, now + BUDGET_WINDOW).on);
+    }
+
+    #[test]
+    fn volume_budget() {
+        let config = SafetyConfig {
+            daily_volume: Some(100.0),
+            flow_rate: Some(10.0),
+            ..Default::default()
+        };
+        let mut interlock = PumpInterlock::new(config);
+        let start = Instant::now();
+        interlock.update(true, true, start);
+        assert_eq!(interlock.volume(start + secs(5)), Some(50.0));
+        assert_eq!(
+            interlock.update(true, true, start + secs(10)).trip,
+            Some(Trip::DailyBudget)
+        );
+    }
+
+    #[test]
+    fn volume_of_zones_with_different_flow() {
+        let config = SafetyConfig {
+            min_off_time: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut interlock = PumpInterlock::new(config);
+        let start = Instant::now();
+        interlock.set_flow_rate(Some(10.0));
+        interlock.update(true, true, start);
+        interlock.update(false, true, start + secs(5));
+        interlock.set_flow_rate(Some(4.0));
+        interlock.update(true, true, start + secs(5));
+        assert_eq!(interlock.volume(start + secs(10)), Some(70.0));
+    }
+
+    #[test]
+    fn trip_and_budget_survive_a_reboot() {
+        let config = SafetyConfig {
+            daily_run_time: secs(30),
+            min_off_time: Duration::ZERO,
+            ..Default::default()
+        };
+        let mut interlock = PumpInterlock::new(config.clone());
+        let start = Instant::now();
+        interlock.update(true, true, start);
+        interlock.update(false, true, start + secs(20));
+        interlock.update(true, false, start + secs(60));
+        let state = interlock.changed_state(start + secs(60)).unwrap();
+        assert_eq!(interlock.changed_state(start + secs(61)), None);
+        assert_eq!(state.window_left, BUDGET_WINDOW - secs(60));
+
+        let json = serde_json::to_string(&state).unwrap();
+        let boot = start + secs(3600);
+        let mut restored =
+            PumpInterlock::restored(config, serde_json::from_str(&json).unwrap(), boot);
+        // Still latched and nothing to save until something changes
+        assert_eq!(restored.trip(), Some(Trip::DryRun));
+        assert!(!restored.update(true, true, boot).on);
+        assert_eq!(restored.changed_state(boot), None);
+
+        // The budget used before the reboot still counts
+        assert_eq!(restored.acknowledge(), Some(Trip::DryRun));
+        assert_eq!(restored.run_time(boot), secs(20));
+        assert!(restored.update(true, true, boot).on);
+        assert_eq!(
+            restored.update(true, true, boot + secs(10)).trip,
+            Some(Trip::DailyBudget)
+        );
+
+        // and the window ends a day after it started, without the time powered off
+        restored.acknowledge();
+        let window_end = boot + BUDGET_WINDOW - secs(60);
+        assert_eq!(
+            restored.update(true, true, window_end - secs(1)).trip,
+            Some(Trip::DailyBudget)
+        );
+        restored.acknowledge();
+        assert!(restored.update(true, true, window_end).on);
+    }
+
+    #[test]
+    fn dry_run_protection() {
+        let mut interlock = PumpInterlock::new(SafetyConfig::default());
+        let start = Instant::now();
+        assert!(interlock.update(true, true, start).on);
+        assert_eq!(
+            interlock.update(true, false, start + secs(1)),
+            Guarded {
+                on: false,
+                trip: Some(Trip::DryRun)
+            }
+        );
+        // Stopping is always allowed and never trips
+        interlock.acknowledge();
+        assert_eq!(interlock.update(false, false, start + secs(2)).trip, None);
+    }
+}
@@ -0,0 +1,139 @@
+//! Soft start and stop of a PWM driven DC pump.\
+//! The duty ramps up instead of stepping, the inrush current of the motor would brown out a
+//! USB powered board. The stop ramp is capped to the valve delay of the
+//! [`ZoneSequencer`](super::zones::ZoneSequencer), so the pump is still before a valve closes.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::{ramp::Ramp, zones::VALVE_DELAY};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SoftStartConfig {
+    /// Ramp from still to the full duty
+    pub soft_start: Duration,
+    /// Ramp from the full duty to still
+    pub soft_stop: Duration,
+    /// Duty in percent while running, lower for a gentle flow
+    pub duty: u8,
+}
+
+impl Default for SoftStartConfig {
+    fn default() -> Self {
+        Self {
+            soft_start: Duration::from_secs(2),
+            soft_stop: Duration::from_millis(500),
+            duty: 100,
+        }
+    }
+}
+
+pub struct SoftStart {
+    config: SoftStartConfig,
+    level: f32,
+    ramp: Option<Ramp>,
+}
+
+impl SoftStart {
+    pub fn new(mut config: SoftStartConfig) -> Self {
+        config.soft_stop = config.soft_stop.min(VALVE_DELAY);
+        config.duty = config.duty.min(100);
+        Self {
+            config,
+            level: 0.0,
+            ramp: None,
+        }
+    }
+
+    pub fn config(&self) -> &SoftStartConfig {
+        &self.config
+    }
+
+    /// Ramp to the running duty or to still.\
+    /// The ramp time is shortened by the part of the way already done, so an interrupted ramp
+    /// keeps its slope.
+    pub fn switch(&mut self, on: bool, now: Instant) {
+        let (to, full) = if on {
+            (f32::from(self.config.duty), self.config.soft_start)
+        } else {
+            (0.0, self.config.soft_stop)
+        };
+        if self.ramp.map_or(self.level, |ramp| ramp.to) == to {
+            return;
+        }
+        let from = self.update(now);
+        let share = (to - from).abs() / f32::from(self.config.duty.max(1));
+        self.ramp = Some(Ramp {
+            from,
+            to,
+            start: now,
+            duration: full.mul_f32(share.min(1.0)),
+        });
+    }
+
+    /// Duty in percent at `now`, a finished ramp is dropped
+    pub fn update(&mut self, now: Instant) -> f32 {
+        if let Some(ramp) = self.ramp {
+            self.level = ramp.level_at(now);
+            if ramp.is_done(now) {
+                self.ramp = None;
+            }
+        }
+        self.level
+    }
+
+    /// Time until the next step of a running ramp, one percent per step
+    pub fn next_step(&self, now: Instant) -> Option<Duration> {
+        let ramp = self.ramp?;
+        Some(ramp.next_step((ramp.to - ramp.from).abs(), now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn ramps_up_and_down() {
+        let mut pump = SoftStart::new(SoftStartConfig {
+            duty: 80,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        pump.switch(true, start);
+        assert_eq!(pump.update(start + millis(1000)), 40.0);
+        assert_eq!(pump.update(start + millis(2000)), 80.0);
+        assert_eq!(pump.next_step(start + millis(2000)), None);
+
+        let stop = start + millis(2000);
+        pump.switch(false, stop);
+        assert_eq!(pump.update(stop + millis(250)), 40.0);
+        assert_eq!(pump.update(stop + millis(500)), 0.0);
+    }
+
+    #[test]
+    fn interrupted_ramp_keeps_slope() {
+        let mut pump = SoftStart::new(SoftStartConfig::default());
+        let start = Instant::now();
+        pump.switch(true, start);
+        // Stopped at half duty, the stop ramp takes half the time
+        let stop = start + millis(1000);
+        pump.switch(false, stop);
+        assert_eq!(pump.update(stop + millis(125)), 25.0);
+        assert_eq!(pump.update(stop + millis(250)), 0.0);
+    }
+
+    #[test]
+    fn stop_ramp_ends_before_valves_close() {
+        let pump = SoftStart::new(SoftStartConfig {
+            soft_stop: Duration::from_secs(5),
+            ..Default::default()
+        });
+        assert_eq!(pump.config().soft_stop, VALVE_DELAY);
+    }
+}
@@ -0,0 +1,383 @@
+//! Thermostat of the heater relay and the exhaust fan.\
+//! The heater follows the day or night setpoint, either with a hysteresis band or with a PID
+//! duty cycle spread over a time window. The fan vents on overheating and on high humidity.
+//! Both relays respect minimum on and off times.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::pid::{Pid, PidGains};
+
+/// Interval of the thermostat updates
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum HeaterMode {
+    /// On below the band around the setpoint, off above it
+    Hysteresis,
+    /// Duty cycle from a PID loop, the heater is on for that share of every window
+    Pid(PidGains),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ThermostatConfig {
+    /// Temperature setpoint in °C while the lights are on
+    pub day_setpoint: f32,
+    /// Temperature setpoint in °C while the lights are off
+    pub night_setpoint: f32,
+    /// Width of the switching band in °C
+    pub hysteresis: f32,
+    pub mode: HeaterMode,
+    /// Time proportioning window of the PID mode
+    pub pid_window: Duration,
+    /// Shortest on time of a relay
+    pub min_on_time: Duration,
+    /// Shortest off time of a relay
+    pub min_off_time: Duration,
+    /// The fan vents above the setpoint plus this many °C
+    pub fan_above: f32,
+    /// The fan vents above this relative humidity in %
+    pub max_humidity: Option<f32>,
+    /// Width of the humidity band in %
+    pub humidity_hysteresis: f32,
+}
+
+impl Default for ThermostatConfig {
+    fn default() -> Self {
+        Self {
+            day_setpoint: 24.0,
+            night_setpoint: 18.0,
+            hysteresis: 1.0,
+            mode: HeaterMode::Hysteresis,
+            pid_window: Duration::from_secs(10 * 60),
+            min_on_time: Duration::from_secs(2 * 60),
+            min_off_time: Duration::from_secs(2 * 60),
+            fan_above: 3.0,
+            max_humidity: Some(70.0),
+            humidity_hysteresis: 5.0,
+        }
+    }
+}
+
+/// Relay state with the time of the last switch
+#[derive(Debug, Clone, Copy)]
+struct Relay {
+    on: bool,
+    since: Option<Instant>,
+}
+
+impl Relay {
+    /// Switch to `on` if the minimum cycle times allow it
+    fn switch(&mut self, on: bool, now: Instant, config: &ThermostatConfig) {
+        let min_time = if self.on {
+            config.min_on_time
+        } else {
+            config.min_off_time
+        };
+        let settled = self
+            .since
+            .map_or(true, |since| now.duration_since(since) >= min_time);
+        if on != self.on && settled {
+            self.force(on, now);
+        }
+    }
+
+    fn force(&mut self, on: bool, now: Instant) {
+        if on != self.on {
+            self.on = on;
+            self.since = Some(now);
+        }
+    }
+}
+
+/// PID loop of the heater, its output is the duty of the window
+#[derive(Debug, Clone)]
+struct PidState {
+    pid: Pid,
+    last: Option<Instant>,
+    window_start: Option<Instant>,
+    duty: f32,
+}
+
+impl PidState {
+    fn new(gains: PidGains) -> Self {
+        Self {
+            pid: Pid::new(gains, 0.0, 1.0),
+            last: None,
+            window_start: None,
+            duty: 0.0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.pid.reset();
+        self.last = None;
+        self.window_start = None;
+    }
+}
+
+/// Outputs and readings of the last update
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClimateState {
+    pub temperature: Option<f32>,
+    pub humidity: Option<f32>,
+    pub setpoint: f32,
+    pub heater: bool,
+    pub fan: bool,
+}
+
+impl ClimateState {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "temperature": self.temperature,
+            "humidity": self.humidity,
+            "setpoint": self.setpoint,
+            "heater": self.heater,
+            "fan": self.fan,
+        })
+    }
+}
+
+pub struct Thermostat {
+    config: ThermostatConfig,
+    heater: Relay,
+    fan: Relay,
+    pid: Option<PidState>,
+    state: ClimateState,
+}
+
+impl Thermostat {
+    pub fn new(config: ThermostatConfig) -> Self {
+        let off = Relay {
+            on: false,
+            since: None,
+        };
+        let setpoint = config.night_setpoint;
+        Self {
+            config,
+            heater: off,
+            fan: off,
+            pid: None,
+            state: ClimateState {
+                temperature: None,
+                humidity: None,
+                setpoint,
+                heater: false,
+                fan: false,
+            },
+        }
+    }
+
+    pub fn config(&self) -> &ThermostatConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> ClimateState {
+        self.state
+    }
+
+    /// Switch the heater to the PID mode with `gains`, a running loop keeps its state
+    pub fn set_gains(&mut self, gains: PidGains) {
+        self.config.mode = HeaterMode::Pid(gains);
+        match &mut self.pid {
+            Some(state) => state.pid.set_gains(gains),
+            None => self.pid = Some(PidState::new(gains)),
+        }
+    }
+
+    /// Decide the relay states, `None` readings are sensor faults.\
+    /// A faulty temperature switches the heater off right away.
+    pub fn update(
+        &mut self,
+        temperature: Option<f32>,
+        humidity: Option<f32>,
+        day: bool,
+        now: Instant,
+    ) -> ClimateState {
+        let setpoint = if day {
+            self.config.day_setpoint
+        } else {
+            self.config.night_setpoint
+        };
+
+        match temperature {
+            Some(temperature) => {
+                let heat = self.heat(temperature, setpoint, now);
+                self.heater.switch(heat, now, &self.config);
+            }
+            None => {
+                if let Some(state) = &mut self.pid {
+                    state.reset();
+                }
+                self.heater.force(false, now);
+            }
+        }
+
+        let band = self.config.hysteresis / 2.0;
+        let too_hot = temperature.map(|temperature| {
+            let limit = setpoint + self.config.fan_above;
+            if self.fan.on {
+                temperature > limit - band
+            } else {
+                temperature > limit + band
+            }
+        });
+        let too_humid = humidity
+            .zip(self.config.max_humidity)
+            .map(|(humidity, max)| {
+                let band = self.config.humidity_hysteresis / 2.0;
+                if self.fan.on {
+                    humidity > max - band
+                } else {
+                    humidity > max + band
+                }
+            });
+        let vent = too_hot.unwrap_or(false) || too_humid.unwrap_or(false);
+        self.fan.switch(vent, now, &self.config);
+
+        self.state = ClimateState {
+            temperature,
+            humidity,
+            setpoint,
+            heater: self.heater.on,
+            fan: self.fan.on,
+        };
+        self.state
+    }
+
+    fn heat(&mut self, temperature: f32, setpoint: f32, now: Instant) -> bool {
+        match self.config.mode {
+            HeaterMode::Hysteresis => {
+                let band = self.config.hysteresis / 2.0;
+                if self.heater.on {
+                    temperature < setpoint + band
+                } else {
+                    temperature < setpoint - band
+                }
+            }
+            HeaterMode::Pid(gains) => {
+                let state = self.pid.get_or_insert_with(|| PidState::new(gains));
+                let window = self.config.pid_window;
+                let window_start = match state.window_start {
+                    Some(start) if now.duration_since(start) < window => start,
+                    _ => {
+                        // New window, the duty is recomputed once per window
+                        let dt = state
+                            .last
+                            .map(|last| now.duration_since(last))
+                            .unwrap_or_default();
+                        state.duty = state.pid.update(setpoint, temperature, dt);
+                        state.last = Some(now);
+                        state.window_start = Some(now);
+                        now
+                    }
+                };
+                now.duration_since(window_start) < window.mul_f32(state.duty)
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.state.to_json()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ThermostatConfig {
+        ThermostatConfig {
+            min_on_time: Duration::ZERO,
+            min_off_time: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn hysteresis_band() {
+        let mut thermostat = Thermostat::new(config());
+        let now = Instant::now();
+        assert!(thermostat.update(Some(23.0), None, true, now).heater);
+        // Keeps heating inside the band
+        assert!(thermostat.update(Some(24.4), None, true, now).heater);
+        assert!(!thermostat.update(Some(24.6), None, true, now).heater);
+        assert!(!thermostat.update(Some(23.6), None, true, now).heater);
+    }
+
+    #[test]
+    fn night_setpoint() {
+        let mut thermostat = Thermostat::new(config());
+        let state = thermostat.update(Some(20.0), None, false, Instant::now());
+        assert_eq!(state.setpoint, 18.0);
+        assert!(!state.heater);
+    }
+
+    #[test]
+    fn minimum_cycle_times() {
+        let mut thermostat = Thermostat::new(ThermostatConfig::default());
+        let start = Instant::now();
+        assert!(thermostat.update(Some(20.0), None, true, start).heater);
+        // Too short on time, the heater keeps running
+        let early = start + Duration::from_secs(60);
+        assert!(thermostat.update(Some(26.0), None, true, early).heater);
+        let late = start + Duration::from_secs(120);
+        assert!(!thermostat.update(Some(26.0), None, true, late).heater);
+    }
+
+    #[test]
+    fn sensor_fault_stops_heater() {
+        let mut thermostat = Thermostat::new(ThermostatConfig::default());
+        let now = Instant::now();
+        assert!(thermostat.update(Some(20.0), None, true, now).heater);
+        assert!(!thermostat.update(None, None, true, now).heater);
+    }
+
+    #[test]
+    fn fan_vents_heat_and_humidity() {
+        let mut thermostat = Thermostat::new(config());
+        let now = Instant::now();
+        assert!(!thermostat.update(Some(25.0), Some(60.0), true, now).fan);
+        assert!(thermostat.update(Some(28.0), Some(60.0), true, now).fan);
+        assert!(!thermostat.update(Some(25.0), Some(60.0), true, now).fan);
+        assert!(thermostat.update(Some(25.0), Some(75.0), true, now).fan);
+        assert!(thermostat.update(Some(25.0), Some(68.0), true, now).fan);
+        assert!(!thermostat.update(Some(25.0), Some(66.0), true, now).fan);
+    }
+
+    #[test]
+    fn pid_duty_cycle() {
+        let mut thermostat = Thermostat::new(ThermostatConfig {
+            mode: HeaterMode::Pid(PidGains {
+                kp: 0.25,
+                ki: 0.0,
+                kd: 0.0,
+            }),
+            ..config()
+        });
+        let start = Instant::now();
+        // 2°C below the setpoint, half of the window
+        assert!(thermostat.update(Some(22.0), None, true, start).heater);
+        let minute = |minutes: u64| start + Duration::from_secs(minutes * 60);
+        assert!(thermostat.update(Some(22.0), None, true, minute(4)).heater);
+        assert!(!thermostat.update(Some(22.0), None, true, minute(6)).heater);
+        // Next window
+        assert!(thermostat.update(Some(22.0), None, true, minute(10)).heater);
+    }
+
+    #[test]
+    fn tuning_switches_to_pid() {
+        let mut thermostat = Thermostat::new(config());
+        let start = Instant::now();
+        // Inside the hysteresis band the heater stays off
+        assert!(!thermostat.update(Some(23.8), None, true, start).heater);
+        thermostat.set_gains(PidGains {
+            kp: 0.5,
+            ki: 0.0,
+            kd: 0.0,
+        });
+        assert!(thermostat.update(Some(23.8), None, true, start).heater);
+    }
+}
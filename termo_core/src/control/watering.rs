@@ -0,0 +1,250 @@
+//! Closed loop watering driven by the soil moisture.
+//!
+//! The soil is watered in short pulses with soak time between them, until the moisture reaches
+//! the upper end of the target band of the plant profile. A new cycle only starts once the
+//! moisture drops below the lower end, so the pump does not chatter around a single threshold.
+
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use super::flow::FlowRate;
+use crate::profile::PlantProfile;
+
+/// How often the soil is checked while no watering is going on
+pub const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Longest pulse sized by a volume, the default limit of a single pump run
+const MAX_PULSE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WateringState {
+    /// Moisture is above the lower end of the band
+    Idle,
+    /// Pump is running until the end of the pulse
+    Watering { until: Instant, pulses: u32 },
+    /// Water soaks in before the next measurement
+    Soaking { until: Instant, pulses: u32 },
+}
+
+/// Why a watering cycle ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CycleEnd {
+    /// Upper end of the band reached
+    TargetReached,
+    /// All pulses used without reaching the target
+    PulseLimit,
+    /// Soil probe faulted or disconnected
+    ProbeFault,
+}
+
+/// Output of one controller step
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WateringStep {
+    /// Desired pump state
+    pub pump_on: bool,
+    /// Time until the controller wants to be updated again
+    pub next_check: Duration,
+    /// Set when a watering cycle ended in this step
+    pub cycle_end: Option<CycleEnd>,
+}
+
+pub struct WateringController {
+    profile: PlantProfile,
+    flow: Option<FlowRate>,
+    state: WateringState,
+}
+
+impl WateringController {
+    pub fn new(profile: PlantProfile) -> Self {
+        Self {
+            profile,
+            flow: None,
+            state: WateringState::Idle,
+        }
+    }
+
+    pub fn state(&self) -> WateringState {
+        self.state
+    }
+
+    pub fn profile(&self) -> &PlantProfile {
+        &self.profile
+    }
+
+    pub fn set_profile(&mut self, profile: PlantProfile) {
+        self.profile = profile;
+    }
+
+    /// Calibrated flow of the zone, pulses are then sized by the pulse volume of the profile
+    pub fn set_flow(&mut self, flow: Option<FlowRate>) {
+        self.flow = flow;
+    }
+
+    /// Pump time of one pulse
+    pub fn pulse_time(&self) -> Duration {
+        match (self.flow, self.profile.pulse_volume) {
+            (Some(flow), Some(volume)) => flow
+                .duration_for(volume, MAX_PULSE)
+                .unwrap_or(self.profile.pulse),
+            _ => self.profile.pulse,
+        }
+    }
+
+    /// Advance the controller.\
+    /// `moisture` is `None` if the probe faulted, any running cycle is stopped then.
+    pub fn update(&mut self, moisture: Option<f32>, now: Instant) -> WateringStep {
+        let Some(moisture) = moisture else {
+            let cycle_end = (self.state != WateringState::Idle).then(|| {
+                warn!("Soil probe fault, watering stopped");
+                CycleEnd::ProbeFault
+            });
+            self.state = WateringState::Idle;
+            return self.step(now, cycle_end);
+        };
+
+        let mut cycle_end = None;
+        self.state = match self.state {
+            WateringState::Idle if moisture < self.profile.moisture_low => {
+                info!("Soil at {moisture:.1}%, start watering");
+                self.pulse(now, 1)
+            }
+            WateringState::Idle => WateringState::Idle,
+            WateringState::Watering { until, pulses } if now >= until => WateringState::Soaking {
+                until: now + self.profile.soak,
+                pulses,
+            },
+            watering @ WateringState::Watering { .. } => watering,
+            WateringState::Soaking { until, pulses } if now >= until => {
+                if moisture >= self.profile.moisture_high {
+                    info!("Soil at {moisture:.1}%, watering done after {pulses} pulses");
+                    cycle_end = Some(CycleEnd::TargetReached);
+                    WateringState::Idle
+                } else if pulses >= self.profile.max_pulses {
+                    warn!("Soil at {moisture:.1}% after {pulses} pulses, target not reached");
+                    cycle_end = Some(CycleEnd::PulseLimit);
+                    WateringState::Idle
+                } else {
+                    self.pulse(now, pulses + 1)
+                }
+            }
+            soaking @ WateringState::Soaking { .. } => soaking,
+        };
+        self.step(now, cycle_end)
+    }
+
+    /// Stop any running cycle, e.g. on a manual stop
+    pub fn stop(&mut self) {
+        self.state = WateringState::Idle;
+    }
+
+    fn pulse(&self, now: Instant, pulses: u32) -> WateringState {
+        WateringState::Watering {
+            until: now + self.pulse_time(),
+            pulses,
+        }
+    }
+
+    fn step(&self, now: Instant, cycle_end: Option<CycleEnd>) -> WateringStep {
+        let (pump_on, next_check) = match self.state {
+            WateringState::Idle => (false, IDLE_CHECK_INTERVAL),
+            WateringState::Watering { until, .. } => (true, until.saturating_duration_since(now)),
+            WateringState::Soaking { until, .. } => (false, until.saturating_duration_since(now)),
+        };
+        WateringStep {
+            pump_on,
+            next_check,
+            cycle_end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> PlantProfile {
+        PlantProfile {
+            max_pulses: 3,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn waters_in_pulses_until_target() {
+        let mut controller = WateringController::new(profile());
+        let start = Instant::now();
+
+        let step = controller.update(Some(20.0), start);
+        assert!(step.pump_on);
+        assert_eq!(step.next_check, Duration::from_secs(3));
+
+        let mut now = start + step.next_check;
+        let step = controller.update(Some(20.0), now);
+        assert!(!step.pump_on);
+        assert_eq!(step.next_check, Duration::from_secs(60));
+
+        now += step.next_check;
+        let step = controller.update(Some(30.0), now);
+        assert!(step.pump_on, "second pulse below the target");
+
+        now += step.next_check;
+        now += controller.update(Some(30.0), now).next_check;
+        let step = controller.update(Some(40.0), now);
+        assert!(!step.pump_on);
+        assert_eq!(step.cycle_end, Some(CycleEnd::TargetReached));
+        assert_eq!(controller.state(), WateringState::Idle);
+    }
+
+    #[test]
+    fn hysteresis_band() {
+        let mut controller = WateringController::new(profile());
+        let now = Instant::now();
+        // Inside the band nothing starts
+        assert!(!controller.update(Some(30.0), now).pump_on);
+        assert!(!controller.update(Some(25.0), now).pump_on);
+        assert!(controller.update(Some(24.9), now).pump_on);
+    }
+
+    #[test]
+    fn pulse_limit_ends_cycle() {
+        let mut controller = WateringController::new(profile());
+        let mut now = Instant::now();
+        let mut pulses = 0;
+        let end = loop {
+            let step = controller.update(Some(10.0), now);
+            pulses += step.pump_on as u32;
+            if let Some(end) = step.cycle_end {
+                break end;
+            }
+            now += step.next_check;
+        };
+        assert_eq!(end, CycleEnd::PulseLimit);
+        assert_eq!(pulses, 3);
+    }
+
+    #[test]
+    fn probe_fault_stops_pump() {
+        let mut controller = WateringController::new(profile());
+        let now = Instant::now();
+        assert!(controller.update(Some(10.0), now).pump_on);
+
+        let step = controller.update(None, now + Duration::from_secs(1));
+        assert!(!step.pump_on);
+        assert_eq!(step.cycle_end, Some(CycleEnd::ProbeFault));
+        // A faulty probe never starts watering
+        assert!(!controller.update(None, now).pump_on);
+    }
+
+    #[test]
+    fn pulses_sized_by_volume() {
+        let mut controller = WateringController::new(PlantProfile {
+            pulse_volume: Some(50.0),
+            ..profile()
+        });
+        // Uncalibrated zones keep the timed pulse
+        assert_eq!(controller.pulse_time(), Duration::from_secs(3));
+        controller.set_flow(Some(FlowRate(10.0)));
+        let step = controller.update(Some(10.0), Instant::now());
+        assert_eq!(step.next_check, Duration::from_secs(5));
+    }
+}
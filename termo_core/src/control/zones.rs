@@ -0,0 +1,324 @@
+//! Sequencing of the watering zones sharing one pump.\
+//! Zones are watered one after the other: open valve, start pump, water, stop pump, close valve.
+//! The pump is only ever on while the valve of the watered zone is open.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+/// Time given to a valve to open before the pump starts and to the pressure to drop after it stops
+pub const VALVE_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZonePhase {
+    Idle,
+    /// Valve opening, pump still off
+    Opening {
+        zone: usize,
+        until: Instant,
+        duration: Duration,
+    },
+    /// Valve open and pump running
+    Watering {
+        zone: usize,
+        until: Instant,
+    },
+    /// Pump stopped, valve closes at the end
+    Closing {
+        zone: usize,
+        until: Instant,
+    },
+}
+
+/// State the valves and the pump have to be driven to
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ZoneOutputs {
+    /// The only open valve
+    pub valve: Option<usize>,
+    pub pump: bool,
+}
+
+impl ZoneOutputs {
+    /// The pump never runs against closed valves
+    pub fn is_safe(&self) -> bool {
+        !self.pump || self.valve.is_some()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ZoneError {
+    #[error("Unknown zone: {0}")]
+    UnknownZone(usize),
+    #[error("Zone {0} is already watering or waiting")]
+    Busy(usize),
+}
+
+pub struct ZoneSequencer {
+    zones: usize,
+    queue: VecDeque<(usize, Duration)>,
+    phase: ZonePhase,
+    /// Rest the pump interlock needs between two runs
+    pump_rest: Duration,
+    pump_off: Option<Instant>,
+}
+
+impl ZoneSequencer {
+    pub fn new(zones: usize) -> Self {
+        Self {
+            zones,
+            queue: VecDeque::new(),
+            phase: ZonePhase::Idle,
+            pump_rest: Duration::ZERO,
+            pump_off: None,
+        }
+    }
+
+    /// Keep the valve of the next zone opening until the pump rested for `rest`, so the run time
+    /// is not lost to the interlock
+    pub fn with_pump_rest(mut self, rest: Duration) -> Self {
+        self.pump_rest = rest;
+        self
+    }
+
+    /// Queue watering of `zone` for `duration`, returns whether it was queued.\
+    /// A zone already waiting or watered is not queued again.
+    pub fn request(&mut self, zone: usize, duration: Duration) -> Result<bool, ZoneError> {
+        if zone >= self.zones {
+            return Err(ZoneError::UnknownZone(zone));
+        }
+        let busy = self.active_zone() == Some(zone)
+            || self.queue.iter().any(|(queued, _)| *queued == zone);
+        if !busy {
+            self.queue.push_back((zone, duration));
+        }
+        Ok(!busy)
+    }
+
+    /// Stop watering `zone`, the valve closes after the pump stopped
+    pub fn stop(&mut self, zone: usize, now: Instant) {
+        self.queue.retain(|(queued, _)| *queued != zone);
+        if self.active_zone() == Some(zone) {
+            self.close(now);
+        }
+    }
+
+    pub fn stop_all(&mut self, now: Instant) {
+        self.queue.clear();
+        self.close(now);
+    }
+
+    pub fn phase(&self) -> ZonePhase {
+        self.phase
+    }
+
+    pub fn active_zone(&self) -> Option<usize> {
+        match self.phase {
+            ZonePhase::Idle => None,
+            ZonePhase::Opening { zone, .. }
+            | ZonePhase::Watering { zone, .. }
+            | ZonePhase::Closing { zone, .. } => Some(zone),
+        }
+    }
+
+    /// Watering time left of the active zone
+    pub fn remaining(&self, now: Instant) -> Option<Duration> {
+        match self.phase {
+            ZonePhase::Opening {
+                until, duration, ..
+            } => Some(until.saturating_duration_since(now) + duration),
+            ZonePhase::Watering { until, .. } => Some(until.saturating_duration_since(now)),
+            ZonePhase::Idle | ZonePhase::Closing { .. } => None,
+        }
+    }
+
+    /// Advance the sequence, returns the outputs and the time until the next step if any
+    pub fn update(&mut self, now: Instant) -> (ZoneOutputs, Option<Duration>) {
+        loop {
+            let next = match self.phase {
+                ZonePhase::Idle => match self.queue.pop_front() {
+                    Some((zone, duration)) => {
+                        info!("Watering zone {zone} for {}s", duration.as_secs());
+                        let rested = self.pump_off.map_or(now, |off| off + self.pump_rest);
+                        ZonePhase::Opening {
+                            zone,
+                            until: rested.max(now + VALVE_DELAY),
+                            duration,
+                        }
+                    }
+                    None => break,
+                },
+                ZonePhase::Opening {
+                    zone,
+                    until,
+                    duration,
+                } if now >= until => ZonePhase::Watering {
+                    zone,
+                    until: now + duration,
+                },
+                ZonePhase::Watering { zone, until } if now >= until => {
+                    self.pump_off = Some(now);
+                    ZonePhase::Closing {
+                        zone,
+                        until: now + VALVE_DELAY,
+                    }
+                }
+                ZonePhase::Closing { until, .. } if now >= until => ZonePhase::Idle,
+                _ => break,
+            };
+            self.phase = next;
+        }
+        (self.outputs(), self.next_step(now))
+    }
+
+    fn close(&mut self, now: Instant) {
+        if matches!(self.phase, ZonePhase::Watering { .. }) {
+            self.pump_off = Some(now);
+        }
+        self.phase = match self.phase {
+            ZonePhase::Opening { zone, .. } | ZonePhase::Watering { zone, .. } => {
+                ZonePhase::Closing {
+                    zone,
+                    until: now + VALVE_DELAY,
+                }
+            }
+            phase => phase,
+        };
+    }
+
+    fn outputs(&self) -> ZoneOutputs {
+        ZoneOutputs {
+            valve: self.active_zone(),
+            pump: matches!(self.phase, ZonePhase::Watering { .. }),
+        }
+    }
+
+    fn next_step(&self, now: Instant) -> Option<Duration> {
+        match self.phase {
+            ZonePhase::Idle => None,
+            ZonePhase::Opening { until, .. }
+            | ZonePhase::Watering { until, .. }
+            | ZonePhase::Closing { until, .. } => Some(until.saturating_duration_since(now)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the sequencer to the end, the outputs of every step are returned
+    fn run(sequencer: &mut ZoneSequencer, mut now: Instant) -> Vec<ZoneOutputs> {
+        let mut steps = vec![];
+        while let (outputs, Some(next)) = sequencer.update(now) {
+            assert!(outputs.is_safe(), "unsafe outputs {outputs:?}");
+            steps.push(outputs);
+            now += next;
+        }
+        steps
+    }
+
+    #[test]
+    fn zones_run_one_after_the_other() {
+        let mut sequencer = ZoneSequencer::new(2);
+        sequencer.request(1, Duration::from_secs(5)).unwrap();
+        sequencer.request(0, Duration::from_secs(3)).unwrap();
+
+        let open = |zone| ZoneOutputs {
+            valve: Some(zone),
+            pump: false,
+        };
+        let watering = |zone| ZoneOutputs {
+            valve: Some(zone),
+            pump: true,
+        };
+        assert_eq!(
+            run(&mut sequencer, Instant::now()),
+            vec![open(1), watering(1), open(1), open(0), watering(0), open(0)]
+        );
+        assert_eq!(sequencer.update(Instant::now()).0, ZoneOutputs::default());
+    }
+
+    #[test]
+    fn back_to_back_zones_wait_for_the_pump_rest() {
+        let rest = Duration::from_secs(10);
+        let mut sequencer = ZoneSequencer::new(2).with_pump_rest(rest);
+        sequencer.request(0, Duration::from_secs(5)).unwrap();
+        sequencer.request(1, Duration::from_secs(5)).unwrap();
+        let start = Instant::now();
+        let mut now = start;
+        let mut pump_runs = vec![];
+        let mut pump_on = false;
+        while let (outputs, Some(next)) = sequencer.update(now) {
+            if outputs.pump != pump_on {
+                pump_runs.push(now - start);
+                pump_on = outputs.pump;
+            }
+            now += next;
+        }
+        let secs = Duration::from_secs;
+        // The second zone opens 2s after the first one closed but starts pumping after the rest
+        assert_eq!(pump_runs, vec![secs(1), secs(6), secs(16), secs(21)]);
+        // Each zone gets its full run time
+        assert_eq!(pump_runs[1] - pump_runs[0], secs(5));
+        assert_eq!(pump_runs[3] - pump_runs[2], secs(5));
+        assert!(pump_runs[2] - pump_runs[1] >= rest);
+    }
+
+    #[test]
+    fn duplicate_and_unknown_requests() {
+        let mut sequencer = ZoneSequencer::new(2);
+        assert!(sequencer.request(0, Duration::from_secs(3)).unwrap());
+        sequencer.update(Instant::now());
+        assert!(!sequencer.request(0, Duration::from_secs(3)).unwrap());
+        assert!(sequencer.queue.is_empty());
+        assert!(sequencer.request(1, Duration::from_secs(3)).unwrap());
+        assert!(!sequencer.request(1, Duration::from_secs(5)).unwrap());
+        assert_eq!(sequencer.queue.len(), 1);
+        assert!(matches!(
+            sequencer.request(2, Duration::from_secs(3)),
+            Err(ZoneError::UnknownZone(2))
+        ));
+    }
+
+    #[test]
+    fn stop_closes_after_pump() {
+        let mut sequencer = ZoneSequencer::new(1);
+        let now = Instant::now();
+        sequencer.request(0, Duration::from_secs(30)).unwrap();
+        sequencer.update(now);
+        let (outputs, _) = sequencer.update(now + VALVE_DELAY);
+        assert!(outputs.pump);
+
+        sequencer.stop(0, now + Duration::from_secs(2));
+        let (outputs, next) = sequencer.update(now + Duration::from_secs(2));
+        assert_eq!(
+            outputs,
+            ZoneOutputs {
+                valve: Some(0),
+                pump: false
+            }
+        );
+        assert_eq!(next, Some(VALVE_DELAY));
+        let (outputs, _) = sequencer.update(now + Duration::from_secs(3));
+        assert_eq!(outputs, ZoneOutputs::default());
+    }
+
+    #[test]
+    fn remaining_watering_time() {
+        let mut sequencer = ZoneSequencer::new(1);
+        let now = Instant::now();
+        assert_eq!(sequencer.remaining(now), None);
+        sequencer.request(0, Duration::from_secs(20)).unwrap();
+        sequencer.update(now);
+        assert_eq!(sequencer.remaining(now), Some(Duration::from_secs(21)));
+        let watering = now + VALVE_DELAY;
+        sequencer.update(watering);
+        assert_eq!(
+            sequencer.remaining(watering + Duration::from_secs(5)),
+            Some(Duration::from_secs(15))
+        );
+    }
+}
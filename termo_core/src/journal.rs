@@ -0,0 +1,107 @@
+//! Journal of the actuator states, persisted so a reboot can restore them safely.\
+//! The lamp level is restored and the photoperiod carries on with the light day. The heater and
+//! the fan are not journaled, they start off until the thermostat has a valid reading. A watering
+//! that was running is never resumed, it is taken out of the journal and reported as interrupted.
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::control::photoperiod::LightDay;
+
+/// Watering of a zone that was in flight
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WateringRecord {
+    pub zone: String,
+    /// Wall clock start, `None` if the clock was not synced yet
+    pub started: Option<NaiveDateTime>,
+}
+
+/// Last states of the actuators, the setters return whether the journal changed so only changes
+/// need to be written
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Journal {
+    /// Lamp level in percent
+    pub lamp: u8,
+    pub watering: Option<WateringRecord>,
+    /// Light day of the photoperiod, journaled with the lamp level
+    #[serde(default)]
+    pub light_day: Option<LightDay>,
+}
+
+impl Journal {
+    pub fn set_lamp(&mut self, level: u8) -> bool {
+        replace(&mut self.lamp, level)
+    }
+
+    pub fn set_light_day(&mut self, day: LightDay) -> bool {
+        replace(&mut self.light_day, Some(day))
+    }
+
+    /// `None` once the watering finished
+    pub fn set_watering(&mut self, watering: Option<WateringRecord>) -> bool {
+        replace(&mut self.watering, watering)
+    }
+
+    /// Take out the watering interrupted by the reboot, it must not be resumed
+    pub fn take_interrupted(&mut self) -> Option<WateringRecord> {
+        self.watering.take()
+    }
+}
+
+fn replace<T: PartialEq>(field: &mut T, value: T) -> bool {
+    let changed = *field != value;
+    *field = value;
+    changed
+}
+
+/// Report of a watering interrupted by a reboot, `reason` is the reset reason
+pub fn get_interrupted_message(record: &WateringRecord, reason: &str) -> String {
+    let started = match record.started {
+        Some(started) => format!(" started at {}", started.format("%Y-%m-%d %H:%M")),
+        None => String::new(),
+    };
+    format!(
+        "Watering interrupted :warning:\\n> {}{started} was cut by a reboot ( {reason} ), it is not resumed",
+        record.zone
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn reports_changes() {
+        let mut journal = Journal::default();
+        assert!(journal.set_lamp(80));
+        assert!(!journal.set_lamp(80));
+        assert!(journal.set_light_day(LightDay::default()));
+        assert!(!journal.set_light_day(LightDay::default()));
+    }
+
+    #[test]
+    fn watering_is_not_resumed() {
+        let started = NaiveDate::from_ymd_opt(2023, 6, 1)
+            .unwrap()
+            .and_hms_opt(8, 30, 0);
+        let mut journal = Journal::default();
+        journal.set_lamp(60);
+        journal.set_watering(Some(WateringRecord {
+            zone: "pot 1".to_string(),
+            started,
+        }));
+
+        let json = serde_json::to_string(&journal).unwrap();
+        let mut restored: Journal = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, journal);
+
+        let interrupted = restored.take_interrupted().unwrap();
+        assert_eq!(restored.watering, None);
+        assert_eq!(restored.lamp, 60);
+        assert_eq!(
+            get_interrupted_message(&interrupted, "panic"),
+            r"Watering interrupted :warning:\n> pot 1 started at 2023-06-01 08:30 was cut by a reboot ( panic ), it is not resumed"
+        );
+    }
+}
@@ -0,0 +1,18 @@
+//! Hardware independent core of the plant station.\
+//! Domain logic, command parsing, scheduling math and message formatting live here, so they can
+//! be built and tested on the host. The peripherals and ESP-IDF services are in the firmware crate.
+
+pub mod clock;
+pub mod command;
+pub mod config;
+pub mod control;
+pub mod journal;
+pub mod profile;
+pub mod report;
+pub mod rules;
+pub mod schedule;
+pub mod sensor;
+pub mod sun;
+pub mod time_sync;
+pub mod timezone;
+pub mod trigger;
@@ -0,0 +1,39 @@
+//! Plant profiles, the care targets of the grown plants.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Watering targets of a plant
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlantProfile {
+    pub name: String,
+    /// Watering starts when the soil moisture drops below this precentage
+    pub moisture_low: f32,
+    /// Watering stops once the soil moisture reaches this precentage
+    pub moisture_high: f32,
+    /// Pump on time of one watering pulse
+    pub pulse: Duration,
+    /// Water volume of one pulse in ml, it replaces `pulse` once the flow of the zone is calibrated
+    #[serde(default)]
+    pub pulse_volume: Option<f32>,
+    /// Time given to the water to soak in before the soil is measured again
+    pub soak: Duration,
+    /// Upper limit of pulses in one watering cycle, in case the target is never reached
+    pub max_pulses: u32,
+}
+
+impl Default for PlantProfile {
+    /// Keeps the soil in the optimal band of [`SoilStatus`](crate::sensor::status::SoilStatus)
+    fn default() -> Self {
+        Self {
+            name: "generic".to_string(),
+            moisture_low: 25.0,
+            moisture_high: 38.0,
+            pulse: Duration::from_secs(3),
+            pulse_volume: None,
+            soak: Duration::from_secs(60),
+            max_pulses: 10,
+        }
+    }
+}
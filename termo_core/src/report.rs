@@ -0,0 +1,164 @@
+//! Messages of the daily report sent to discord.
+
+use std::fmt::Display;
+
+use crate::{control::thermostat::ClimateState, sensor::Sensor};
+
+pub fn get_message<S, H, T>(soil_sensor: &mut S, hum_sensor: &mut H, temp_sensor: &mut T) -> String
+where
+    S: Sensor,
+    S::Status: Display,
+    H: Sensor,
+    T: Sensor,
+{
+    let status = match soil_sensor.get_status() {
+        Ok(status) => status.to_string(),
+        Err(_) => "Sensor not connected".to_string(),
+    };
+    let soil = printer(soil_sensor);
+    let hum = printer(hum_sensor);
+    let temp = printer(temp_sensor);
+
+    format!(
+        r#"
+                    Good morning! :sun_with_face:
+                    Here is the daily report:
+                    > Soil moisture: {soil}{}
+                    > Soil moisture status: **{status}**
+                    > Temperature: **{temp}{}**
+                    > Humidity: **{hum}{}**
+                    "#,
+        soil_sensor.get_unit(),
+        temp_sensor.get_unit(),
+        hum_sensor.get_unit()
+    )
+    .replace('\n', r"\n")
+    .replace("  ", "")
+}
+
+/// Report lines of the hydroponic tub, appended to the daily message
+pub fn get_hydro_message<S1, S2>(ph_probe: &mut S1, ec_probe: &mut S2) -> String
+where
+    S1: Sensor,
+    S1::Status: Display,
+    S2: Sensor,
+    S2::Status: Display,
+{
+    fn status<S: Sensor>(s: &mut S) -> String
+    where
+        S::Status: Display,
+    {
+        match s.get_status() {
+            Ok(status) => status.to_string(),
+            Err(_) => "Sensor not connected".to_string(),
+        }
+    }
+    let ph = printer(ph_probe);
+    let ph_status = status(ph_probe);
+    let ec = printer(ec_probe);
+    let ec_status = status(ec_probe);
+
+    format!(
+        r#"> pH: **{ph}** ({ph_status})
+                    > EC: **{ec}{}** ({ec_status})
+                    "#,
+        ec_probe.get_unit()
+    )
+    .replace('\n', r"\n")
+    .replace("  ", "")
+}
+
+/// Report lines of the thermostat, appended to the daily message
+pub fn get_climate_message(state: &ClimateState) -> String {
+    fn on_off(on: bool) -> &'static str {
+        if on {
+            "on"
+        } else {
+            "off"
+        }
+    }
+    format!(
+        r#"> Heater: **{}** (setpoint {:.1}°C)
+                    > Fan: **{}**
+                    "#,
+        on_off(state.heater),
+        state.setpoint,
+        on_off(state.fan),
+    )
+    .replace('\n', r"\n")
+    .replace("  ", "")
+}
+
+fn printer<S: Sensor>(s: &mut S) -> String {
+    match s.get_measurment() {
+        Ok(value) => format!("{:.1}", value),
+        Err(_) => "Sensor not connected".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::{
+        sim::{Failure, Signal, SimulatedSensor},
+        status::{CropRange, EcStatus, HumidityStatus, PhStatus, SoilStatus, TempStatus},
+    };
+
+    #[test]
+    fn daily_report() {
+        let mut soil = SimulatedSensor::new(
+            "soil moisture",
+            "%",
+            Signal::constant(32.54),
+            SoilStatus::from_precentage,
+        );
+        let mut hum = SimulatedSensor::new(
+            "humidity",
+            "%",
+            Signal::constant(45.0),
+            HumidityStatus::from_precentage,
+        );
+        let mut temp = SimulatedSensor::new(
+            "temperature",
+            "°C",
+            Signal::constant(21.0),
+            TempStatus::from_celsius,
+        )
+        .with_failure(Failure::Disconnected);
+
+        let message = get_message(&mut soil, &mut hum, &mut temp);
+        assert!(!message.contains('\n'));
+        assert!(message.contains(r"> Soil moisture: 32.5%\n"));
+        assert!(message.contains("> Soil moisture status: **Optimal 💚**"));
+        assert!(message.contains("> Temperature: **Sensor not connected°C**"));
+        assert!(message.contains("> Humidity: **45.0%**"));
+    }
+
+    #[test]
+    fn hydro_report() {
+        let mut ph = SimulatedSensor::new("pH", "pH", Signal::constant(5.0), |ph| {
+            PhStatus::from(CropRange { min: 5.5, max: 6.5 }.status(ph))
+        });
+        let mut ec =
+            SimulatedSensor::new("EC", "mS/cm", Signal::constant(1.8), |_| EcStatus::Optimal);
+        assert_eq!(
+            get_hydro_message(&mut ph, &mut ec),
+            r"> pH: **5.0** (Acidic 🍋)\n> EC: **1.8mS/cm** (Optimal 💚)\n"
+        );
+    }
+
+    #[test]
+    fn climate_report() {
+        let state = ClimateState {
+            temperature: Some(17.2),
+            humidity: Some(75.0),
+            setpoint: 18.0,
+            heater: true,
+            fan: true,
+        };
+        assert_eq!(
+            get_climate_message(&state),
+            r"> Heater: **on** (setpoint 18.0°C)\n> Fan: **on**\n"
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! Declarative automation rules.\
+//! A rule runs its action when all of its conditions hold, e.g. water a zone when the soil is dry
+//! during the day. Rules are plain JSON so they can be changed without flashing, and the
+//! evaluation is pure: it only looks at the [`Facts`] it is given. The runs of a rule are kept by
+//! its name, so the daily limit and the cooldown hold over a rule update and a reboot.
+
+use std::{collections::BTreeMap, time::Duration};
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::command::{Amount, Command};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RuleError {
+    #[error("Rules are not valid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Rule `{0}` is invalid: {1}")]
+    Invalid(String, &'static str),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    /// The action runs when all of the conditions hold
+    pub when: Vec<Condition>,
+    pub then: Action,
+    /// Most runs in a day
+    #[serde(default)]
+    pub max_per_day: Option<u32>,
+    /// Shortest time between two runs in minutes
+    #[serde(default)]
+    pub cooldown: Option<u32>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// Reading of a sensor below and/or above the limits, false while the sensor has no reading
+    Reading {
+        sensor: String,
+        #[serde(default)]
+        below: Option<f32>,
+        #[serde(default)]
+        above: Option<f32>,
+    },
+    /// Local time from the `from` hour until the `to` hour, it may span midnight
+    Hours { from: u32, to: u32 },
+    /// Days of the week
+    Days(Vec<Weekday>),
+    /// State of an actuator
+    Actuator { name: String, on: bool },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    /// Water a zone for a time or a volume, the default manual watering without either
+    Water {
+        zone: u8,
+        #[serde(default)]
+        seconds: Option<f32>,
+        #[serde(default)]
+        ml: Option<f32>,
+    },
+    /// Lamp override in percent, for the default time without minutes
+    Lamp {
+        level: u8,
+        #[serde(default)]
+        minutes: Option<f32>,
+    },
+    /// Message to the notification channels
+    Notify(String),
+}
+
+impl Action {
+    /// Command carrying out the action, `None` for the notifications
+    pub fn command(&self) -> Option<Command> {
+        match *self {
+            Self::Water { zone, seconds, ml } => Some(Command::Water {
+                zone,
+                on: true,
+                amount: match (seconds, ml) {
+                    (Some(seconds), _) => {
+                        Duration::try_from_secs_f32(seconds).ok().map(Amount::Time)
+                    }
+                    (None, Some(ml)) => Some(Amount::Volume(ml)),
+                    (None, None) => None,
+                },
+            }),
+            Self::Lamp { level, minutes } => Some(Command::Lamp {
+                level,
+                duration: minutes
+                    .and_then(|minutes| Duration::try_from_secs_f32(minutes * 60.0).ok()),
+            }),
+            Self::Notify(_) => None,
+        }
+    }
+}
+
+/// Everything the conditions look at, readings and actuators by name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Facts {
+    /// Local time
+    pub time: NaiveDateTime,
+    pub readings: BTreeMap<String, f32>,
+    pub actuators: BTreeMap<String, bool>,
+}
+
+impl Condition {
+    pub fn holds(&self, facts: &Facts) -> bool {
+        match self {
+            Self::Reading {
+                sensor,
+                below,
+                above,
+            } => facts.readings.get(sensor).map_or(false, |value| {
+                below.map_or(true, |below| *value < below)
+                    && above.map_or(true, |above| *value > above)
+            }),
+            Self::Hours { from, to } => {
+                let hour = facts.time.hour();
+                if from <= to {
+                    *from <= hour && hour < *to
+                } else {
+                    hour >= *from || hour < *to
+                }
+            }
+            Self::Days(days) => days.contains(&facts.time.weekday()),
+            Self::Actuator { name, on } => facts.actuators.get(name) == Some(on),
+        }
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        match self {
+            Self::Reading {
+                below: None,
+                above: None,
+                ..
+            } => Err("a reading needs a `below` or an `above` limit"),
+            Self::Hours { from, to } if *from > 24 || *to > 24 => Err("hours are 0 to 24"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Rule {
+    fn validate(&self) -> Result<(), RuleError> {
+        let invalid = |reason| RuleError::Invalid(self.name.clone(), reason);
+        for condition in &self.when {
+            condition.validate().map_err(invalid)?;
+        }
+        let positive = |value: Option<f32>| value.map_or(true, |value| value > 0.0);
+        match self.then {
+            Action::Water {
+                seconds: Some(_),
+                ml: Some(_),
+                ..
+            } => Err(invalid("water takes either `seconds` or `ml`")),
+            Action::Water { seconds, ml, .. } if !positive(seconds) || !positive(ml) => {
+                Err(invalid("the water amount has to be positive"))
+            }
+            Action::Lamp { level, .. } if level > 100 => Err(invalid("the lamp level is 0 to 100")),
+            Action::Lamp { minutes, .. } if !positive(minutes) => {
+                Err(invalid("the lamp minutes have to be positive"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parse and check a JSON list of rules, the names have to be unique
+pub fn parse_rules(json: &[u8]) -> Result<Vec<Rule>, RuleError> {
+    let rules: Vec<Rule> = serde_json::from_slice(json)?;
+    for (index, rule) in rules.iter().enumerate() {
+        rule.validate()?;
+        if rules[..index].iter().any(|other| other.name == rule.name) {
+            return Err(RuleError::Invalid(
+                rule.name.clone(),
+                "the name is used by another rule",
+            ));
+        }
+    }
+    Ok(rules)
+}
+
+/// Runs of a rule, for the daily limit and the cooldown
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Runs {
+    day: Option<NaiveDate>,
+    count: u32,
+    last: Option<NaiveDateTime>,
+}
+
+pub struct RuleEngine {
+    rules: Vec<Rule>,
+    /// Runs by rule name
+    runs: BTreeMap<String, Runs>,
+}
+
+impl RuleEngine {
+    /// `runs` are the runs saved before a reboot, the ones of unknown rules are dropped
+    pub fn new(rules: Vec<Rule>, runs: BTreeMap<String, Runs>) -> Self {
+        let mut engine = Self { rules, runs };
+        engine.drop_unknown_runs();
+        engine
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Runs to save, they change whenever a rule fires
+    pub fn runs(&self) -> &BTreeMap<String, Runs> {
+        &self.runs
+    }
+
+    /// Replace the rules, the runs of the rules that are kept by name go on
+    pub fn set_rules(&mut self, rules: Vec<Rule>) {
+        self.rules = rules;
+        self.drop_unknown_runs();
+    }
+
+    /// Names and actions of the rules that run now
+    pub fn evaluate(&mut self, facts: &Facts) -> Vec<(String, Action)> {
+        let mut actions = Vec::new();
+        for rule in &self.rules {
+            if !rule.enabled || !rule.when.iter().all(|condition| condition.holds(facts)) {
+                continue;
+            }
+            let runs = self.runs.entry(rule.name.clone()).or_default();
+            let today = facts.time.date();
+            if runs.day != Some(today) {
+                runs.day = Some(today);
+                runs.count = 0;
+            }
+            if rule.max_per_day.map_or(false, |max| runs.count >= max) {
+                continue;
+            }
+            let cooling = runs
+                .last
+                .zip(rule.cooldown)
+                .map_or(false, |(last, cooldown)| {
+                    facts.time - last < chrono::Duration::minutes(cooldown.into())
+                });
+            if cooling {
+                continue;
+            }
+            runs.count += 1;
+            runs.last = Some(facts.time);
+            actions.push((rule.name.clone(), rule.then.clone()));
+        }
+        actions
+    }
+
+    fn drop_unknown_runs(&mut self) {
+        let rules = &self.rules;
+        self.runs
+            .retain(|name, _| rules.iter().any(|rule| &rule.name == name));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Water for 15s when the soil is dry during the day and the tank is not empty, 3 times a day
+    const DRY_POT: &str = r#"[{
+        "name": "dry pot",
+        "when": [
+            {"reading": {"sensor": "soil", "below": 25}},
+            {"hours": {"from": 6, "to": 20}},
+            {"reading": {"sensor": "tank", "above": 10}}
+        ],
+        "then": {"water": {"zone": 0, "seconds": 15}},
+        "max_per_day": 3
+    }]"#;
+
+    fn facts(day: u32, hour: u32, minute: u32, soil: f32) -> Facts {
+        Facts {
+            time: NaiveDate::from_ymd_opt(2023, 10, day)
+                .unwrap()
+                .and_hms_opt(hour, minute, 0)
+                .unwrap(),
+            readings: BTreeMap::from([("soil".to_string(), soil), ("tank".to_string(), 50.0)]),
+            actuators: BTreeMap::from([("pump".to_string(), false)]),
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        let mut engine = RuleEngine::new(parse_rules(DRY_POT.as_bytes()).unwrap(), BTreeMap::new());
+        assert!(engine.evaluate(&facts(2, 12, 0, 30.0)).is_empty());
+        // Night time
+        assert!(engine.evaluate(&facts(2, 21, 0, 20.0)).is_empty());
+        let mut empty_tank = facts(2, 12, 0, 20.0);
+        empty_tank.readings.insert("tank".to_string(), 5.0);
+        assert!(engine.evaluate(&empty_tank).is_empty());
+        // No tank reading
+        empty_tank.readings.remove("tank");
+        assert!(engine.evaluate(&empty_tank).is_empty());
+
+        let actions = engine.evaluate(&facts(2, 12, 0, 20.0));
+        assert_eq!(
+            actions,
+            vec![(
+                "dry pot".to_string(),
+                Action::Water {
+                    zone: 0,
+                    seconds: Some(15.0),
+                    ml: None
+                }
+            )]
+        );
+        assert_eq!(
+            actions[0].1.command(),
+            Some(Command::Water {
+                zone: 0,
+                on: true,
+                amount: Some(Amount::Time(Duration::from_secs(15)))
+            })
+        );
+    }
+
+    #[test]
+    fn daily_limit_and_cooldown() {
+        let mut rules = parse_rules(DRY_POT.as_bytes()).unwrap();
+        let mut engine = RuleEngine::new(rules.clone(), BTreeMap::new());
+        for minute in 0..5 {
+            engine.evaluate(&facts(2, 12, minute, 20.0));
+        }
+        assert!(engine.evaluate(&facts(2, 13, 0, 20.0)).is_empty());
+        // The limit starts over the next day
+        assert_eq!(engine.evaluate(&facts(3, 12, 0, 20.0)).len(), 1);
+
+        // An updated rule keeps its runs, the cooldown counts from the last one
+        rules[0].cooldown = Some(30);
+        engine.set_rules(rules);
+        assert!(engine.evaluate(&facts(3, 12, 29, 20.0)).is_empty());
+        assert_eq!(engine.evaluate(&facts(3, 12, 30, 20.0)).len(), 1);
+        assert_eq!(engine.evaluate(&facts(3, 13, 0, 20.0)).len(), 1);
+        assert!(engine.evaluate(&facts(3, 14, 0, 20.0)).is_empty());
+    }
+
+    #[test]
+    fn runs_survive_a_reboot() {
+        let rules = parse_rules(DRY_POT.as_bytes()).unwrap();
+        let mut engine = RuleEngine::new(rules.clone(), BTreeMap::new());
+        for hour in 8..11 {
+            engine.evaluate(&facts(2, hour, 0, 20.0));
+        }
+        let json = serde_json::to_string(engine.runs()).unwrap();
+
+        let mut rebooted = RuleEngine::new(rules.clone(), serde_json::from_str(&json).unwrap());
+        assert!(rebooted.evaluate(&facts(2, 12, 0, 20.0)).is_empty());
+
+        // A renamed rule starts over, the runs of the old name are dropped
+        let mut renamed = rules;
+        renamed[0].name = "dry pot 1".to_string();
+        rebooted.set_rules(renamed);
+        assert_eq!(rebooted.evaluate(&facts(2, 12, 0, 20.0)).len(), 1);
+        assert_eq!(rebooted.runs().len(), 1);
+        assert!(rebooted.runs().contains_key("dry pot 1"));
+    }
+
+    #[test]
+    fn time_and_actuator_conditions() {
+        let night = Condition::Hours { from: 20, to: 6 };
+        assert!(night.holds(&facts(2, 23, 0, 0.0)));
+        assert!(night.holds(&facts(2, 5, 59, 0.0)));
+        assert!(!night.holds(&facts(2, 6, 0, 0.0)));
+
+        // The 2nd of october 2023 is a monday
+        let weekend = Condition::Days(vec![Weekday::Sat, Weekday::Sun]);
+        assert!(!weekend.holds(&facts(2, 12, 0, 0.0)));
+        assert!(weekend.holds(&facts(1, 12, 0, 0.0)));
+
+        let pump_off = Condition::Actuator {
+            name: "pump".to_string(),
+            on: false,
+        };
+        assert!(pump_off.holds(&facts(2, 12, 0, 0.0)));
+        let lamp_on = Condition::Actuator {
+            name: "lamp".to_string(),
+            on: true,
+        };
+        assert!(!lamp_on.holds(&facts(2, 12, 0, 0.0)));
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        assert!(matches!(parse_rules(b"{}"), Err(RuleError::Json(_))));
+        for rule in [
+            r#"[{"name":"a","when":[{"reading":{"sensor":"soil"}}],"then":{"notify":"dry"}}]"#,
+            r#"[{"name":"a","when":[{"hours":{"from":6,"to":25}}],"then":{"notify":"x"}}]"#,
+            r#"[{"name":"a","when":[],"then":{"water":{"zone":0,"seconds":5,"ml":50}}}]"#,
+            r#"[{"name":"a","when":[],"then":{"water":{"zone":0,"seconds":-5}}}]"#,
+            r#"[{"name":"a","when":[],"then":{"lamp":{"level":150}}}]"#,
+            r#"[{"name":"a","when":[],"then":{"notify":"x"}},{"name":"a","when":[],"then":{"notify":"y"}}]"#,
+        ] {
+            assert!(
+                matches!(parse_rules(rule.as_bytes()), Err(RuleError::Invalid(..))),
+                "{rule}"
+            );
+        }
+        let rules = parse_rules(
+            br#"[{"name":"dusk","when":[{"days":["sat","sun"]}],"then":{"lamp":{"level":50}}}]"#,
+        )
+        .unwrap();
+        assert!(rules[0].enabled);
+    }
+}
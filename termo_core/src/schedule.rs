@@ -0,0 +1,699 @@
+//! Scheduling math, independent of the clock source.\
+//! Jobs run on cron like expressions or relative to the sun, the next run is always computed from
+//! the wall clock time
+//! so the schedule doesn't drift. On a daylight saving change a skipped time runs right after the
+//! change and a repeated one runs only once.
+
+use std::{cell::RefCell, collections::BTreeMap, str::FromStr};
+
+use chrono::{Datelike, Duration, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clock::{Clock, Timer},
+    sun::{Location, SolarTime},
+    timezone::TimeZone,
+};
+
+/// Longest sleep of [`run_jobs`], so changes of the jobs and of the clock are picked up
+pub const SCHEDULER_RECHECK: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ScheduleError {
+    #[error("Invalid schedule `{0}`, expected `minute hour day-of-week` or e.g. `sunrise+30m`")]
+    InvalidExpression(String),
+    #[error("There is no job {0}")]
+    UnknownJob(usize),
+}
+
+/// Duration from `current_time` until the next occurrence of `hour` o'clock.\
+/// If the hour has already passed today, the duration until tomorrow's is returned.
+pub fn duration_until_next(current_time: NaiveTime, hour: u32) -> Option<std::time::Duration> {
+    let target_time = NaiveTime::from_hms_opt(hour, 0, 0)?;
+    Some(duration_until(current_time, target_time))
+}
+
+/// Duration from `current_time` until the next occurrence of `target_time`, zero if it is now
+pub fn duration_until(current_time: NaiveTime, target_time: NaiveTime) -> std::time::Duration {
+    let elapsed = if current_time <= target_time {
+        // If current time is before the target, calculate duration until the target of the same day
+        target_time - current_time
+    } else {
+        // If current time is after the target, calculate duration until the target of the next day
+        Duration::days(1) - (current_time - target_time)
+    };
+    elapsed.to_std().unwrap_or_default()
+}
+
+/// Cron like expression of `minute hour day-of-week`.\
+/// Fields take `*`, values, ranges and steps, e.g. `*/15 6-20 mon-fri` or `0 8,20 *`.
+/// Days of the week are `0`-`7` from sunday ( `7` is sunday again ) or their three letter names.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    weekdays: u64,
+}
+
+impl FromStr for Cron {
+    type Err = ScheduleError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let invalid = || ScheduleError::InvalidExpression(expression.to_string());
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, weekdays] = fields[..] else {
+            return Err(invalid());
+        };
+        let minutes = parse_field(minutes, 59, |value| value.parse().ok()).ok_or_else(invalid)?;
+        let hours = parse_field(hours, 23, |value| value.parse().ok()).ok_or_else(invalid)?;
+        let weekdays = parse_field(weekdays, 7, parse_weekday).ok_or_else(invalid)?;
+        Ok(Self {
+            expression: fields.join(" "),
+            minutes,
+            hours,
+            // Sunday is both 0 and 7
+            weekdays: (weekdays | weekdays >> 7) & 0x7f,
+        })
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = ScheduleError;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        expression.parse()
+    }
+}
+
+impl From<Cron> for String {
+    fn from(cron: Cron) -> Self {
+        cron.expression
+    }
+}
+
+impl std::fmt::Display for Cron {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl Cron {
+    /// Every day at `hour` o'clock
+    pub fn daily(hour: u32) -> Result<Self, ScheduleError> {
+        format!("0 {hour} *").parse()
+    }
+
+    /// The expression matches the minute of `time`
+    pub fn matches(&self, time: NaiveDateTime) -> bool {
+        self.weekdays & 1 << time.weekday().num_days_from_sunday() != 0
+            && self.hours & 1 << time.hour() != 0
+            && self.minutes & 1 << time.minute() != 0
+    }
+
+    /// First matching minute after `time`
+    pub fn next_after(&self, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = time.date().and_hms_opt(time.hour(), time.minute(), 0)? + Duration::minutes(1);
+        let (hours, minutes) = (self.hours, self.minutes);
+        // Every weekday is checked once, the first one again a week later for the earlier minutes
+        (0..=7)
+            .map(|day| start.date() + Duration::days(day))
+            .filter(|date| self.weekdays & 1 << date.weekday().num_days_from_sunday() != 0)
+            .flat_map(move |date| {
+                (0..24)
+                    .filter(move |hour| hours & 1 << hour != 0)
+                    .flat_map(move |hour| {
+                        (0..60)
+                            .filter(move |minute| minutes & 1 << minute != 0)
+                            .filter_map(move |minute| date.and_hms_opt(hour, minute, 0))
+                    })
+            })
+            .find(|next| *next >= start)
+    }
+}
+
+/// Bit mask of the values of a cron field, `None` if it is malformed
+fn parse_field(field: &str, max: u32, value: impl Fn(&str) -> Option<u32>) -> Option<u64> {
+    let mut mask = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().ok().filter(|step| *step > 0)?),
+            None => (item, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (0, max),
+            Some((start, end)) => (value(start)?, value(end)?),
+            None => (value(range)?, value(range)?),
+        };
+        if start > end || end > max {
+            return None;
+        }
+        for value in (start..=end).step_by(step) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+fn parse_weekday(value: &str) -> Option<u32> {
+    const NAMES: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+    value.parse().ok().or_else(|| {
+        NAMES
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(value))
+            .map(|day| day as u32)
+    })
+}
+
+/// Time of a job, a cron expression or a time relative to the sun like `sunrise+30m`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Timing {
+    Cron(Cron),
+    Sun(SolarTime),
+}
+
+impl Timing {
+    /// First run after `time`, sun times need the location of the station
+    pub fn next_after(
+        &self,
+        time: NaiveDateTime,
+        location: Option<Location>,
+        timezone: &TimeZone,
+    ) -> Option<NaiveDateTime> {
+        match self {
+            Self::Cron(cron) => cron.next_after(time),
+            Self::Sun(sun) => sun.next_after(time, location?, timezone),
+        }
+    }
+}
+
+impl FromStr for Timing {
+    type Err = ScheduleError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        if expression.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            expression
+                .parse()
+                .map(Self::Sun)
+                .map_err(|_| ScheduleError::InvalidExpression(expression.to_string()))
+        } else {
+            expression.parse().map(Self::Cron)
+        }
+    }
+}
+
+impl TryFrom<String> for Timing {
+    type Error = ScheduleError;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        expression.parse()
+    }
+}
+
+impl From<Timing> for String {
+    fn from(timing: Timing) -> Self {
+        timing.to_string()
+    }
+}
+
+impl std::fmt::Display for Timing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cron(cron) => cron.fmt(f),
+            Self::Sun(sun) => sun.fmt(f),
+        }
+    }
+}
+
+/// What a job does about the runs missed while the device was off
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUp {
+    /// Wait for the next run
+    #[default]
+    Skip,
+    /// Run once at startup if a run was missed in the last hours
+    Within(u32),
+}
+
+/// Named job of the [`Scheduler`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Job {
+    pub name: String,
+    pub when: Timing,
+    pub enabled: bool,
+    #[serde(default)]
+    pub catch_up: CatchUp,
+}
+
+impl Job {
+    pub fn new(name: &str, expression: &str) -> Result<Self, ScheduleError> {
+        Ok(Self {
+            name: name.to_string(),
+            when: expression.parse()?,
+            enabled: true,
+            catch_up: CatchUp::Skip,
+        })
+    }
+
+    pub fn with_catch_up(mut self, catch_up: CatchUp) -> Self {
+        self.catch_up = catch_up;
+        self
+    }
+}
+
+/// Runs the jobs whose time has come since the last look at the clock
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    last: Option<NaiveDateTime>,
+    /// Time of the last run of each job, kept over restarts for the catch up
+    last_runs: BTreeMap<String, NaiveDateTime>,
+    location: Option<Location>,
+    timezone: TimeZone,
+}
+
+impl Scheduler {
+    pub fn new(jobs: Vec<Job>) -> Self {
+        Self {
+            jobs,
+            last: None,
+            last_runs: BTreeMap::new(),
+            location: None,
+            timezone: TimeZone::utc(),
+        }
+    }
+
+    /// Location and timezone of the station for the sun times, without a location they never run
+    pub fn set_location(&mut self, location: Option<Location>, timezone: TimeZone) {
+        self.location = location;
+        self.timezone = timezone;
+    }
+
+    fn next_after(&self, job: &Job, time: NaiveDateTime) -> Option<NaiveDateTime> {
+        job.when.next_after(time, self.location, &self.timezone)
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    pub fn last_runs(&self) -> &BTreeMap<String, NaiveDateTime> {
+        &self.last_runs
+    }
+
+    /// Last runs stored before a restart, the first [`Scheduler::due`] catches up on them
+    pub fn restore_runs(&mut self, last_runs: BTreeMap<String, NaiveDateTime>) {
+        self.last_runs = last_runs;
+    }
+
+    /// Enable or disable the job with the index `job`
+    pub fn set_enabled(&mut self, job: usize, enabled: bool) -> Result<&Job, ScheduleError> {
+        let entry = self
+            .jobs
+            .get_mut(job)
+            .ok_or(ScheduleError::UnknownJob(job))?;
+        entry.enabled = enabled;
+        Ok(entry)
+    }
+
+    /// Earliest next run of the enabled jobs
+    pub fn next_run(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        self.jobs
+            .iter()
+            .filter(|job| job.enabled)
+            .filter_map(|job| self.next_after(job, now))
+            .min()
+    }
+
+    /// Names of the enabled jobs due since the last call.\
+    /// A job runs once even if several of its times passed. The first call starts the schedule,
+    /// only the jobs that missed a run within their [`CatchUp`] time run then.
+    pub fn due(&mut self, now: NaiveDateTime) -> Vec<String> {
+        let last = match self.last {
+            Some(last) if last <= now => last,
+            // Set back a little, e.g. by a resync, nothing runs until the clock caught up again
+            Some(last) if last - now < Duration::days(1) => return Vec::new(),
+            Some(_) => {
+                self.last = Some(now);
+                return Vec::new();
+            }
+            None => {
+                self.last = Some(now);
+                let missed = self.missed(now);
+                self.record(&missed, now);
+                return missed;
+            }
+        };
+        self.last = Some(now);
+        let due: Vec<String> = self
+            .jobs
+            .iter()
+            .filter(|job| job.enabled)
+            .filter(|job| self.next_after(job, last).map_or(false, |next| next <= now))
+            .map(|job| job.name.clone())
+            .collect();
+        self.record(&due, now);
+        due
+    }
+
+    /// Jobs with a run between their last run and `now`, no older than their catch up time
+    fn missed(&self, now: NaiveDateTime) -> Vec<String> {
+        self.jobs
+            .iter()
+            .filter(|job| job.enabled)
+            .filter(|job| {
+                let CatchUp::Within(hours) = job.catch_up else {
+                    return false;
+                };
+                let Some(last_run) = self.last_runs.get(&job.name) else {
+                    return false;
+                };
+                let from = (*last_run).max(now - Duration::hours(hours.into()));
+                self.next_after(job, from).map_or(false, |next| next <= now)
+            })
+            .map(|job| job.name.clone())
+            .collect()
+    }
+
+    fn record(&mut self, jobs: &[String], now: NaiveDateTime) {
+        for job in jobs {
+            self.last_runs.insert(job.clone(), now);
+        }
+    }
+}
+
+/// Run the jobs of the `scheduler`, `run` gets the names of the jobs that are due.\
+/// The time to the next run is computed from the `clock` after every wake up, so the schedule
+/// doesn't drift. Nothing runs while the clock isn't set.
+pub async fn run_jobs<T: Timer>(
+    scheduler: &RefCell<Scheduler>,
+    clock: &impl Clock,
+    timer: &mut T,
+    mut run: impl FnMut(&[String]),
+) -> Result<(), T::Error> {
+    loop {
+        let Some(now) = clock.now() else {
+            timer.after(SCHEDULER_RECHECK).await?;
+            continue;
+        };
+        let due = scheduler.borrow_mut().due(now);
+        if !due.is_empty() {
+            run(&due);
+        }
+        let next_run = scheduler.borrow().next_run(now);
+        let wait = next_run
+            .and_then(|next| (next - now).to_std().ok())
+            .map_or(SCHEDULER_RECHECK, |wait| wait.min(SCHEDULER_RECHECK));
+        timer.after(wait).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clock::{run_virtual, SimulationEnd, VirtualClock},
+        timezone::TimeZone,
+    };
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    /// Time on a day of the first week of october 2023, `1` is monday
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2023, 10, day + 1)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn later_the_same_day() {
+        assert_eq!(
+            duration_until_next(time(6, 30), 8),
+            Some(std::time::Duration::from_secs(90 * 60))
+        );
+        assert_eq!(
+            duration_until_next(time(8, 0), 8),
+            Some(std::time::Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn wraps_to_the_next_day() {
+        assert_eq!(
+            duration_until_next(time(8, 1), 8),
+            Some(std::time::Duration::from_secs(24 * 3600 - 60))
+        );
+        assert_eq!(
+            duration_until_next(time(23, 0), 0),
+            Some(std::time::Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn invalid_hour() {
+        assert_eq!(duration_until_next(time(8, 0), 24), None);
+    }
+
+    #[test]
+    fn parses_cron() {
+        let cron: Cron = "*/15 6-8,20 mon-fri".parse().unwrap();
+        assert!(cron.matches(at(1, 6, 45)));
+        assert!(cron.matches(at(5, 20, 0)));
+        assert!(!cron.matches(at(1, 9, 0)));
+        assert!(!cron.matches(at(1, 6, 10)));
+        assert!(!cron.matches(at(6, 6, 0)));
+
+        let sunday: Cron = "0 8 7".parse().unwrap();
+        assert!(sunday.matches(at(0, 8, 0)));
+        assert_eq!(sunday.to_string(), "0 8 7");
+
+        for invalid in [
+            "0 8",
+            "60 8 *",
+            "0 8-6 *",
+            "0 */0 *",
+            "0 8 8",
+            "0 8 funday",
+            "",
+        ] {
+            assert!(invalid.parse::<Cron>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn next_run() {
+        let daily = Cron::daily(8).unwrap();
+        assert_eq!(daily.next_after(at(1, 6, 30)), Some(at(1, 8, 0)));
+        assert_eq!(daily.next_after(at(1, 8, 0)), Some(at(2, 8, 0)));
+
+        let weekly: Cron = "30 7 mon".parse().unwrap();
+        assert_eq!(weekly.next_after(at(1, 7, 29)), Some(at(1, 7, 30)));
+        assert_eq!(weekly.next_after(at(1, 7, 30)), Some(at(8, 7, 30)));
+    }
+
+    #[test]
+    fn runs_jobs_once() {
+        let mut scheduler = Scheduler::new(vec![
+            Job::new("report", "0 8 *").unwrap(),
+            Job::new("water", "0 */2 *").unwrap(),
+        ]);
+        // Nothing runs at startup
+        assert!(scheduler.due(at(1, 8, 0)).is_empty());
+        assert_eq!(scheduler.next_run(at(1, 8, 0)), Some(at(1, 10, 0)));
+        assert!(scheduler.due(at(1, 9, 59)).is_empty());
+        assert_eq!(scheduler.due(at(1, 10, 0)), vec!["water"]);
+        assert!(scheduler.due(at(1, 10, 0)).is_empty());
+        // A late wake up runs each job once
+        assert_eq!(scheduler.due(at(2, 8, 30)), vec!["report", "water"]);
+    }
+
+    #[test]
+    fn disabled_jobs() {
+        let mut scheduler = Scheduler::new(vec![Job::new("report", "0 8 *").unwrap()]);
+        scheduler.due(at(1, 7, 0));
+        assert!(!scheduler.set_enabled(0, false).unwrap().enabled);
+        assert_eq!(scheduler.next_run(at(1, 7, 0)), None);
+        assert!(scheduler.due(at(1, 9, 0)).is_empty());
+        assert_eq!(
+            scheduler.set_enabled(1, true),
+            Err(ScheduleError::UnknownJob(1))
+        );
+    }
+
+    #[test]
+    fn daylight_saving_changes() {
+        let zone: TimeZone = "CET-1CEST,M3.5.0,M10.5.0/3".parse().unwrap();
+        // The clocks go forward on the 26th of march and back on the 29th of october
+        for (month, day) in [(3, 26), (10, 29)] {
+            for hour in [2, 3] {
+                let start = chrono::NaiveDate::from_ymd_opt(2023, month, day - 1)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0)
+                    .unwrap();
+                let mut scheduler =
+                    Scheduler::new(vec![Job::new("job", &format!("30 {hour} *")).unwrap()]);
+                let runs: Vec<NaiveDateTime> = (0..2 * 24 * 60)
+                    .map(|minute| zone.to_local(start + Duration::minutes(minute)))
+                    .filter(|now| !scheduler.due(*now).is_empty())
+                    .collect();
+                // Once a day, the skipped 2:30 of march runs at 3:00
+                assert_eq!(runs.len(), 2, "{runs:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn sun_times() {
+        let london: TimeZone = "GMT0BST,M3.5.0/1,M10.5.0".parse().unwrap();
+        let mut scheduler = Scheduler::new(vec![Job::new("lamp", "dusk+15m").unwrap()]);
+        // Never runs without a location
+        assert_eq!(scheduler.next_run(at(1, 12, 0)), None);
+
+        scheduler.set_location(
+            Some(Location {
+                latitude: 51.5074,
+                longitude: -0.1278,
+            }),
+            london,
+        );
+        // Dusk is at 19:07 on the 2nd of october
+        let next = scheduler.next_run(at(1, 20, 0)).unwrap();
+        assert_eq!(next.date(), at(2, 0, 0).date());
+        assert!((19 * 60 + 18..=19 * 60 + 26).contains(&(next.hour() * 60 + next.minute())));
+
+        assert_eq!(
+            "sunset+2h".parse::<Timing>().unwrap().to_string(),
+            "sunset+120m"
+        );
+        assert_eq!(
+            "0 8 *".parse::<Timing>().unwrap(),
+            Timing::Cron(Cron::daily(8).unwrap())
+        );
+        assert!("moonrise".parse::<Timing>().is_err());
+    }
+
+    #[test]
+    fn catches_up_after_restart() {
+        let jobs = || {
+            vec![
+                Job::new("report", "0 8 *")
+                    .unwrap()
+                    .with_catch_up(CatchUp::Within(4)),
+                Job::new("water", "0 7 *").unwrap(),
+            ]
+        };
+        let mut scheduler = Scheduler::new(jobs());
+        scheduler.due(at(1, 6, 0));
+        assert_eq!(scheduler.due(at(1, 8, 0)), vec!["report", "water"]);
+        let last_runs = scheduler.last_runs().clone();
+        assert_eq!(last_runs.get("report"), Some(&at(1, 8, 0)));
+
+        // Off from 7:59 to 9:30, only the report catches up
+        let mut restarted = Scheduler::new(jobs());
+        restarted.restore_runs(last_runs.clone());
+        assert_eq!(restarted.due(at(2, 9, 30)), vec!["report"]);
+        assert!(restarted.due(at(2, 9, 31)).is_empty());
+        assert_eq!(restarted.last_runs().get("report"), Some(&at(2, 9, 30)));
+
+        // The missed run is too old
+        let mut restarted = Scheduler::new(jobs());
+        restarted.restore_runs(last_runs.clone());
+        assert!(restarted.due(at(2, 12, 30)).is_empty());
+
+        // Nothing was missed
+        let mut restarted = Scheduler::new(jobs());
+        restarted.restore_runs(last_runs);
+        assert!(restarted.due(at(2, 7, 30)).is_empty());
+        assert_eq!(restarted.due(at(2, 8, 0)), vec!["report"]);
+
+        // Never ran, there is nothing to catch up
+        let mut scheduler = Scheduler::new(jobs());
+        assert!(scheduler.due(at(2, 9, 0)).is_empty());
+    }
+
+    #[test]
+    fn clock_set_back() {
+        let mut scheduler = Scheduler::new(vec![Job::new("report", "0 8 *").unwrap()]);
+        scheduler.due(at(1, 8, 30));
+        // The report of 8:00 isn't repeated after the clock went back an hour
+        assert!(scheduler.due(at(1, 7, 30)).is_empty());
+        assert!(scheduler.due(at(1, 8, 10)).is_empty());
+        assert_eq!(scheduler.due(at(2, 8, 0)), vec!["report"]);
+    }
+
+    /// Run the jobs on the virtual `clock` until `end`, the names and times of the runs
+    fn simulate(
+        scheduler: &RefCell<Scheduler>,
+        clock: &VirtualClock,
+        end: NaiveDateTime,
+    ) -> Vec<(String, NaiveDateTime)> {
+        let mut runs = Vec::new();
+        let result = run_virtual(run_jobs(
+            scheduler,
+            clock,
+            &mut clock.timer_until(end),
+            |due| {
+                for job in due {
+                    runs.push((job.clone(), clock.now().unwrap()));
+                }
+            },
+        ));
+        assert_eq!(result, Err(SimulationEnd(end)));
+        runs
+    }
+
+    #[test]
+    fn simulated_week() {
+        let clock = VirtualClock::new(at(1, 0, 0));
+        let scheduler = RefCell::new(Scheduler::new(vec![
+            Job::new("report", "0 8 *").unwrap(),
+            Job::new("water", "0 */6 mon-fri").unwrap(),
+        ]));
+        let runs = simulate(&scheduler, &clock, at(7, 23, 0));
+        let reports: Vec<NaiveDateTime> = runs
+            .iter()
+            .filter(|(job, _)| job == "report")
+            .map(|(_, time)| *time)
+            .collect();
+        assert_eq!(
+            reports,
+            (1..=7).map(|day| at(day, 8, 0)).collect::<Vec<_>>()
+        );
+        // The monday midnight run is before the start
+        let waterings = runs.iter().filter(|(job, _)| job == "water").count();
+        assert_eq!(waterings, 3 + 4 * 4);
+        assert!(runs
+            .iter()
+            .all(|(_, time)| time.weekday().number_from_monday() <= 5 || time.hour() == 8));
+    }
+
+    #[test]
+    fn simulated_restart() {
+        let jobs = || {
+            vec![Job::new("report", "0 8 *")
+                .unwrap()
+                .with_catch_up(CatchUp::Within(4))]
+        };
+        let clock = VirtualClock::new(at(1, 0, 0));
+        let scheduler = RefCell::new(Scheduler::new(jobs()));
+        assert_eq!(simulate(&scheduler, &clock, at(2, 7, 59)).len(), 1);
+
+        // Off from 7:59 to 9:30 on tuesday
+        let last_runs = scheduler.borrow().last_runs().clone();
+        let scheduler = RefCell::new(Scheduler::new(jobs()));
+        scheduler.borrow_mut().restore_runs(last_runs);
+        clock.set(at(2, 9, 30));
+        assert_eq!(
+            simulate(&scheduler, &clock, at(3, 12, 0)),
+            vec![
+                ("report".to_string(), at(2, 9, 30)),
+                ("report".to_string(), at(3, 8, 0))
+            ]
+        );
+    }
+}
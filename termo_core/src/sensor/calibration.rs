@@ -0,0 +1,117 @@
+//! Buffer calibration of the analog probes and the EC temperature compensation.
+
+use serde::{Deserialize, Serialize};
+
+/// Calibration supports 2 or 3 buffer points
+const MAX_CALIBRATION_POINTS: usize = 3;
+/// Temperature the EC readings are compensated to
+pub const EC_REFERENCE_TEMP: f32 = 25.0;
+/// Conductivity change per °C, typical for nutrient solutions
+const EC_TEMP_COEFFICIENT: f32 = 0.02;
+
+/// One buffer solution reading: the probe voltage and the known value of the buffer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct CalibrationPoint {
+    pub millivolts: f32,
+    pub value: f32,
+}
+
+/// Multi-point (2 or 3) calibration, the probe voltage is mapped piecewise linearly
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Calibration {
+    points: Vec<CalibrationPoint>,
+}
+
+impl Calibration {
+    /// Add a buffer reading.\
+    /// A point with the same buffer value is replaced, when all slots are taken the oldest is dropped.
+    pub fn add_point(&mut self, millivolts: f32, value: f32) {
+        self.points
+            .retain(|point| (point.value - value).abs() > f32::EPSILON);
+        if self.points.len() == MAX_CALIBRATION_POINTS {
+            self.points.remove(0);
+        }
+        self.points.push(CalibrationPoint { millivolts, value });
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.points.len() >= 2
+    }
+
+    /// Convert a probe voltage to the calibrated value.\
+    /// Outside the calibrated range the closest segment is extrapolated.
+    pub fn apply(&self, millivolts: f32) -> Option<f32> {
+        if !self.is_valid() {
+            return None;
+        }
+        let mut sorted = self.points.clone();
+        sorted.sort_by(|a, b| a.millivolts.total_cmp(&b.millivolts));
+
+        let segment = sorted
+            .windows(2)
+            .find(|pair| millivolts <= pair[1].millivolts)
+            .unwrap_or(&sorted[sorted.len() - 2..]);
+        let (low, high) = (segment[0], segment[1]);
+
+        let span = high.millivolts - low.millivolts;
+        if span.abs() < f32::EPSILON {
+            return None;
+        }
+        let slope = (high.value - low.value) / span;
+        Some(low.value + slope * (millivolts - low.millivolts))
+    }
+}
+
+/// Ratio of the measured conductivity at `temp` and at the reference temperature
+pub fn temperature_factor(temp: f32) -> f32 {
+    1.0 + EC_TEMP_COEFFICIENT * (temp - EC_REFERENCE_TEMP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(value: Option<f32>, expected: f32) {
+        let value = value.expect("calibrated value");
+        assert!((value - expected).abs() < 0.01, "{value} != {expected}");
+    }
+
+    #[test]
+    fn needs_two_points() {
+        let mut calibration = Calibration::default();
+        calibration.add_point(2000.0, 4.0);
+        assert_eq!(calibration.apply(1500.0), None);
+
+        calibration.add_point(1500.0, 7.0);
+        assert_close(calibration.apply(1500.0), 7.0);
+        assert_close(calibration.apply(1750.0), 5.5);
+    }
+
+    #[test]
+    fn three_points_are_piecewise() {
+        let mut calibration = Calibration::default();
+        calibration.add_point(2000.0, 4.0);
+        calibration.add_point(1500.0, 7.0);
+        calibration.add_point(1100.0, 10.0);
+        assert_close(calibration.apply(1300.0), 8.5);
+        assert_close(calibration.apply(1750.0), 5.5);
+        // Extrapolated with the outer segments
+        assert_close(calibration.apply(2100.0), 3.4);
+        assert_close(calibration.apply(1000.0), 10.75);
+    }
+
+    #[test]
+    fn same_buffer_replaces_point() {
+        let mut calibration = Calibration::default();
+        calibration.add_point(2000.0, 4.0);
+        calibration.add_point(1500.0, 7.0);
+        calibration.add_point(1600.0, 7.0);
+        assert_close(calibration.apply(1600.0), 7.0);
+    }
+
+    #[test]
+    fn compensation_is_neutral_at_reference() {
+        assert_eq!(temperature_factor(EC_REFERENCE_TEMP), 1.0);
+        assert!((temperature_factor(20.0) - 0.9).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,425 @@
+//! Health monitoring of the sensors.\
+//! Every reading passes through plausibility checks: physical range, rate of change and stuck
+//! values. Read errors are counted, a sensor that keeps failing is reported as faulty.
+
+use std::time::{Duration, Instant};
+
+use log::warn;
+use serde_json::{json, Value};
+
+use super::Sensor;
+
+/// Limits a reading of a sensor has to respect to be trusted
+#[derive(Debug, Clone, Copy)]
+pub struct HealthConfig {
+    /// Lowest physically possible value
+    pub min: f32,
+    /// Highest physically possible value
+    pub max: f32,
+    /// Largest believable change per second
+    pub max_rate: f32,
+    /// Noise band ignored by the rate and stuck checks
+    pub noise: f32,
+    /// How long the reading may stay identical before the sensor is considered stuck.\
+    /// It is a time and not a number of reads, as every consumer reads the sensor on its own.
+    pub stuck_after: Duration,
+    /// Consecutive read errors before the sensor is considered faulty
+    pub max_errors: u32,
+}
+
+impl HealthConfig {
+    /// Capacitive soil probe in percentage
+    pub fn soil() -> Self {
+        Self {
+            min: 0.0,
+            max: 100.0,
+            max_rate: 1.0,
+            noise: 2.0,
+            stuck_after: Duration::from_secs(2 * 60 * 60),
+            max_errors: 3,
+        }
+    }
+
+    /// Bme280 temperature range in °C
+    pub fn temperature() -> Self {
+        Self {
+            min: -40.0,
+            max: 85.0,
+            max_rate: 0.1,
+            noise: 0.5,
+            stuck_after: Duration::from_secs(60 * 60),
+            max_errors: 3,
+        }
+    }
+
+    /// Bme280 relative humidity in %
+    pub fn humidity() -> Self {
+        Self {
+            min: 0.0,
+            max: 100.0,
+            max_rate: 0.5,
+            noise: 2.0,
+            stuck_after: Duration::from_secs(60 * 60),
+            max_errors: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Reads keep failing
+    NotResponding,
+    /// Reading outside of the physical range of the sensor
+    OutOfRange(f32),
+    /// The same value is repeated for too long
+    Stuck(f32),
+    /// Reading changed faster than physically possible
+    Implausible { from: f32, to: f32 },
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Fault::NotResponding => write!(f, "not responding"),
+            Fault::OutOfRange(value) => write!(f, "out of range ({value:.1})"),
+            Fault::Stuck(value) => write!(f, "stuck at {value:.1}"),
+            Fault::Implausible { from, to } => write!(f, "implausible jump {from:.1} -> {to:.1}"),
+        }
+    }
+}
+
+/// Health state of one sensor, fed with every reading
+#[derive(Debug, Clone)]
+pub struct SensorHealth {
+    config: HealthConfig,
+    /// Last accepted reading, the reference of the rate check
+    last: Option<(f32, Instant)>,
+    /// Since when the reading hasn't changed
+    unchanged_since: Option<Instant>,
+    consecutive_errors: u32,
+    total_reads: u32,
+    total_errors: u32,
+    total_faults: u32,
+    fault: Option<Fault>,
+}
+
+impl SensorHealth {
+    pub fn new(config: HealthConfig) -> Self {
+        Self {
+            config,
+            last: None,
+            unchanged_since: None,
+            consecutive_errors: 0,
+            total_reads: 0,
+            total_errors: 0,
+            total_faults: 0,
+            fault: None,
+        }
+    }
+
+    /// Record a failed read
+    pub fn record_error(&mut self) -> Fault {
+        self.total_reads += 1;
+        self.total_errors += 1;
+        self.consecutive_errors += 1;
+        if self.consecutive_errors >= self.config.max_errors {
+            self.set_fault(Some(Fault::NotResponding));
+        }
+        Fault::NotResponding
+    }
+
+    /// Check a successful read taken at `now`, the value is returned if it can be trusted
+    pub fn check(&mut self, value: f32, now: Instant) -> Result<f32, Fault> {
+        self.total_reads += 1;
+        self.consecutive_errors = 0;
+
+        let fault = self.evaluate(value, now);
+        // Rejected values are no reference for the next rate check, a stuck one is still real
+        if matches!(fault, None | Some(Fault::Stuck(_))) {
+            self.last = Some((value, now));
+        }
+        self.set_fault(fault);
+        match fault {
+            Some(fault) => {
+                self.total_errors += 1;
+                Err(fault)
+            }
+            None => Ok(value),
+        }
+    }
+
+    fn evaluate(&mut self, value: f32, now: Instant) -> Option<Fault> {
+        let HealthConfig { min, max, .. } = self.config;
+        if !value.is_finite() || value < min || value > max {
+            return Some(Fault::OutOfRange(value));
+        }
+        let (last, taken) = self.last?;
+        let delta = (value - last).abs();
+
+        let elapsed = now.duration_since(taken).as_secs_f32();
+        if delta > self.config.noise && elapsed > 0.0 {
+            let rate = (delta - self.config.noise) / elapsed;
+            if rate > self.config.max_rate {
+                return Some(Fault::Implausible {
+                    from: last,
+                    to: value,
+                });
+            }
+        }
+
+        // Saturated readings at the edge of the range are legit repeats ( e.g. 0% dry soil )
+        if delta < f32::EPSILON && value > min && value < max {
+            let since = *self.unchanged_since.get_or_insert(taken);
+            (now.duration_since(since) >= self.config.stuck_after).then_some(Fault::Stuck(value))
+        } else {
+            self.unchanged_since = None;
+            None
+        }
+    }
+
+    fn set_fault(&mut self, fault: Option<Fault>) {
+        if fault.is_some() && fault != self.fault {
+            self.total_faults += 1;
+        }
+        self.fault = fault;
+    }
+
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    pub fn is_faulty(&self) -> bool {
+        self.fault.is_some()
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "status": self.fault.map_or("ok".to_string(), |fault| fault.to_string()),
+            "reads": self.total_reads,
+            "errors": self.total_errors,
+            "faults": self.total_faults,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HealthError<E: std::fmt::Debug> {
+    #[error("Sensor error: {0:?}")]
+    Sensor(E),
+    #[error("Sensor fault: {0}")]
+    Fault(Fault),
+}
+
+/// Sensor wrapper that runs every measurement through the health checks
+pub struct Monitored<S: Sensor> {
+    sensor: S,
+    health: SensorHealth,
+}
+
+impl<S: Sensor> Monitored<S> {
+    pub fn new(sensor: S, config: HealthConfig) -> Self {
+        Self {
+            sensor,
+            health: SensorHealth::new(config),
+        }
+    }
+
+    pub fn health(&self) -> &SensorHealth {
+        &self.health
+    }
+
+    pub fn is_faulty(&self) -> bool {
+        self.health.is_faulty()
+    }
+
+    pub fn inner(&mut self) -> &mut S {
+        &mut self.sensor
+    }
+
+    /// Read the sensor `samples` times and report the outcome, used at boot
+    pub fn self_test(&mut self, samples: u32) -> Value
+    where
+        S::Error: std::fmt::Debug,
+    {
+        for _ in 0..samples {
+            if let Err(err) = self.get_measurment() {
+                warn!("Self test {}: {}", self.sensor.get_name(), err);
+            }
+        }
+        let mut report = self.health.to_json();
+        report["type"] = self.sensor.get_name().into();
+        report
+    }
+}
+
+impl<S> Sensor for Monitored<S>
+where
+    S: Sensor,
+    S::Error: std::fmt::Debug,
+{
+    type Error = HealthError<S::Error>;
+    type Status = S::Status;
+
+    fn get_unit(&self) -> &str {
+        self.sensor.get_unit()
+    }
+
+    fn get_name(&self) -> &str {
+        self.sensor.get_name()
+    }
+
+    fn get_measurment(&mut self) -> Result<f32, Self::Error> {
+        match self.sensor.get_measurment() {
+            Ok(value) => self
+                .health
+                .check(value, Instant::now())
+                .map_err(HealthError::Fault),
+            Err(err) => {
+                self.health.record_error();
+                Err(HealthError::Sensor(err))
+            }
+        }
+    }
+
+    fn get_status(&mut self) -> Result<Self::Status, Self::Error> {
+        if let Some(fault) = self.health.fault() {
+            return Err(HealthError::Fault(fault));
+        }
+        self.sensor.get_status().map_err(HealthError::Sensor)
+    }
+}
+
+/// Boot time diagnostics summary of the self tested sensors
+pub fn diagnostics_message(reports: &[Value]) -> String {
+    let lines: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            format!(
+                "> {}: **{}** ({} reads, {} errors)",
+                report["type"].as_str().unwrap_or("unknown"),
+                report["status"].as_str().unwrap_or("unknown"),
+                report["reads"],
+                report["errors"],
+            )
+        })
+        .collect();
+    format!("Self test after boot :stethoscope:\\n{}", lines.join("\\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::{
+        sim::{Failure, Signal, SimulatedSensor},
+        status::SoilStatus,
+    };
+
+    fn config() -> HealthConfig {
+        HealthConfig {
+            stuck_after: Duration::from_secs(60),
+            ..HealthConfig::soil()
+        }
+    }
+
+    #[test]
+    fn out_of_range_is_a_fault() {
+        let mut health = SensorHealth::new(config());
+        assert_eq!(
+            health.check(120.0, Instant::now()),
+            Err(Fault::OutOfRange(120.0))
+        );
+        assert!(health.is_faulty());
+        assert_eq!(health.check(40.0, Instant::now()), Ok(40.0));
+        assert!(!health.is_faulty());
+    }
+
+    #[test]
+    fn fast_change_is_implausible() {
+        let mut health = SensorHealth::new(config());
+        let start = Instant::now();
+        assert!(health.check(30.0, start).is_ok());
+        // Noise is tolerated even between back to back reads
+        assert!(health.check(31.0, start).is_ok());
+        assert_eq!(
+            health.check(80.0, start + Duration::from_secs(1)),
+            Err(Fault::Implausible {
+                from: 31.0,
+                to: 80.0
+            })
+        );
+        // A slow change is fine
+        assert!(health.check(60.0, start + Duration::from_secs(60)).is_ok());
+    }
+
+    #[test]
+    fn rejected_spike_is_no_reference() {
+        let mut health = SensorHealth::new(config());
+        let start = Instant::now();
+        assert!(health.check(30.0, start).is_ok());
+        assert!(health.check(80.0, start + Duration::from_secs(1)).is_err());
+        // Back to the real value, it is compared with the last accepted reading
+        assert_eq!(health.check(30.5, start + Duration::from_secs(2)), Ok(30.5));
+        // A spike isn't accepted by repeating it either
+        assert!(health.check(80.0, start + Duration::from_secs(3)).is_err());
+        assert_eq!(
+            health.check(80.0, start + Duration::from_secs(4)),
+            Err(Fault::Implausible {
+                from: 30.5,
+                to: 80.0
+            })
+        );
+    }
+
+    #[test]
+    fn repeated_value_is_stuck() {
+        let mut health = SensorHealth::new(config());
+        let start = Instant::now();
+        // Many consumers reading the same sample in a short time is no stuck sensor
+        for _ in 0..100 {
+            assert!(health.check(42.0, start).is_ok());
+        }
+        assert!(health.check(42.0, start + Duration::from_secs(59)).is_ok());
+        assert_eq!(
+            health.check(42.0, start + Duration::from_secs(60)),
+            Err(Fault::Stuck(42.0))
+        );
+        // A change restarts the time
+        assert!(health.check(43.0, start + Duration::from_secs(61)).is_ok());
+        assert!(health.check(43.0, start + Duration::from_secs(120)).is_ok());
+        // Saturated dry soil is not stuck
+        let mut health = SensorHealth::new(config());
+        for minute in 0..10 {
+            assert!(health
+                .check(0.0, start + Duration::from_secs(minute * 60))
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn monitored_sensor_counts_errors() {
+        let sensor = SimulatedSensor::new(
+            "soil moisture",
+            "%",
+            Signal::constant(30.0),
+            SoilStatus::from_precentage,
+        )
+        .with_failure(Failure::After(2));
+        let mut monitored = Monitored::new(sensor, config());
+
+        let report = monitored.self_test(5);
+        assert!(monitored.is_faulty());
+        assert_eq!(report["status"], "not responding");
+        assert_eq!(report["reads"], 5);
+        assert_eq!(report["errors"], 3);
+        assert!(monitored.get_status().is_err());
+    }
+
+    #[test]
+    fn diagnostics_summary() {
+        let report = json!({"type": "humidity", "status": "ok", "reads": 5, "errors": 0});
+        assert_eq!(
+            diagnostics_message(&[report]),
+            "Self test after boot :stethoscope:\\n> humidity: **ok** (5 reads, 0 errors)"
+        );
+    }
+}
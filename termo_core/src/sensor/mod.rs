@@ -0,0 +1,95 @@
+use std::fmt::Display;
+
+use serde_json::{json, Value};
+
+pub mod calibration;
+pub mod health;
+#[cfg(any(test, feature = "simulation"))]
+pub mod sim;
+pub mod status;
+
+pub trait MessageAble {
+    fn to_json(&mut self) -> Value;
+}
+
+pub trait Sensor {
+    type Error;
+    type Status;
+
+    fn get_unit(&self) -> &str;
+    fn get_name(&self) -> &str;
+
+    fn get_measurment(&mut self) -> Result<f32, Self::Error>;
+    fn get_status(&mut self) -> Result<Self::Status, Self::Error>;
+}
+
+impl<ST, E, S> MessageAble for S
+where
+    ST: Display,
+    E: std::fmt::Debug,
+    S: Sensor<Error = E, Status = ST>,
+{
+    fn to_json(&mut self) -> Value {
+        if let (Ok(stat), Ok(msrmnt)) = (self.get_status(), self.get_measurment()) {
+            json!( {
+                    "type":self.get_name(),
+                    "value": msrmnt,
+                    "status": stat.to_string(),
+                    "unit": self.get_unit()
+            })
+        } else {
+            json!( {
+                    "type":self.get_name(),
+                    "value": self.get_measurment().ok(),
+                    "status": "Not connected",
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        sim::{Failure, Signal, SimulatedSensor},
+        status::SoilStatus,
+        *,
+    };
+
+    #[test]
+    fn json_of_connected_sensor() {
+        let mut sensor = SimulatedSensor::new(
+            "soil moisture",
+            "%",
+            Signal::constant(30.0),
+            SoilStatus::from_precentage,
+        );
+        assert_eq!(
+            sensor.to_json(),
+            json!({
+                "type": "soil moisture",
+                "value": 30.0,
+                "status": "Optimal 💚",
+                "unit": "%"
+            })
+        );
+    }
+
+    #[test]
+    fn json_of_disconnected_sensor() {
+        let mut sensor = SimulatedSensor::new(
+            "soil moisture",
+            "%",
+            Signal::constant(30.0),
+            SoilStatus::from_precentage,
+        )
+        .with_failure(Failure::Disconnected);
+        assert_eq!(
+            sensor.to_json(),
+            json!({
+                "type": "soil moisture",
+                "value": null,
+                "status": "Not connected",
+            })
+        );
+    }
+}
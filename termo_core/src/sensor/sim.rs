@@ -0,0 +1,221 @@
+//! Simulated sensors to run the status, report and JSON logic without the ESP32 peripherals.
+//!
+//! # Example
+//! ```
+//! use termo_core::sensor::{sim::*, status::SoilStatus, MessageAble};
+//!
+//! let mut soil = SimulatedSensor::new(
+//!     "soil moisture",
+//!     "%",
+//!     Signal::constant(35.0),
+//!     SoilStatus::from_precentage,
+//! )
+//! .with_failure(Failure::After(10));
+//! assert_eq!(soil.to_json()["status"], "Optimal 💚");
+//! ```
+
+use super::Sensor;
+
+#[derive(Debug, Clone, Copy, PartialEq, thiserror::Error)]
+pub enum SimError {
+    #[error("Simulated sensor not connected")]
+    NotConnected,
+    #[error("Injected read failure")]
+    Injected,
+}
+
+/// Value source of a simulated sensor
+#[derive(Debug, Clone)]
+pub enum Signal {
+    /// The same value on every read
+    Constant(f32),
+    /// Scripted values played in order, the last one is held afterwards
+    Script { values: Vec<f32>, cursor: usize },
+    /// Moves `value` by `drift` every read, with uniform noise of `+-noise` on top
+    Drift {
+        value: f32,
+        drift: f32,
+        noise: f32,
+        seed: u32,
+    },
+}
+
+impl Signal {
+    pub fn constant(value: f32) -> Self {
+        Signal::Constant(value)
+    }
+
+    pub fn script(values: impl Into<Vec<f32>>) -> Self {
+        Signal::Script {
+            values: values.into(),
+            cursor: 0,
+        }
+    }
+
+    /// `seed` makes the noise repeatable between runs
+    pub fn drift(base: f32, drift: f32, noise: f32, seed: u32) -> Self {
+        Signal::Drift {
+            value: base,
+            drift,
+            noise,
+            seed: seed.max(1),
+        }
+    }
+
+    fn next(&mut self) -> Option<f32> {
+        match self {
+            Signal::Constant(value) => Some(*value),
+            Signal::Script { values, cursor } => {
+                let value = values.get(*cursor).or(values.last()).copied();
+                *cursor += 1;
+                value
+            }
+            Signal::Drift {
+                value,
+                drift,
+                noise,
+                seed,
+            } => {
+                *value += *drift;
+                Some(*value + *noise * next_noise(seed))
+            }
+        }
+    }
+}
+
+/// Xorshift noise in the range -1.0..1.0
+fn next_noise(seed: &mut u32) -> f32 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    (*seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+}
+
+/// Failure injected into the reads of a simulated sensor
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    None,
+    /// Every read fails as if the sensor was unplugged
+    Disconnected,
+    /// Every n-th read fails
+    Every(u32),
+    /// Reads fail after n successful reads
+    After(u32),
+    /// Reads succeed but always return the same value
+    StuckAt(f32),
+}
+
+/// Sensor backed by a [`Signal`] instead of a peripheral
+pub struct SimulatedSensor<St> {
+    name: &'static str,
+    unit: &'static str,
+    signal: Signal,
+    failure: Failure,
+    status: fn(f32) -> St,
+    reads: u32,
+}
+
+impl<St> SimulatedSensor<St> {
+    /// status -> status mapping of the simulated sensor, e.g. `SoilStatus::from_precentage`
+    pub fn new(
+        name: &'static str,
+        unit: &'static str,
+        signal: Signal,
+        status: fn(f32) -> St,
+    ) -> Self {
+        Self {
+            name,
+            unit,
+            signal,
+            failure: Failure::None,
+            status,
+            reads: 0,
+        }
+    }
+
+    pub fn with_failure(mut self, failure: Failure) -> Self {
+        self.failure = failure;
+        self
+    }
+
+    /// Change the injected failure on the fly, e.g. to unplug a sensor mid test
+    pub fn set_failure(&mut self, failure: Failure) {
+        self.failure = failure;
+    }
+
+    pub fn reads(&self) -> u32 {
+        self.reads
+    }
+}
+
+impl<St> Sensor for SimulatedSensor<St> {
+    type Error = SimError;
+    type Status = St;
+
+    fn get_unit(&self) -> &str {
+        self.unit
+    }
+
+    fn get_name(&self) -> &str {
+        self.name
+    }
+
+    fn get_measurment(&mut self) -> Result<f32, Self::Error> {
+        self.reads += 1;
+        match self.failure {
+            Failure::Disconnected => return Err(SimError::NotConnected),
+            Failure::Every(n) if n > 0 && self.reads % n == 0 => return Err(SimError::Injected),
+            Failure::After(n) if self.reads > n => return Err(SimError::Injected),
+            Failure::StuckAt(value) => return Ok(value),
+            _ => {}
+        }
+        self.signal.next().ok_or(SimError::NotConnected)
+    }
+
+    fn get_status(&mut self) -> Result<Self::Status, Self::Error> {
+        let value = self.get_measurment()?;
+        Ok((self.status)(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sensor::status::SoilStatus;
+
+    fn soil(signal: Signal) -> SimulatedSensor<SoilStatus> {
+        SimulatedSensor::new("soil moisture", "%", signal, SoilStatus::from_precentage)
+    }
+
+    #[test]
+    fn script_holds_last_value() {
+        let mut sensor = soil(Signal::script([10.0, 30.0]));
+        assert_eq!(sensor.get_measurment(), Ok(10.0));
+        assert_eq!(sensor.get_status(), Ok(SoilStatus::Optimal));
+        assert_eq!(sensor.get_measurment(), Ok(30.0));
+    }
+
+    #[test]
+    fn drift_stays_within_noise() {
+        let mut sensor = soil(Signal::drift(50.0, -1.0, 0.5, 7));
+        for read in 1..=20 {
+            let value = sensor.get_measurment().unwrap();
+            let expected = 50.0 - read as f32;
+            assert!((value - expected).abs() <= 0.5, "{value} vs {expected}");
+        }
+    }
+
+    #[test]
+    fn injected_failures() {
+        let mut sensor = soil(Signal::constant(30.0)).with_failure(Failure::Every(3));
+        assert!(sensor.get_measurment().is_ok());
+        assert!(sensor.get_measurment().is_ok());
+        assert_eq!(sensor.get_measurment(), Err(SimError::Injected));
+
+        sensor.set_failure(Failure::Disconnected);
+        assert_eq!(sensor.get_status(), Err(SimError::NotConnected));
+
+        sensor.set_failure(Failure::StuckAt(42.0));
+        assert_eq!(sensor.get_measurment(), Ok(42.0));
+    }
+}
@@ -0,0 +1,258 @@
+//! Mapping of the measurements to the status of the plant environment.\
+//! Kept apart from the drivers so it can be exercised on the host.
+
+use parse_display::Display;
+
+const MAX_DRY: u16 = 2800;
+const MAX_WET: u16 = 1300;
+
+const MOISTURE_RANGE: u16 = MAX_DRY - MAX_WET;
+const FULL_PRECENTAGE: f32 = 100.0;
+const NO_PRECENTAGE: f32 = 0.0;
+
+/// Convert the averaged raw soil probe read to moisture precentage.\
+/// `None` if the read is too low for a connected probe.
+pub fn moisture_precentage(raw: u16) -> Option<f32> {
+    let measurement = match raw {
+        msmnt if msmnt < 1000 => None,
+        msmnt => Some(msmnt),
+    }?;
+
+    if measurement > MAX_DRY {
+        return Some(NO_PRECENTAGE);
+    } else if measurement < MAX_WET {
+        return Some(FULL_PRECENTAGE);
+    }
+
+    let value_diff = MAX_DRY - measurement;
+    Some((value_diff as f32 / MOISTURE_RANGE as f32) * FULL_PRECENTAGE)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SoilStatus {
+    Dry,
+    Optimal,
+    Damp,
+    Wet,
+}
+
+impl SoilStatus {
+    /// Get the status of the soil
+    /// Dry -> 0-20%
+    /// Optimal -> 20-40%
+    /// Damp -> 40-55%
+    /// Wet -> 55-100%
+    pub fn from_precentage(percentage: f32) -> Self {
+        match percentage {
+            p if p < 20.0 => SoilStatus::Dry,
+            p if p < 40.0 => SoilStatus::Optimal,
+            p if p < 55.0 => SoilStatus::Damp,
+            _ => SoilStatus::Wet,
+        }
+    }
+}
+
+impl std::fmt::Display for SoilStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SoilStatus::Dry => write!(f, "Dry🔥"),
+            SoilStatus::Optimal => write!(f, "Optimal 💚"),
+            SoilStatus::Damp => write!(f, "Damp ⚠️"),
+            SoilStatus::Wet => write!(f, "Wet 💦"),
+        }
+    }
+}
+
+#[derive(Debug, Display, PartialEq)]
+pub enum TempStatus {
+    Freezing,
+    Cold,
+    Optimal,
+    Hot,
+}
+
+impl TempStatus {
+    pub fn from_celsius(temp: f32) -> Self {
+        match temp {
+            t if t < 0.0 => TempStatus::Freezing,
+            t if t < 18.0 => TempStatus::Cold,
+            t if t < 25.0 => TempStatus::Optimal,
+            _ => TempStatus::Hot,
+        }
+    }
+}
+
+#[derive(Debug, Display, PartialEq)]
+pub enum HumidityStatus {
+    Dry,
+    Optimal,
+    Moist,
+    Wet,
+}
+
+impl HumidityStatus {
+    pub fn from_precentage(humidity: f32) -> Self {
+        match humidity {
+            h if h < 30.0 => HumidityStatus::Dry,
+            h if h < 50.0 => HumidityStatus::Optimal,
+            h if h < 70.0 => HumidityStatus::Moist,
+            _ => HumidityStatus::Wet,
+        }
+    }
+}
+
+#[derive(Debug, Display, PartialEq)]
+pub enum PressureStatus {
+    Low,
+    Optimal,
+    High,
+}
+
+impl PressureStatus {
+    pub fn from_hpa(pressure: f32) -> Self {
+        match pressure {
+            p if p < 1000.0 => PressureStatus::Low,
+            p if p < 1013.0 => PressureStatus::Optimal,
+            _ => PressureStatus::High,
+        }
+    }
+}
+
+#[derive(Debug, Display, PartialEq)]
+pub enum LightStatus {
+    Dark,
+    Dull,
+    Bright,
+}
+
+impl LightStatus {
+    /// Dark -> below 50 lx ( night )
+    /// Dull -> below 10 000 lx ( overcast )
+    /// Bright -> daylight
+    pub fn from_lux(lux: f32) -> Self {
+        match lux {
+            l if l < 50.0 => LightStatus::Dark,
+            l if l < 10_000.0 => LightStatus::Dull,
+            _ => LightStatus::Bright,
+        }
+    }
+}
+
+/// Acceptable range of a reading for the grown crop
+#[derive(Debug, Clone, Copy)]
+pub struct CropRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeStatus {
+    Low,
+    Optimal,
+    High,
+}
+
+impl CropRange {
+    pub fn status(&self, value: f32) -> RangeStatus {
+        match value {
+            v if v < self.min => RangeStatus::Low,
+            v if v > self.max => RangeStatus::High,
+            _ => RangeStatus::Optimal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PhStatus {
+    Acidic,
+    Optimal,
+    Alkaline,
+}
+
+impl From<RangeStatus> for PhStatus {
+    fn from(status: RangeStatus) -> Self {
+        match status {
+            RangeStatus::Low => PhStatus::Acidic,
+            RangeStatus::Optimal => PhStatus::Optimal,
+            RangeStatus::High => PhStatus::Alkaline,
+        }
+    }
+}
+
+impl std::fmt::Display for PhStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PhStatus::Acidic => write!(f, "Acidic 🍋"),
+            PhStatus::Optimal => write!(f, "Optimal 💚"),
+            PhStatus::Alkaline => write!(f, "Alkaline 🧂"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EcStatus {
+    Hungry,
+    Optimal,
+    Overfed,
+}
+
+impl From<RangeStatus> for EcStatus {
+    fn from(status: RangeStatus) -> Self {
+        match status {
+            RangeStatus::Low => EcStatus::Hungry,
+            RangeStatus::Optimal => EcStatus::Optimal,
+            RangeStatus::High => EcStatus::Overfed,
+        }
+    }
+}
+
+impl std::fmt::Display for EcStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EcStatus::Hungry => write!(f, "Hungry ⚠️"),
+            EcStatus::Optimal => write!(f, "Optimal 💚"),
+            EcStatus::Overfed => write!(f, "Overfed ⚠️"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moisture_is_mapped_between_dry_and_wet() {
+        assert_eq!(moisture_precentage(999), None);
+        assert_eq!(moisture_precentage(3000), Some(0.0));
+        assert_eq!(moisture_precentage(1200), Some(100.0));
+        assert_eq!(moisture_precentage(2050), Some(50.0));
+    }
+
+    #[test]
+    fn soil_status_bands() {
+        assert_eq!(SoilStatus::from_precentage(10.0), SoilStatus::Dry);
+        assert_eq!(SoilStatus::from_precentage(20.0), SoilStatus::Optimal);
+        assert_eq!(SoilStatus::from_precentage(45.0), SoilStatus::Damp);
+        assert_eq!(SoilStatus::from_precentage(80.0), SoilStatus::Wet);
+    }
+
+    #[test]
+    fn climate_status_bands() {
+        assert_eq!(TempStatus::from_celsius(-2.0), TempStatus::Freezing);
+        assert_eq!(TempStatus::from_celsius(21.0), TempStatus::Optimal);
+        assert_eq!(
+            HumidityStatus::from_precentage(45.0),
+            HumidityStatus::Optimal
+        );
+        assert_eq!(PressureStatus::from_hpa(1020.0), PressureStatus::High);
+        assert_eq!(TempStatus::Hot.to_string(), "Hot");
+    }
+
+    #[test]
+    fn crop_range_status() {
+        let range = CropRange { min: 5.5, max: 6.5 };
+        assert_eq!(PhStatus::from(range.status(5.0)), PhStatus::Acidic);
+        assert_eq!(PhStatus::from(range.status(6.0)), PhStatus::Optimal);
+        assert_eq!(EcStatus::from(range.status(7.0)), EcStatus::Overfed);
+    }
+}
@@ -0,0 +1,270 @@
+//! Sunrise, sunset and civil twilight of a location.\
+//! The times come from the sunrise equation of the almanac for computers, they are within a
+//! couple of minutes of the published tables. Near the poles some days have no sunrise or sunset.
+
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::timezone::TimeZone;
+
+/// Position of the station, latitude north and longitude east in degrees
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SunEvent {
+    /// Start of the civil twilight, the sun is 6° below the horizon
+    Dawn,
+    Sunrise,
+    Sunset,
+    /// End of the civil twilight
+    Dusk,
+}
+
+impl SunEvent {
+    const ALL: [SunEvent; 4] = [Self::Dawn, Self::Sunrise, Self::Sunset, Self::Dusk];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Dawn => "dawn",
+            Self::Sunrise => "sunrise",
+            Self::Sunset => "sunset",
+            Self::Dusk => "dusk",
+        }
+    }
+
+    /// Zenith angle of the sun at the event in degrees, refraction included
+    fn zenith(&self) -> f64 {
+        match self {
+            Self::Sunrise | Self::Sunset => 90.833,
+            Self::Dawn | Self::Dusk => 96.0,
+        }
+    }
+
+    fn rising(&self) -> bool {
+        matches!(self, Self::Dawn | Self::Sunrise)
+    }
+
+    /// UTC time of the event on `date`, `None` if the sun doesn't get there that day
+    pub fn utc(&self, date: NaiveDate, location: Location) -> Option<NaiveDateTime> {
+        let hour_offset = location.longitude / 15.0;
+        // Rough time of the event as day of the year
+        let base = if self.rising() { 6.0 } else { 18.0 };
+        let t = f64::from(date.ordinal()) + (base - hour_offset) / 24.0;
+        // Mean anomaly and true longitude of the sun
+        let anomaly = 0.9856 * t - 3.289;
+        let longitude = (anomaly + 1.916 * sin(anomaly) + 0.020 * sin(2.0 * anomaly) + 282.634)
+            .rem_euclid(360.0);
+        // Right ascension in the quadrant of the longitude, in hours
+        let ascension = (0.91764 * tan(longitude))
+            .atan()
+            .to_degrees()
+            .rem_euclid(360.0);
+        let ascension = (ascension + (longitude / 90.0).floor() * 90.0
+            - (ascension / 90.0).floor() * 90.0)
+            / 15.0;
+        let sin_declination = 0.39782 * sin(longitude);
+        let cos_declination = sin_declination.asin().cos();
+        let cos_hour_angle = (cos(self.zenith()) - sin_declination * sin(location.latitude))
+            / (cos_declination * cos(location.latitude));
+        if !(-1.0..=1.0).contains(&cos_hour_angle) {
+            return None;
+        }
+        let hour_angle = cos_hour_angle.acos().to_degrees() / 15.0;
+        let hour_angle = if self.rising() {
+            24.0 - hour_angle
+        } else {
+            hour_angle
+        };
+        let local_mean = hour_angle + ascension - 0.06571 * t - 6.622;
+        let utc = (local_mean - hour_offset).rem_euclid(24.0);
+        // The event is within half a day of its rough time, it may fall on another UTC day
+        let midnight = date.and_hms_opt(0, 0, 0)?;
+        let expected = midnight + seconds(base - hour_offset);
+        let event = midnight + seconds(utc);
+        [event - Duration::days(1), event, event + Duration::days(1)]
+            .into_iter()
+            .min_by_key(|event| (*event - expected).num_seconds().abs())
+    }
+}
+
+fn sin(degrees: f64) -> f64 {
+    degrees.to_radians().sin()
+}
+
+fn cos(degrees: f64) -> f64 {
+    degrees.to_radians().cos()
+}
+
+fn tan(degrees: f64) -> f64 {
+    degrees.to_radians().tan()
+}
+
+fn seconds(hours: f64) -> Duration {
+    Duration::seconds((hours * 3600.0).round() as i64)
+}
+
+/// Time relative to a sun event, e.g. `sunrise+30m`, `dusk` or `sunset-1h`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolarTime {
+    pub event: SunEvent,
+    /// Minutes after the event, before it if negative
+    pub offset: i64,
+}
+
+impl SolarTime {
+    /// First local time after `time`, `None` if the sun doesn't get to the event these days
+    pub fn next_after(
+        &self,
+        time: NaiveDateTime,
+        location: Location,
+        timezone: &TimeZone,
+    ) -> Option<NaiveDateTime> {
+        (-1..=2)
+            .map(|day| time.date() + Duration::days(day))
+            .filter_map(|date| self.event.utc(date, location))
+            .map(|utc| timezone.to_local(utc) + Duration::minutes(self.offset))
+            .find(|next| *next > time)
+    }
+}
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+#[error("Invalid sun time `{0}`, expected e.g. `sunrise+30m`")]
+pub struct SolarTimeError(String);
+
+impl FromStr for SolarTime {
+    type Err = SolarTimeError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let invalid = || SolarTimeError(input.to_string());
+        let event = SunEvent::ALL
+            .into_iter()
+            .find(|event| input.starts_with(event.name()))
+            .ok_or_else(invalid)?;
+        let offset = match &input[event.name().len()..] {
+            "" => 0,
+            offset => {
+                let (sign, offset) = match offset.split_at(1) {
+                    ("+", offset) => (1, offset),
+                    ("-", offset) => (-1, offset),
+                    _ => return Err(invalid()),
+                };
+                let minutes = match offset.split_at(offset.len().saturating_sub(1)) {
+                    (value, "m") => value.parse::<u32>().ok().map(i64::from),
+                    (value, "h") => value.parse::<u32>().ok().map(|hours| i64::from(hours) * 60),
+                    _ => None,
+                }
+                .filter(|minutes| *minutes <= 12 * 60)
+                .ok_or_else(invalid)?;
+                sign * minutes
+            }
+        };
+        Ok(Self { event, offset })
+    }
+}
+
+impl std::fmt::Display for SolarTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.event.name())?;
+        match self.offset {
+            0 => Ok(()),
+            offset => write!(f, "{offset:+}m"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: Location = Location {
+        latitude: 51.5074,
+        longitude: -0.1278,
+    };
+    const SYDNEY: Location = Location {
+        latitude: -33.8688,
+        longitude: 151.2093,
+    };
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn assert_close(time: Option<NaiveDateTime>, expected: NaiveDateTime) {
+        let time = time.unwrap();
+        assert!(
+            (time - expected).num_minutes().abs() <= 3,
+            "{time} is not close to {expected}"
+        );
+    }
+
+    #[test]
+    fn london() {
+        let summer = NaiveDate::from_ymd_opt(2023, 6, 21).unwrap();
+        assert_close(SunEvent::Dawn.utc(summer, LONDON), utc(6, 21, 2, 57));
+        assert_close(SunEvent::Sunrise.utc(summer, LONDON), utc(6, 21, 3, 43));
+        assert_close(SunEvent::Sunset.utc(summer, LONDON), utc(6, 21, 20, 21));
+        assert_close(SunEvent::Dusk.utc(summer, LONDON), utc(6, 21, 21, 7));
+
+        let winter = NaiveDate::from_ymd_opt(2023, 12, 21).unwrap();
+        assert_close(SunEvent::Sunrise.utc(winter, LONDON), utc(12, 21, 8, 4));
+        assert_close(SunEvent::Sunset.utc(winter, LONDON), utc(12, 21, 15, 53));
+    }
+
+    #[test]
+    fn other_side_of_the_world() {
+        // Sunrise in Sydney is on the previous UTC day
+        let date = NaiveDate::from_ymd_opt(2023, 12, 21).unwrap();
+        assert_close(SunEvent::Sunrise.utc(date, SYDNEY), utc(12, 20, 18, 41));
+        assert_close(SunEvent::Sunset.utc(date, SYDNEY), utc(12, 21, 9, 5));
+    }
+
+    #[test]
+    fn polar_day() {
+        let tromso = Location {
+            latitude: 69.6492,
+            longitude: 18.9553,
+        };
+        let date = NaiveDate::from_ymd_opt(2023, 6, 21).unwrap();
+        assert_eq!(SunEvent::Sunrise.utc(date, tromso), None);
+        assert_eq!(SunEvent::Sunset.utc(date, tromso), None);
+    }
+
+    #[test]
+    fn solar_times() {
+        let time: SolarTime = "sunrise+30m".parse().unwrap();
+        assert_eq!(time.offset, 30);
+        assert_eq!(time.to_string(), "sunrise+30m");
+        assert_eq!("dusk-1h".parse::<SolarTime>().unwrap().offset, -60);
+        assert_eq!("dusk".parse::<SolarTime>().unwrap().to_string(), "dusk");
+        for invalid in [
+            "moonrise",
+            "sunrise30m",
+            "sunrise+30",
+            "sunset+13h",
+            "dawn+-5m",
+        ] {
+            assert!(invalid.parse::<SolarTime>().is_err(), "{invalid}");
+        }
+
+        let london: TimeZone = "GMT0BST,M3.5.0/1,M10.5.0".parse().unwrap();
+        // 30 minutes after the 4:43 BST sunrise
+        assert_close(
+            time.next_after(utc(6, 21, 0, 0), LONDON, &london),
+            utc(6, 21, 5, 13),
+        );
+        assert_close(
+            time.next_after(utc(6, 21, 6, 0), LONDON, &london),
+            utc(6, 22, 5, 13),
+        );
+    }
+}
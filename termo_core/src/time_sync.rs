@@ -0,0 +1,99 @@
+//! State of the wall clock synchronization.\
+//! The clock starts in 1970 after boot, the time based schedules wait until SNTP set it. The
+//! NTP servers come from the device configuration, the public pool is the fallback.
+
+use chrono::{Duration, NaiveDateTime};
+use serde::Serialize;
+
+/// NTP servers of a new configuration
+pub const DEFAULT_NTP_SERVERS: [&str; 1] = ["pool.ntp.org"];
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct TimeSync {
+    /// UTC time of the last sync
+    pub last_sync: Option<NaiveDateTime>,
+    /// Syncs since boot
+    pub syncs: u32,
+    /// The last sync came from the fallback servers
+    pub fallback: bool,
+}
+
+impl TimeSync {
+    pub const fn new() -> Self {
+        Self {
+            last_sync: None,
+            syncs: 0,
+            fallback: false,
+        }
+    }
+
+    /// The clock was set since boot
+    pub fn is_valid(&self) -> bool {
+        self.last_sync.is_some()
+    }
+
+    pub fn synced(&mut self, utc: NaiveDateTime, fallback: bool) {
+        self.last_sync = Some(utc);
+        self.syncs += 1;
+        self.fallback = fallback;
+    }
+
+    /// No sync for longer than `max_age`, e.g. the servers can't be reached any more
+    pub fn is_stale(&self, utc: NaiveDateTime, max_age: Duration) -> bool {
+        self.last_sync
+            .map_or(true, |last_sync| utc - last_sync > max_age)
+    }
+
+    /// Sync status for the reports
+    pub fn message(&self, utc: NaiveDateTime) -> String {
+        match self.last_sync {
+            Some(last_sync) => format!(
+                r"> Time synced: **{} min ago** ({} UTC{})\n",
+                (utc - last_sync).num_minutes(),
+                last_sync.format("%Y-%m-%d %H:%M"),
+                if self.fallback {
+                    ", fallback servers"
+                } else {
+                    ""
+                }
+            ),
+            None => r"> Time synced: **never**\n".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(hour: u32, minute: u32) -> NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2023, 10, 2)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn sync_status() {
+        let mut sync = TimeSync::new();
+        assert!(!sync.is_valid());
+        assert!(sync.is_stale(utc(8, 0), Duration::hours(3)));
+        assert_eq!(sync.message(utc(8, 0)), r"> Time synced: **never**\n");
+
+        sync.synced(utc(8, 0), false);
+        assert!(sync.is_valid());
+        assert!(!sync.is_stale(utc(10, 0), Duration::hours(3)));
+        assert!(sync.is_stale(utc(11, 1), Duration::hours(3)));
+        assert_eq!(
+            sync.message(utc(8, 5)),
+            r"> Time synced: **5 min ago** (2023-10-02 08:00 UTC)\n"
+        );
+
+        sync.synced(utc(9, 0), true);
+        assert_eq!(sync.syncs, 2);
+        assert_eq!(
+            sync.message(utc(9, 0)),
+            r"> Time synced: **0 min ago** (2023-10-02 09:00 UTC, fallback servers)\n"
+        );
+    }
+}
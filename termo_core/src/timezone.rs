@@ -0,0 +1,342 @@
+//! POSIX TZ strings, e.g. `CET-1CEST,M3.5.0,M10.5.0/3` for central Europe.\
+//! The firmware hands the string to the C library, here it is validated and the local time is
+//! computed the same way, so the schedules can be tested over the daylight saving changes.
+
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, thiserror::Error, PartialEq)]
+#[error("Invalid POSIX TZ string `{0}`")]
+pub struct TimeZoneError(String);
+
+/// Day of the year a daylight saving period starts or ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleDate {
+    /// `Mm.w.d`: day `d` ( 0 is sunday ) of week `w` of month `m`, week 5 is the last one
+    Month { month: u32, week: u32, weekday: u32 },
+    /// `Jn`: day 1 to 365, the 29th of february is never counted
+    Julian(u32),
+    /// `n`: day 0 to 365 counting the 29th of february
+    Day(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rule {
+    date: RuleDate,
+    /// Local time of the change in seconds
+    time: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Dst {
+    /// Offset east of UTC in seconds
+    offset: i64,
+    start: Rule,
+    end: Rule,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeZone {
+    tz: String,
+    /// Standard offset east of UTC in seconds
+    offset: i64,
+    dst: Option<Dst>,
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        Self::utc()
+    }
+}
+
+impl TimeZone {
+    pub fn utc() -> Self {
+        Self {
+            tz: "UTC0".to_string(),
+            offset: 0,
+            dst: None,
+        }
+    }
+
+    /// Local time of the `utc` time
+    pub fn to_local(&self, utc: NaiveDateTime) -> NaiveDateTime {
+        utc + Duration::seconds(self.offset_at(utc))
+    }
+
+    /// The daylight saving time is in effect at the `utc` time
+    pub fn is_dst(&self, utc: NaiveDateTime) -> bool {
+        let Some(dst) = self.dst else {
+            return false;
+        };
+        let year = (utc + Duration::seconds(self.offset)).year();
+        // The start is given in standard time, the end in daylight saving time
+        let start = dst.start.local(year) - Duration::seconds(self.offset);
+        let end = dst.end.local(year) - Duration::seconds(dst.offset);
+        if start < end {
+            start <= utc && utc < end
+        } else {
+            // Southern hemisphere, the summer spans the new year
+            utc < end || start <= utc
+        }
+    }
+
+    /// Offset east of UTC in seconds at the `utc` time
+    pub fn offset_at(&self, utc: NaiveDateTime) -> i64 {
+        match self.dst {
+            Some(dst) if self.is_dst(utc) => dst.offset,
+            _ => self.offset,
+        }
+    }
+}
+
+impl Rule {
+    /// Local time of the change in `year`
+    fn local(&self, year: i32) -> NaiveDateTime {
+        let date = match self.date {
+            RuleDate::Month {
+                month,
+                week,
+                weekday,
+            } => {
+                let first = NaiveDate::from_ymd_opt(year, month, 1).expect("Valid month");
+                let first_weekday = first.weekday().num_days_from_sunday();
+                let mut date = first + Duration::days(((7 + weekday - first_weekday) % 7).into());
+                date += Duration::weeks((week - 1).into());
+                // Week 5 is the last one, it may be the 4th
+                while date.month() != month {
+                    date -= Duration::weeks(1);
+                }
+                date
+            }
+            RuleDate::Julian(day) => {
+                let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+                let day = if leap && day >= 60 { day } else { day - 1 };
+                NaiveDate::from_yo_opt(year, 1).expect("Valid year") + Duration::days(day.into())
+            }
+            RuleDate::Day(day) => {
+                NaiveDate::from_yo_opt(year, 1).expect("Valid year") + Duration::days(day.into())
+            }
+        };
+        date.and_hms_opt(0, 0, 0).expect("Valid time") + Duration::seconds(self.time)
+    }
+}
+
+impl FromStr for TimeZone {
+    type Err = TimeZoneError;
+
+    fn from_str(tz: &str) -> Result<Self, Self::Err> {
+        let invalid = || TimeZoneError(tz.to_string());
+        let mut parser = Parser(tz);
+        parser.name().ok_or_else(invalid)?;
+        // POSIX offsets are west of UTC
+        let offset = -parser.time().ok_or_else(invalid)?;
+        let dst = if parser.0.is_empty() {
+            None
+        } else {
+            parser.name().ok_or_else(invalid)?;
+            // An hour ahead of the standard time by default
+            let dst_offset = if parser.0.is_empty() || parser.0.starts_with(',') {
+                offset + 3600
+            } else {
+                -parser.time().ok_or_else(invalid)?
+            };
+            // Without rules the US ones apply
+            let (start, end) = if parser.0.is_empty() {
+                (
+                    Parser("M3.2.0").rule().ok_or_else(invalid)?,
+                    Parser("M11.1.0").rule().ok_or_else(invalid)?,
+                )
+            } else {
+                parser.expect(',').ok_or_else(invalid)?;
+                let start = parser.rule().ok_or_else(invalid)?;
+                parser.expect(',').ok_or_else(invalid)?;
+                (start, parser.rule().ok_or_else(invalid)?)
+            };
+            Some(Dst {
+                offset: dst_offset,
+                start,
+                end,
+            })
+        };
+        if !parser.0.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            tz: tz.to_string(),
+            offset,
+            dst,
+        })
+    }
+}
+
+impl TryFrom<String> for TimeZone {
+    type Error = TimeZoneError;
+
+    fn try_from(tz: String) -> Result<Self, Self::Error> {
+        tz.parse()
+    }
+}
+
+impl From<TimeZone> for String {
+    fn from(zone: TimeZone) -> Self {
+        zone.tz
+    }
+}
+
+impl std::fmt::Display for TimeZone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.tz)
+    }
+}
+
+/// Parser of the parts of a TZ string, each takes its part off the front of the rest
+struct Parser<'a>(&'a str);
+
+impl Parser<'_> {
+    fn take(&mut self, len: usize) -> &str {
+        let (part, rest) = self.0.split_at(len);
+        self.0 = rest;
+        part
+    }
+
+    fn expect(&mut self, c: char) -> Option<()> {
+        self.0 = self.0.strip_prefix(c)?;
+        Some(())
+    }
+
+    /// Zone name of at least 3 letters, or any in angle brackets like `<+03>`
+    fn name(&mut self) -> Option<()> {
+        if let Some(rest) = self.0.strip_prefix('<') {
+            let end = rest.find('>')?;
+            self.0 = &rest[end + 1..];
+            return (end >= 3).then_some(());
+        }
+        let len = self
+            .0
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(self.0.len());
+        self.take(len);
+        (len >= 3).then_some(())
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let len = self
+            .0
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(self.0.len());
+        self.take(len).parse().ok()
+    }
+
+    /// `[+-]hh[:mm[:ss]]` in seconds
+    fn time(&mut self) -> Option<i64> {
+        let sign = match self.0.chars().next()? {
+            '-' => -1,
+            '+' => 1,
+            _ => 0,
+        };
+        if sign != 0 {
+            self.take(1);
+        }
+        let mut seconds = i64::from(self.number().filter(|hours| *hours <= 167)?) * 3600;
+        for unit in [60, 1] {
+            if self.expect(':').is_none() {
+                break;
+            }
+            seconds += i64::from(self.number().filter(|value| *value < 60)?) * unit;
+        }
+        Some(if sign < 0 { -seconds } else { seconds })
+    }
+
+    /// Date of the change with an optional `/time`, 2 AM by default
+    fn rule(&mut self) -> Option<Rule> {
+        let date = if self.expect('M').is_some() {
+            let month = self.number().filter(|month| (1..=12).contains(month))?;
+            self.expect('.')?;
+            let week = self.number().filter(|week| (1..=5).contains(week))?;
+            self.expect('.')?;
+            let weekday = self.number().filter(|day| *day < 7)?;
+            RuleDate::Month {
+                month,
+                week,
+                weekday,
+            }
+        } else if self.expect('J').is_some() {
+            RuleDate::Julian(self.number().filter(|day| (1..=365).contains(day))?)
+        } else {
+            RuleDate::Day(self.number().filter(|day| *day <= 365)?)
+        };
+        let time = match self.expect('/') {
+            Some(()) => self.time()?,
+            None => 2 * 3600,
+        };
+        Some(Rule { date, time })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Weekday;
+
+    fn utc(month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn central_europe() {
+        let zone: TimeZone = "CET-1CEST,M3.5.0,M10.5.0/3".parse().unwrap();
+        assert_eq!(zone.to_local(utc(1, 10, 7, 0)), utc(1, 10, 8, 0));
+        assert_eq!(zone.to_local(utc(7, 10, 6, 0)), utc(7, 10, 8, 0));
+        // Clocks go forward at 2 AM on the last sunday of march, back at 3 AM in october
+        assert_eq!(utc(3, 26, 0, 0).weekday(), Weekday::Sun);
+        assert_eq!(zone.to_local(utc(3, 26, 0, 59)), utc(3, 26, 1, 59));
+        assert_eq!(zone.to_local(utc(3, 26, 1, 0)), utc(3, 26, 3, 0));
+        assert_eq!(zone.to_local(utc(10, 29, 0, 59)), utc(10, 29, 2, 59));
+        assert_eq!(zone.to_local(utc(10, 29, 1, 0)), utc(10, 29, 2, 0));
+    }
+
+    #[test]
+    fn other_zones() {
+        let utc_zone = TimeZone::default();
+        assert_eq!(utc_zone.to_local(utc(7, 1, 12, 0)), utc(7, 1, 12, 0));
+
+        // US rules by default: second sunday of march to the first sunday of november
+        let new_york: TimeZone = "EST5EDT".parse().unwrap();
+        assert_eq!(new_york.to_local(utc(3, 12, 6, 59)), utc(3, 12, 1, 59));
+        assert_eq!(new_york.to_local(utc(3, 12, 7, 0)), utc(3, 12, 3, 0));
+        assert_eq!(new_york.to_local(utc(11, 5, 6, 0)), utc(11, 5, 1, 0));
+
+        // Summer over the new year
+        let sydney: TimeZone = "AEST-10AEDT,M10.1.0,M4.1.0/3".parse().unwrap();
+        assert_eq!(sydney.offset_at(utc(1, 1, 0, 0)), 11 * 3600);
+        assert_eq!(sydney.offset_at(utc(7, 1, 0, 0)), 10 * 3600);
+
+        let india: TimeZone = "<+0530>-5:30".parse().unwrap();
+        assert_eq!(india.to_local(utc(7, 1, 0, 0)), utc(7, 1, 5, 30));
+    }
+
+    #[test]
+    fn invalid_zones() {
+        for tz in [
+            "",
+            "CET",
+            "C-1",
+            "CET-1CEST,M3.5.0",
+            "CET-1CEST,M13.5.0,M10.5.0",
+            "CET-1CEST,M3.5.7,M10.5.0",
+            "CET-1 ",
+        ] {
+            assert_eq!(tz.parse::<TimeZone>(), Err(TimeZoneError(tz.to_string())));
+        }
+        assert_eq!(
+            serde_json::from_str::<TimeZone>(r#""UTC0""#).unwrap(),
+            TimeZone::utc()
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Event triggers on the sensor readings and the digital inputs.\
+//! The firmware polls the triggers, the events they raise go on the [`EventBus`] where the
+//! actions and the notifications subscribe to them.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+/// Poll interval of the sensor triggers
+pub const SENSOR_POLL: Duration = Duration::from_secs(30);
+/// Poll interval of the input triggers, short enough to catch a button press
+pub const INPUT_POLL: Duration = Duration::from_millis(50);
+
+pub trait Trigger {
+    /// Sample the input, the event is returned when the trigger fires
+    fn poll(&mut self, now: Instant) -> Option<Event>;
+    /// Time between two polls
+    fn interval(&self) -> Duration;
+}
+
+/// Events raised by the triggers
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The reading went past the limit, or came back, and stayed there for the dwell time
+    Threshold {
+        source: String,
+        value: f32,
+        limit: f32,
+        /// `true` past the limit, `false` once back inside the hysteresis
+        active: bool,
+    },
+    /// The reading changes faster than allowed
+    Rate {
+        source: String,
+        /// Change per minute
+        rate: f32,
+    },
+    /// A debounced input changed its level
+    Edge { source: String, rising: bool },
+}
+
+/// Side of the limit that raises the event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Above,
+    Below,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Threshold {
+    pub direction: Direction,
+    pub limit: f32,
+    /// Distance from the limit the reading has to come back to clear the event
+    pub hysteresis: f32,
+    /// Time the reading has to stay past the limit, or back, before it counts
+    pub dwell: Duration,
+}
+
+/// Threshold crossing of a reading with hysteresis and dwell time
+pub struct ThresholdTrigger<R> {
+    source: String,
+    threshold: Threshold,
+    read: R,
+    interval: Duration,
+    active: bool,
+    pending_since: Option<Instant>,
+}
+
+impl<R: FnMut() -> Option<f32>> ThresholdTrigger<R> {
+    /// `read` gives the reading, `None` on a read error which is skipped
+    pub fn new(source: &str, threshold: Threshold, read: R) -> Self {
+        Self {
+            source: source.to_string(),
+            threshold,
+            read,
+            interval: SENSOR_POLL,
+            active: false,
+            pending_since: None,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn update(&mut self, value: f32, now: Instant) -> Option<Event> {
+        let Threshold {
+            direction,
+            limit,
+            hysteresis,
+            dwell,
+        } = self.threshold;
+        let changing = match (direction, self.active) {
+            (Direction::Above, false) => value > limit,
+            (Direction::Above, true) => value < limit - hysteresis,
+            (Direction::Below, false) => value < limit,
+            (Direction::Below, true) => value > limit + hysteresis,
+        };
+        if !changing {
+            self.pending_since = None;
+            return None;
+        }
+        let since = *self.pending_since.get_or_insert(now);
+        if now.duration_since(since) < dwell {
+            return None;
+        }
+        self.pending_since = None;
+        self.active = !self.active;
+        Some(Event::Threshold {
+            source: self.source.clone(),
+            value,
+            limit,
+            active: self.active,
+        })
+    }
+}
+
+impl<R: FnMut() -> Option<f32>> Trigger for ThresholdTrigger<R> {
+    fn poll(&mut self, now: Instant) -> Option<Event> {
+        let value = (self.read)()?;
+        self.update(value, now)
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Rate of change of a reading over a sliding window, it fires once per fast change
+pub struct RateTrigger<R> {
+    source: String,
+    /// Largest allowed change per minute
+    max_rate: f32,
+    window: Duration,
+    read: R,
+    interval: Duration,
+    samples: VecDeque<(Instant, f32)>,
+    active: bool,
+}
+
+impl<R: FnMut() -> Option<f32>> RateTrigger<R> {
+    pub fn new(source: &str, max_rate: f32, window: Duration, read: R) -> Self {
+        Self {
+            source: source.to_string(),
+            max_rate,
+            window,
+            read,
+            interval: SENSOR_POLL,
+            samples: VecDeque::new(),
+            active: false,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn update(&mut self, value: f32, now: Instant) -> Option<Event> {
+        while let Some((time, _)) = self.samples.front() {
+            if now.duration_since(*time) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, value));
+        let (start, first) = *self.samples.front()?;
+        let minutes = now.duration_since(start).as_secs_f32() / 60.0;
+        if minutes <= 0.0 {
+            return None;
+        }
+        let rate = (value - first) / minutes;
+        let fast = rate.abs() > self.max_rate;
+        let fired = fast && !self.active;
+        self.active = fast;
+        fired.then(|| Event::Rate {
+            source: self.source.clone(),
+            rate,
+        })
+    }
+}
+
+impl<R: FnMut() -> Option<f32>> Trigger for RateTrigger<R> {
+    fn poll(&mut self, now: Instant) -> Option<Event> {
+        let value = (self.read)()?;
+        self.update(value, now)
+    }
+
+    fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Edges of a digital input that raise the event
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Edge {
+    Rising,
+    Falling,
+    Both,
+}
+
+/// Debounced edges of a digital input
+pub struct EdgeTrigger<R> {
+    source: String,
+    edge: Edge,
+    /// Time the new level has to be stable
+    debounce: Duration,
+    read: R,
+    level: Option<bool>,
+    candidate: Option<(bool, Instant)>,
+}
+
+impl<R: FnMut() -> Option<bool>> EdgeTrigger<R> {
+    pub fn new(source: &str, edge: Edge, debounce: Duration, read: R) -> Self {
+        Self {
+            source: source.to_string(),
+            edge,
+            debounce,
+            read,
+            level: None,
+            candidate: None,
+        }
+    }
+
+    /// The first level read is the starting state, it raises no event
+    pub fn update(&mut self, level: bool, now: Instant) -> Option<Event> {
+        let Some(current) = self.level else {
+            self.level = Some(level);
+            return None;
+        };
+        if current == level {
+            self.candidate = None;
+            return None;
+        }
+        let since = match self.candidate {
+            Some((candidate, since)) if candidate == level => since,
+            _ => self.candidate.insert((level, now)).1,
+        };
+        if now.duration_since(since) < self.debounce {
+            return None;
+        }
+        self.level = Some(level);
+        self.candidate = None;
+        let wanted = match self.edge {
+            Edge::Rising => level,
+            Edge::Falling => !level,
+            Edge::Both => true,
+        };
+        wanted.then(|| Event::Edge {
+            source: self.source.clone(),
+            rising: level,
+        })
+    }
+}
+
+impl<R: FnMut() -> Option<bool>> Trigger for EdgeTrigger<R> {
+    fn poll(&mut self, now: Instant) -> Option<Event> {
+        let level = (self.read)()?;
+        self.update(level, now)
+    }
+
+    fn interval(&self) -> Duration {
+        INPUT_POLL
+    }
+}
+
+type Subscriber<'a> = Box<dyn FnMut(&Event) + 'a>;
+
+/// Delivers every published event to all the subscribers
+#[derive(Default)]
+pub struct EventBus<'a> {
+    subscribers: Vec<Subscriber<'a>>,
+}
+
+impl<'a> EventBus<'a> {
+    pub fn subscribe(&mut self, subscriber: impl FnMut(&Event) + 'a) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    pub fn publish(&mut self, event: &Event) {
+        for subscriber in &mut self.subscribers {
+            subscriber(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    fn seconds(start: Instant, seconds: u64) -> Instant {
+        start + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn threshold_with_dwell_and_hysteresis() {
+        let mut trigger = ThresholdTrigger::new(
+            "temperature",
+            Threshold {
+                direction: Direction::Above,
+                limit: 30.0,
+                hysteresis: 1.0,
+                dwell: Duration::from_secs(60),
+            },
+            || None,
+        );
+        let start = Instant::now();
+        // A short spike doesn't count
+        assert_eq!(trigger.update(31.0, start), None);
+        assert_eq!(trigger.update(29.0, seconds(start, 30)), None);
+        assert_eq!(trigger.update(31.0, seconds(start, 60)), None);
+        assert_eq!(trigger.update(31.5, seconds(start, 90)), None);
+        assert_eq!(
+            trigger.update(31.5, seconds(start, 120)),
+            Some(Event::Threshold {
+                source: "temperature".to_string(),
+                value: 31.5,
+                limit: 30.0,
+                active: true
+            })
+        );
+        assert_eq!(trigger.update(32.0, seconds(start, 300)), None);
+        // Inside the hysteresis band it stays active
+        assert_eq!(trigger.update(29.5, seconds(start, 400)), None);
+        assert_eq!(trigger.update(29.5, seconds(start, 500)), None);
+        assert_eq!(trigger.update(28.5, seconds(start, 600)), None);
+        assert!(matches!(
+            trigger.update(28.0, seconds(start, 660)),
+            Some(Event::Threshold { active: false, .. })
+        ));
+    }
+
+    #[test]
+    fn threshold_below() {
+        let mut trigger = ThresholdTrigger::new(
+            "soil moisture",
+            Threshold {
+                direction: Direction::Below,
+                limit: 15.0,
+                hysteresis: 2.0,
+                dwell: Duration::ZERO,
+            },
+            || Some(10.0),
+        );
+        let start = Instant::now();
+        assert!(matches!(
+            trigger.poll(start),
+            Some(Event::Threshold { active: true, .. })
+        ));
+        assert_eq!(trigger.poll(start), None);
+        assert_eq!(trigger.update(16.0, start), None);
+        assert!(matches!(
+            trigger.update(17.5, start),
+            Some(Event::Threshold { active: false, .. })
+        ));
+    }
+
+    #[test]
+    fn rate_of_change() {
+        let mut trigger = RateTrigger::new("temperature", 0.5, Duration::from_secs(600), || None);
+        let start = Instant::now();
+        assert_eq!(trigger.update(20.0, start), None);
+        assert_eq!(trigger.update(20.2, seconds(start, 60)), None);
+        // 2°C in 2 minutes
+        assert_eq!(
+            trigger.update(22.0, seconds(start, 120)),
+            Some(Event::Rate {
+                source: "temperature".to_string(),
+                rate: 1.0
+            })
+        );
+        // Fires once while it stays fast
+        assert_eq!(trigger.update(24.0, seconds(start, 180)), None);
+        // The old samples leave the window
+        assert_eq!(trigger.update(24.0, seconds(start, 1200)), None);
+        assert_eq!(trigger.update(24.1, seconds(start, 1260)), None);
+    }
+
+    #[test]
+    fn debounced_edges() {
+        let mut trigger =
+            EdgeTrigger::new("button", Edge::Falling, Duration::from_millis(100), || None);
+        let start = Instant::now();
+        let millis = |millis| start + Duration::from_millis(millis);
+        assert_eq!(trigger.update(true, start), None);
+        // Bounce
+        assert_eq!(trigger.update(false, millis(50)), None);
+        assert_eq!(trigger.update(true, millis(100)), None);
+        assert_eq!(trigger.update(false, millis(150)), None);
+        assert_eq!(
+            trigger.update(false, millis(250)),
+            Some(Event::Edge {
+                source: "button".to_string(),
+                rising: false
+            })
+        );
+        // The release is not wanted
+        assert_eq!(trigger.update(true, millis(300)), None);
+        assert_eq!(trigger.update(true, millis(400)), None);
+        assert_eq!(trigger.update(false, millis(500)), None);
+    }
+
+    #[test]
+    fn bus_delivers_to_every_subscriber() {
+        let received = RefCell::new(Vec::new());
+        let mut bus = EventBus::default();
+        bus.subscribe(|event| received.borrow_mut().push(event.clone()));
+        bus.subscribe(|event| received.borrow_mut().push(event.clone()));
+        let event = Event::Edge {
+            source: "button".to_string(),
+            rising: true,
+        };
+        bus.publish(&event);
+        drop(bus);
+        assert_eq!(received.into_inner(), vec![event.clone(), event]);
+    }
+}